@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use crate::{build::BuildReport, time::format_duration};
+
+/// Outcome of one project of `cw install <path>...`.
+#[derive(Debug)]
+pub struct ProjectOutcome {
+    pub project: PathBuf,
+    pub result: Result<BuildReport, String>,
+}
+
+/// The project directory for a path given on the command line, which may also point at
+/// the `Cargo.toml` itself.
+pub fn find_project_dir(path: impl AsRef<Path>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    const MANIFEST: &str = "Cargo.toml";
+    let path = path.as_ref();
+    let dir = if path
+        .file_name()
+        .map(|name| name == MANIFEST)
+        .unwrap_or(false)
+    {
+        path.parent().unwrap_or(Path::new("."))
+    } else {
+        path
+    };
+    if !dir.join(MANIFEST).is_file() {
+        return Err(format!("no {} in {}", MANIFEST, dir.display()).into());
+    }
+    Ok(dir.canonicalize()?)
+}
+
+pub fn render_status_table(outcomes: &[ProjectOutcome]) -> String {
+    let rows = outcomes
+        .iter()
+        .map(|outcome| {
+            let project = outcome.project.display().to_string();
+            match &outcome.result {
+                Ok(report) => [
+                    project,
+                    "ok".to_string(),
+                    format_duration(report.duration),
+                    report
+                        .binaries
+                        .iter()
+                        .map(|binary| binary.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ],
+                Err(e) => [project, "failed".to_string(), "-".to_string(), e.clone()],
+            }
+        })
+        .collect::<Vec<_>>();
+    let header = ["PROJECT", "STATUS", "TIME", "BINARIES"].map(String::from);
+    let width = |column: usize| {
+        std::iter::once(&header)
+            .chain(rows.iter())
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or_default()
+    };
+    let widths = [width(0), width(1), width(2)];
+    std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| {
+            format!(
+                "{:<w0$}  {:<w1$}  {:>w2$}  {}\n",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{build::BuiltBinary, commands::write_file};
+
+    use super::*;
+    #[test]
+    fn ディレクトリかcargo_tomlのパスからプロジェクトを見つける() {
+        let project = tempfile::tempdir().unwrap();
+        write_file(project.path().join("Cargo.toml"), "").unwrap();
+        let expected = project.path().canonicalize().unwrap();
+
+        assert_eq!(find_project_dir(project.path()).unwrap(), expected);
+        assert_eq!(
+            find_project_dir(project.path().join("Cargo.toml")).unwrap(),
+            expected
+        );
+        assert!(find_project_dir(project.path().join("src")).is_err());
+    }
+    #[test]
+    fn プロジェクトごとの結果を表にする() {
+        let report = BuildReport {
+            profile: "release",
+            duration: Duration::from_millis(12_300),
+            crates_compiled: 1,
+            binaries: vec![BuiltBinary {
+                name: "app".to_string(),
+                bin: "app".to_string(),
+                path: PathBuf::from("/work/app/target/release/app"),
+                size: 0,
+                version: None,
+            }],
+            installed: Vec::new(),
+            stripped: Vec::new(),
+            cached: false,
+        };
+        let outcomes = [
+            ProjectOutcome {
+                project: PathBuf::from("/work/app"),
+                result: Ok(report),
+            },
+            ProjectOutcome {
+                project: PathBuf::from("/work/broken-tool"),
+                result: Err("`cargo build` exited with status 101".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            render_status_table(&outcomes),
+            "\
+PROJECT            STATUS   TIME  BINARIES
+/work/app          ok      12.3s  app
+/work/broken-tool  failed      -  `cargo build` exited with status 101
+"
+        );
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Display, Write},
+    path::Path,
+};
+
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+use serde::Serialize;
+
+use crate::{
+    build::BuiltBinary,
+    git::short,
+    history::format_size,
+    ledger::{Ledger, LedgerEntry},
+};
+
+/// Functions that could not be demangled, e.g. from C libraries.
+const UNKNOWN_CRATE: &str = "[unknown]";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SectionSize {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateSize {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionSize {
+    /// Demangled path without the hash.
+    pub name: String,
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub size: u64,
+}
+
+/// Where the bytes of an ELF file go.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bloat {
+    pub file_size: u64,
+    /// Sum of the executable sections.
+    pub text_size: u64,
+    /// Whether the file still has a symbol table; a stripped file has only sections.
+    pub symbols: bool,
+    /// Largest first.
+    pub sections: Vec<SectionSize>,
+    /// Size of the functions attributed to each crate, largest first.
+    pub crates: Vec<CrateSize>,
+    /// Largest first.
+    pub functions: Vec<FunctionSize>,
+}
+
+impl Bloat {
+    pub fn analyze(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = object::File::parse(data)?;
+        let mut sections = file
+            .sections()
+            .filter(|section| section.size() > 0)
+            .map(|section| SectionSize {
+                name: section.name().unwrap_or_default().to_string(),
+                size: section.size(),
+            })
+            .collect::<Vec<_>>();
+        sort_by_size(&mut sections, |section| (section.size, &section.name));
+        let text_size = file
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Text)
+            .map(|section| section.size())
+            .sum();
+
+        // Identical functions folded by the linker share an address; count them once.
+        let mut addresses = BTreeSet::new();
+        let mut functions = file
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.size() > 0)
+            .filter(|symbol| addresses.insert(symbol.address()))
+            .map(|symbol| {
+                let (name, crate_name) = demangle(symbol.name().unwrap_or_default());
+                FunctionSize {
+                    name,
+                    crate_name,
+                    size: symbol.size(),
+                }
+            })
+            .collect::<Vec<_>>();
+        sort_by_size(&mut functions, |function| (function.size, &function.name));
+
+        let mut by_crate = BTreeMap::<&str, u64>::new();
+        for function in &functions {
+            *by_crate.entry(&function.crate_name).or_default() += function.size;
+        }
+        let mut crates = by_crate
+            .into_iter()
+            .map(|(name, size)| CrateSize {
+                name: name.to_string(),
+                size,
+            })
+            .collect::<Vec<_>>();
+        sort_by_size(&mut crates, |krate| (krate.size, &krate.name));
+
+        Ok(Self {
+            file_size: data.len() as u64,
+            text_size,
+            symbols: !addresses.is_empty(),
+            sections,
+            crates,
+            functions,
+        })
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        Self::analyze(&data).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+    /// Keeps only the `count` largest functions.
+    pub fn top(mut self, count: usize) -> Self {
+        self.functions.truncate(count);
+        self
+    }
+}
+
+fn sort_by_size<T>(items: &mut [T], key: impl Fn(&T) -> (u64, &String)) {
+    items.sort_by(|a, b| {
+        let (a, b) = (key(a), key(b));
+        b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1))
+    });
+}
+
+/// Demangled name and the crate it belongs to.
+fn demangle(symbol: &str) -> (String, String) {
+    match rustc_demangle::try_demangle(symbol) {
+        Ok(demangled) => {
+            let name = format!("{:#}", demangled);
+            let crate_name = crate_of(&name).unwrap_or(UNKNOWN_CRATE).to_string();
+            (name, crate_name)
+        }
+        Err(_) => (symbol.to_string(), UNKNOWN_CRATE.to_string()),
+    }
+}
+
+/// The first path segment, so `core::ptr::drop_in_place<app::Config>` goes to `core`.
+///
+/// `<Type as Trait>::f` goes to the crate of the type, or of the trait when the type is
+/// a primitive like `&str`; inherent methods of primitives such as `<str>::trim` go to `core`.
+fn crate_of(path: &str) -> Option<&str> {
+    let Some(qualified) = path.strip_prefix('<') else {
+        return first_segment(path);
+    };
+    let mut depth = 0;
+    let mut end = qualified.len();
+    let mut separator = None;
+    for (i, c) in qualified.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' if depth == 0 => {
+                end = i;
+                break;
+            }
+            '>' | ')' | ']' => depth -= 1,
+            _ if depth == 0 && qualified[i..].starts_with(" as ") => separator = Some(i),
+            _ => {}
+        }
+    }
+    let (self_type, trait_path) = match separator {
+        Some(i) => (&qualified[..i], Some(&qualified[i + " as ".len()..end])),
+        None => (&qualified[..end], None),
+    };
+    let self_type = ["&mut ", "&", "*mut ", "*const ", "dyn "]
+        .iter()
+        .fold(self_type, |ty, prefix| {
+            ty.strip_prefix(prefix).unwrap_or(ty)
+        });
+    if self_type.contains("::") && !self_type.starts_with(['[', '(']) {
+        return crate_of(self_type);
+    }
+    match trait_path {
+        Some(trait_path) => crate_of(trait_path),
+        None => Some("core"),
+    }
+}
+
+fn first_segment(path: &str) -> Option<&str> {
+    path.split([':', '<', ' '])
+        .next()
+        .filter(|segment| !segment.is_empty())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeChange {
+    pub name: String,
+    pub previous: u64,
+    pub current: u64,
+}
+impl SizeChange {
+    pub fn delta(&self) -> i64 {
+        self.current as i64 - self.previous as i64
+    }
+}
+impl Display for SizeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let delta = self.delta();
+        write!(
+            f,
+            "{:<24} {:>9} -> {:>9} ({}{})",
+            self.name,
+            format_size(self.previous),
+            format_size(self.current),
+            if delta < 0 { "-" } else { "+" },
+            format_size(delta.unsigned_abs())
+        )
+    }
+}
+
+/// Size changes since the version recorded in the ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Comparison {
+    pub installed_at: String,
+    pub commit: Option<String>,
+    /// The whole file and `.text`, then every crate that changed, largest change first.
+    ///
+    /// Crates are left out when the installed file was stripped.
+    pub changes: Vec<SizeChange>,
+}
+impl Comparison {
+    pub fn new(entry: &LedgerEntry, previous: &Bloat, current: &Bloat) -> Self {
+        let mut changes = vec![
+            SizeChange {
+                name: "file".to_string(),
+                previous: previous.file_size,
+                current: current.file_size,
+            },
+            SizeChange {
+                name: "text".to_string(),
+                previous: previous.text_size,
+                current: current.text_size,
+            },
+        ];
+        if previous.symbols && current.symbols {
+            let size = |bloat: &Bloat, name: &str| {
+                bloat
+                    .crates
+                    .iter()
+                    .find(|krate| krate.name == name)
+                    .map_or(0, |krate| krate.size)
+            };
+            let names = previous
+                .crates
+                .iter()
+                .chain(&current.crates)
+                .map(|krate| krate.name.as_str())
+                .collect::<BTreeSet<_>>();
+            let mut crates = names
+                .into_iter()
+                .map(|name| SizeChange {
+                    name: name.to_string(),
+                    previous: size(previous, name),
+                    current: size(current, name),
+                })
+                .filter(|change| change.delta() != 0)
+                .collect::<Vec<_>>();
+            crates.sort_by_key(|change| std::cmp::Reverse(change.delta().unsigned_abs()));
+            changes.extend(crates);
+        }
+        Self {
+            installed_at: entry.installed_at.clone(),
+            commit: entry.commit.clone(),
+            changes,
+        }
+    }
+}
+
+/// What `cw bloat` prints for one binary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BloatReport {
+    pub name: String,
+    #[serde(flatten)]
+    pub bloat: Bloat,
+    /// Against the installed version, when cw installed one.
+    pub previous: Option<Comparison>,
+}
+impl BloatReport {
+    /// Analyzes a freshly built binary and the version of it in `install_dir`, if any.
+    ///
+    /// An installed file that cannot be read as an object file is not compared against.
+    pub fn new(
+        binary: &BuiltBinary,
+        install_dir: Option<&Path>,
+        top: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let bloat = Bloat::load(&binary.path)?;
+        let previous = match install_dir {
+            Some(dir) => Ledger::load(dir)?.get(&binary.name).and_then(|entry| {
+                let installed = Bloat::load(dir.join(&entry.name)).ok()?;
+                Some(Comparison::new(entry, &installed, &bloat))
+            }),
+            None => None,
+        };
+        Ok(Self {
+            name: binary.name.clone(),
+            bloat: bloat.top(top),
+            previous,
+        })
+    }
+}
+impl Display for BloatReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bloat = &self.bloat;
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}: {}, text {}",
+            self.name,
+            format_size(bloat.file_size),
+            format_size(bloat.text_size)
+        );
+        let _ = writeln!(out, "\nsections:");
+        for section in &bloat.sections {
+            let _ = writeln!(out, "{:>9}  {}", format_size(section.size), section.name);
+        }
+        if bloat.symbols {
+            let _ = writeln!(out, "\ncrates:");
+            for krate in &bloat.crates {
+                let _ = writeln!(
+                    out,
+                    "{:>9} {:>5.1}%  {}",
+                    format_size(krate.size),
+                    percent(krate.size, bloat.text_size),
+                    krate.name
+                );
+            }
+            let _ = writeln!(out, "\nfunctions:");
+            for function in &bloat.functions {
+                let _ = writeln!(
+                    out,
+                    "{:>9} {:>5.1}%  {}",
+                    format_size(function.size),
+                    percent(function.size, bloat.text_size),
+                    function.name
+                );
+            }
+        } else {
+            let _ = writeln!(out, "\nno symbol table; build without strip to see crates");
+        }
+        if let Some(previous) = &self.previous {
+            let _ = writeln!(
+                out,
+                "\nsince the installed version ({}{}):",
+                previous.installed_at,
+                previous
+                    .commit
+                    .as_deref()
+                    .map(|commit| format!(", {}", short(commit)))
+                    .unwrap_or_default()
+            );
+            for change in &previous.changes {
+                let _ = writeln!(out, "{}", change);
+            }
+        }
+        write!(f, "{}", out)
+    }
+}
+
+fn percent(size: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        size as f64 / total as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledger::tests::entry;
+
+    use super::*;
+    #[test]
+    fn シンボルからクレートを求める() {
+        assert_eq!(
+            demangle("_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            ("core::ptr::drop_in_place".to_string(), "core".to_string())
+        );
+        assert_eq!(
+            demangle("_ZN66_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..drop..Drop$GT$4drop17h0123456789abcdefE").1,
+            "alloc"
+        );
+        assert_eq!(crate_of("<&str as core::fmt::Display>::fmt"), Some("core"));
+        assert_eq!(
+            crate_of("<&mut app::Config as serde::Serialize>::serialize"),
+            Some("app")
+        );
+        assert_eq!(
+            crate_of("<[u8] as alloc::slice::hack::ConvertVec>::to_vec"),
+            Some("alloc")
+        );
+        assert_eq!(
+            crate_of("<core::option::Option<u64> as core::fmt::Debug>::fmt"),
+            Some("core")
+        );
+        assert_eq!(crate_of("<str>::trim"), Some("core"));
+        assert_eq!(
+            demangle("memcpy"),
+            ("memcpy".to_string(), UNKNOWN_CRATE.to_string())
+        );
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn 実行ファイルのサイズを内訳に分ける() {
+        let bloat = Bloat::load(std::env::current_exe().unwrap())
+            .unwrap()
+            .top(10);
+
+        assert!(bloat.symbols);
+        assert!(bloat.text_size > 0);
+        assert_eq!(bloat.functions.len(), 10);
+        assert!(bloat
+            .functions
+            .windows(2)
+            .all(|pair| pair[0].size >= pair[1].size));
+        assert!(bloat.crates.iter().any(|krate| krate.name == "cw"));
+        assert!(bloat.crates.iter().any(|krate| krate.name == "std"));
+        assert!(bloat.sections.iter().any(|section| section.name == ".text"));
+        assert!(Bloat::analyze(b"not elf").is_err());
+    }
+    #[test]
+    fn インストール済みの版と比べる() {
+        let bloat = |file_size, crates: &[(&str, u64)]| Bloat {
+            file_size,
+            text_size: crates.iter().map(|(_, size)| size).sum(),
+            symbols: true,
+            sections: Vec::new(),
+            crates: crates
+                .iter()
+                .map(|(name, size)| CrateSize {
+                    name: name.to_string(),
+                    size: *size,
+                })
+                .collect(),
+            functions: Vec::new(),
+        };
+        let previous = bloat(1000, &[("std", 300), ("app", 100), ("regex", 50)]);
+        let current = bloat(2000, &[("std", 300), ("app", 120), ("serde", 400)]);
+        let entry = LedgerEntry {
+            commit: Some("0123456789abcdef".to_string()),
+            ..entry("app", "/work/app")
+        };
+
+        let comparison = Comparison::new(&entry, &previous, &current);
+
+        assert_eq!(
+            comparison
+                .changes
+                .iter()
+                .map(|change| (change.name.as_str(), change.delta()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("file", 1000),
+                ("text", 370),
+                ("serde", 400),
+                ("regex", -50),
+                ("app", 20)
+            ]
+        );
+        assert_eq!(
+            comparison.changes[3].to_string(),
+            format!("{:<24} {:>9} -> {:>9} (-50B)", "regex", "50B", "0B")
+        );
+
+        let stripped = Bloat {
+            symbols: false,
+            crates: Vec::new(),
+            ..previous
+        };
+        assert_eq!(
+            Comparison::new(&entry, &stripped, &current).changes.len(),
+            2
+        );
+    }
+}
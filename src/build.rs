@@ -1,51 +1,1113 @@
-use std::{env::VarError, path::PathBuf};
+use std::{
+    env::VarError,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::commands::{cp, get_exe_filepaths, run_command};
+use crate::{
+    build_info::BuildInfo,
+    cache::{ArtifactCache, CacheKey},
+    cargo_messages::{artifacts, compiled_crate_count, parse_messages, render_diagnostic},
+    checksum::sha256_file,
+    completions::Completions,
+    diagnostics::{DiagnosticSummary, WarningsDenied},
+    env_preset::EnvPreset,
+    git::{current_branch, has_changes, head_commit, head_commit_time, is_dirty, short},
+    install::Installer,
+    ledger::{Ledger, LedgerEntry},
+    lock::InstallLock,
+    metadata::CargoMetadata,
+    reproducible::{normalized_env, Reproducibility},
+    runner::{CommandLine, CommandRunner, SystemCommandRunner},
+    rustc::RustcVersion,
+    sign::SignKey,
+    smoke::SmokeTest,
+    strip::{Strip, Stripped},
+    time::format_rfc3339,
+};
 
-pub struct CargoBuildWrapper {
+/// Installed binaries, how they were stripped and the installed debug files.
+type InstalledExes = (Vec<PathBuf>, Vec<Stripped>, Vec<PathBuf>);
+/// A stripped staged binary and its staged debug file with the destination.
+type StrippedExe = (Stripped, Option<(PathBuf, PathBuf)>);
+
+pub struct CargoBuildWrapper<R: CommandRunner = SystemCommandRunner> {
     mode: BuildMode,
     copy_dir: String,
+    project_dir: PathBuf,
+    timeout: Option<Duration>,
+    wait_for_lock: bool,
+    features: Vec<String>,
+    bins: Vec<String>,
+    deny_warnings: bool,
+    smoke_test: Option<SmokeTest>,
+    strip: Option<Strip>,
+    sign_key: Option<SignKey>,
+    env_preset: Option<EnvPreset>,
+    branch_suffix: bool,
+    cache: Option<ArtifactCache>,
+    completions: Option<Completions>,
+    runner: R,
 }
 
 impl CargoBuildWrapper {
-    const RELEASE_TARGET_DIR: &'static str = "target/release";
-    const DEBUG_TARGET_DIR: &'static str = "target/debug";
     pub fn new(mode: BuildMode, copy_dir: impl Into<String>) -> Self {
+        Self::with_runner(mode, copy_dir, SystemCommandRunner::new())
+    }
+    pub fn from_env(mode: BuildMode) -> Result<Self, VarError> {
+        let copy_dir = std::env::var("RUST_BIN_PATH")?;
+        Ok(Self::new(mode, copy_dir))
+    }
+}
+impl<R: CommandRunner> CargoBuildWrapper<R> {
+    pub fn with_runner(mode: BuildMode, copy_dir: impl Into<String>, runner: R) -> Self {
         Self {
             mode,
             copy_dir: copy_dir.into(),
+            project_dir: PathBuf::from("."),
+            timeout: None,
+            wait_for_lock: true,
+            features: Vec::new(),
+            bins: Vec::new(),
+            deny_warnings: false,
+            smoke_test: None,
+            strip: None,
+            sign_key: None,
+            env_preset: None,
+            branch_suffix: false,
+            cache: None,
+            completions: None,
+            runner,
         }
     }
-    pub fn from_env(mode: BuildMode) -> Result<Self, VarError> {
-        let copy_dir = std::env::var("RUST_BIN_PATH")?;
-        Ok(Self::new(mode, copy_dir))
+    /// The directory `cargo build` runs in, the current one by default.
+    pub fn project_dir(mut self, project_dir: impl AsRef<Path>) -> Self {
+        self.project_dir = project_dir.as_ref().to_path_buf();
+        self
+    }
+    /// Gives up on `cargo build` when it runs longer than `timeout`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// When false, fails instead of waiting for another cw installing into the same directory.
+    pub fn wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+        self.wait_for_lock = wait_for_lock;
+        self
+    }
+    /// Cargo features to enable, recorded in the ledger.
+    pub fn features(mut self, features: Vec<String>) -> Self {
+        self.features = features;
+        self
+    }
+    /// Builds only these `[[bin]]` targets instead of all of them.
+    pub fn bins(mut self, bins: Vec<String>) -> Self {
+        self.bins = bins;
+        self
+    }
+    /// Refuses to install when the build produced any warning.
+    pub fn deny_warnings(mut self, deny_warnings: bool) -> Self {
+        self.deny_warnings = deny_warnings;
+        self
     }
-    pub fn build(&self) -> Result<(), Box<dyn std::error::Error>> {
-        match self.mode {
-            BuildMode::Release => run_command("cargo", &["build", "--release"])?,
-            BuildMode::Debug => run_command("cargo", &["build"])?,
+    /// Runs every installed binary once and restores the previous install when one fails.
+    pub fn smoke_test(mut self, smoke_test: Option<SmokeTest>) -> Self {
+        self.smoke_test = smoke_test;
+        self
+    }
+    /// Strips the installed copies; the artifacts in the target directory keep their debug info.
+    pub fn strip(mut self, strip: Option<Strip>) -> Self {
+        self.strip = strip;
+        self
+    }
+    /// Signs the checksum recorded in the ledger for each installed binary.
+    pub fn sign_key(mut self, sign_key: Option<SignKey>) -> Self {
+        self.sign_key = sign_key;
+        self
+    }
+    /// Runs `cargo build` with the preset's environment, recorded in the ledger.
+    pub fn env_preset(mut self, env_preset: Option<EnvPreset>) -> Self {
+        self.env_preset = env_preset;
+        self
+    }
+    /// Installs `app` as `app@<branch>`, or `app@<commit>` on a detached `HEAD`,
+    /// leaving the unsuffixed install alone.
+    pub fn branch_suffix(mut self, branch_suffix: bool) -> Self {
+        self.branch_suffix = branch_suffix;
+        self
+    }
+    /// Installs from the cache instead of running cargo when the work tree is clean and
+    /// the commit was built before with the same settings, and adds new builds to it.
+    pub fn cache(mut self, cache: Option<ArtifactCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+    /// Writes completion scripts and a man page for each installed binary, tracked in the
+    /// ledger. Skipped for `--branch-suffix` installs, which would take over those of the main one.
+    pub fn completions(mut self, completions: Option<Completions>) -> Self {
+        self.completions = completions;
+        self
+    }
+    pub fn build(&self) -> Result<BuildReport, Box<dyn std::error::Error>> {
+        let cache_key = self.cache_key();
+        let cached = match (&self.cache, &cache_key) {
+            (Some(cache), Some(key)) => self.restore(cache, key),
+            _ => None,
         };
-        self.cp_exes()
+        let mut report = match cached {
+            Some(report) => report,
+            None => self.compile()?,
+        };
+        let installer = self.installer()?;
+        let lock = InstallLock::acquire(&self.copy_dir, self.wait_for_lock)?;
+        let (installed, stripped, debug_files) = self.cp_exes(&installer, &report.binaries)?;
+        report.installed = installed;
+        report.stripped = stripped;
+        self.smoke(&installer, &report.installed, &debug_files)?;
+        let generated = self.generate_docs(&report.binaries, &report.installed);
+        self.record_ledger(&installer, &report.binaries, &report.stripped, &generated)?;
+        drop(lock);
+        if let (Some(cache), Some(key), false) = (&self.cache, &cache_key, report.cached) {
+            if let Err(e) = cache.put(key, &report.binaries) {
+                eprintln!("cw: failed to cache the build: {}", e);
+            }
+        }
+        Ok(report)
     }
-    fn get_exe_filepaths(&self) -> Vec<PathBuf> {
-        match self.mode {
-            BuildMode::Release => get_exe_filepaths(Self::RELEASE_TARGET_DIR),
-            BuildMode::Debug => get_exe_filepaths(Self::DEBUG_TARGET_DIR),
+    /// Runs `cargo build` without installing anything, e.g. for packaging.
+    pub fn compile(&self) -> Result<BuildReport, Box<dyn std::error::Error>> {
+        self.compile_with(None, &[])
+    }
+    /// Builds twice into fresh target directories with a normalized environment
+    /// and compares the binaries byte for byte; nothing is installed.
+    pub fn reproducible_check(&self) -> Result<Reproducibility, Box<dyn std::error::Error>> {
+        let project = self.project_dir.canonicalize()?;
+        let metadata = CargoMetadata::load(&self.runner, &project)?;
+        let source_date_epoch = head_commit_time(&self.runner, &project).unwrap_or(0);
+        let rustflags = std::env::var("RUSTFLAGS").ok();
+        let rustflags = match &self.env_preset {
+            Some(preset) => preset.rustflags(rustflags.as_deref()),
+            None => rustflags,
+        };
+        let cargo_home = std::env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+        let dir = metadata.target_directory.join("cw-reproducible");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
         }
+        let mut builds = Vec::new();
+        for run in ["1", "2"] {
+            let target_dir = dir.join(run);
+            let env = normalized_env(
+                &project,
+                &target_dir,
+                cargo_home.as_deref(),
+                source_date_epoch,
+                rustflags.as_deref(),
+            );
+            builds.push(self.compile_with(Some(&target_dir), &env)?.binaries);
+        }
+        Reproducibility::compare(&builds[0], &builds[1])
     }
-    fn cp_exes(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for path in self
-            .get_exe_filepaths()
-            .into_iter()
-            .filter_map(|path| path.as_os_str().to_str().map(|p| p.to_owned()))
+    /// `None` when the build cannot be cached: outside of git, with uncommitted changes or
+    /// with untracked files that are not ignored.
+    fn cache_key(&self) -> Option<CacheKey> {
+        self.cache.as_ref()?;
+        let project = self.project_dir.canonicalize().ok()?;
+        let commit = head_commit(&self.runner, &project)?;
+        if has_changes(&self.runner, &project).unwrap_or(true) {
+            return None;
+        }
+        let rustc = RustcVersion::load(&self.runner, &project).ok()?;
+        Some(CacheKey {
+            project: project.display().to_string(),
+            commit,
+            profile: self.mode.profile().to_string(),
+            features: self.features.clone(),
+            bins: self.bins.clone(),
+            target: rustc.host,
+            rustc: rustc.version,
+            env_preset: self.env_preset.as_ref().map(|preset| preset.name.clone()),
+            rustflags: std::env::var("RUSTFLAGS").ok(),
+        })
+    }
+    fn restore(&self, cache: &ArtifactCache, key: &CacheKey) -> Option<BuildReport> {
+        let started = Instant::now();
+        let binaries = cache
+            .get(key)
+            .unwrap_or_else(|e| {
+                eprintln!("cw: failed to read the artifact cache: {}", e);
+                None
+            })
+            .filter(|binaries| !binaries.is_empty())?;
+        Some(BuildReport {
+            profile: self.mode.profile(),
+            duration: started.elapsed(),
+            crates_compiled: 0,
+            binaries,
+            installed: Vec::new(),
+            stripped: Vec::new(),
+            cached: true,
+        })
+    }
+    fn compile_with(
+        &self,
+        target_dir: Option<&Path>,
+        env: &[(String, String)],
+    ) -> Result<BuildReport, Box<dyn std::error::Error>> {
+        let command = match self.mode {
+            BuildMode::Release => CommandLine::new("cargo").args(["build", "--release"]),
+            BuildMode::Debug => CommandLine::new("cargo").arg("build"),
+        }
+        .forward_signals();
+        let command = match target_dir {
+            Some(dir) => command.args(["--target-dir".to_string(), dir.display().to_string()]),
+            None => command,
+        };
+        let preset_env = self
+            .env_preset
+            .as_ref()
+            .map(|preset| preset.env(&self.mode, std::env::var("RUSTFLAGS").ok().as_deref()))
+            .unwrap_or_default();
+        let command = preset_env
+            .iter()
+            .chain(env)
+            .fold(command, |command, (key, value)| command.env(key, value));
+        let command = self.bins.iter().fold(command, |command, bin| {
+            command.args(["--bin", bin.as_str()])
+        });
+        let command = if self.features.is_empty() {
+            command
+        } else {
+            command.args(["--features".to_string(), self.features.join(",")])
+        };
+        let started = Instant::now();
+        let command = command
+            .arg("--message-format=json-diagnostic-rendered-ansi")
+            .current_dir(&self.project_dir)
+            .quiet_stdout()
+            .render_stdout(render_diagnostic)
+            .timeout(self.timeout);
+        let output = self.runner.run(&command)?;
+        let duration = started.elapsed();
+        let messages = parse_messages(&output.stdout);
+        let diagnostics = DiagnosticSummary::from_messages(&messages);
+        if !diagnostics.is_empty() {
+            eprint!("{}", diagnostics);
+        }
+        output.check(&command)?;
+        if self.deny_warnings && diagnostics.warnings > 0 {
+            return Err(Box::new(WarningsDenied {
+                warnings: diagnostics.warnings,
+            }));
+        }
+        let binaries = artifacts(&messages)
+            .filter_map(|artifact| {
+                artifact.bin_executable().map(|path| {
+                    BuiltBinary::new(
+                        &artifact.target.name,
+                        path.clone(),
+                        artifact.package_version(),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(BuildReport {
+            profile: self.mode.profile(),
+            duration,
+            crates_compiled: compiled_crate_count(&messages),
+            binaries,
+            installed: Vec::new(),
+            stripped: Vec::new(),
+            cached: false,
+        })
+    }
+    /// Installs the binaries, stripped before they are renamed into place so that a failing
+    /// strip leaves the previous install alone. Also returns the installed debug files.
+    fn cp_exes(
+        &self,
+        installer: &Installer,
+        binaries: &[BuiltBinary],
+    ) -> Result<InstalledExes, Box<dyn std::error::Error>> {
+        let files = binaries
+            .iter()
+            .map(|binary| (binary.path.clone(), binary.name.clone()))
+            .collect::<Vec<_>>();
+        let mut staged = installer.stage_as(&files)?;
+        let stripped = match self.strip_exes(installer, &staged) {
+            Ok(stripped) => stripped,
+            Err(e) => {
+                Installer::discard(&staged);
+                return Err(e);
+            }
+        };
+        staged.extend(stripped.iter().filter_map(|(_, debug)| debug.clone()));
+        let mut installed = installer.commit(&staged)?;
+        let debug_files = installed.split_off(binaries.len());
+        Ok((
+            installed,
+            stripped.into_iter().map(|(stripped, _)| stripped).collect(),
+            debug_files,
+        ))
+    }
+    /// Strips the staged copies, reporting them by their installed paths; staged debug files
+    /// come with their destinations.
+    fn strip_exes(
+        &self,
+        installer: &Installer,
+        staged: &[(PathBuf, PathBuf)],
+    ) -> Result<Vec<StrippedExe>, Box<dyn std::error::Error>> {
+        let Some(strip) = &self.strip else {
+            return Ok(Vec::new());
+        };
+        let staging_dir = installer.staging_dir()?;
+        let mut stripped = Vec::new();
+        for (staging, destination) in staged {
+            let debug_file = Strip::debug_file(destination);
+            let staged_debug = staging_dir.join(debug_file.file_name().unwrap_or_default());
+            match strip.strip(&self.runner, staging, &staged_debug) {
+                Ok(done) => stripped.push((
+                    Stripped {
+                        path: destination.clone(),
+                        debug_file: done.debug_file.as_ref().map(|_| debug_file.clone()),
+                        before: done.before,
+                        after: done.after,
+                    },
+                    done.debug_file.map(|staged| (staged, debug_file)),
+                )),
+                Err(e) => {
+                    let _ = std::fs::remove_file(&staged_debug);
+                    Installer::discard(
+                        &stripped
+                            .into_iter()
+                            .filter_map(|(_, debug)| debug)
+                            .collect::<Vec<_>>(),
+                    );
+                    return Err(e);
+                }
+            }
+        }
+        Ok(stripped)
+    }
+    fn smoke(
+        &self,
+        installer: &Installer,
+        installed: &[PathBuf],
+        debug_files: &[PathBuf],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(smoke_test) = &self.smoke_test else {
+            return Ok(());
+        };
+        if let Err(e) = installed
+            .iter()
+            .try_for_each(|path| smoke_test.check(&self.runner, path))
         {
-            cp(&path, &self.copy_dir)?;
+            installer.rollback(installed)?;
+            installer.rollback(debug_files)?;
+            eprintln!("cw: rolled back to the previous install");
+            return Err(Box::new(e));
         }
         Ok(())
     }
+    /// Paths written for each installed binary; failing to is not worth failing the install.
+    fn generate_docs(&self, binaries: &[BuiltBinary], installed: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+        let Some(completions) = self.completions.as_ref().filter(|_| !self.branch_suffix) else {
+            return Vec::new();
+        };
+        binaries
+            .iter()
+            .zip(installed)
+            .map(|(binary, path)| {
+                completions
+                    .generate(&self.runner, path, &binary.bin)
+                    .unwrap_or_else(|e| {
+                        eprintln!("cw: skipped completions of {}: {}", binary.bin, e);
+                        e.written
+                    })
+            })
+            .collect()
+    }
+    fn installer(&self) -> Result<Installer, Box<dyn std::error::Error>> {
+        let name_suffix = if self.branch_suffix {
+            let branch = current_branch(&self.runner, &self.project_dir);
+            let suffix = branch
+                .map(|branch| branch.replace(|c: char| c == '/' || c.is_whitespace(), "-"))
+                .or_else(|| {
+                    head_commit(&self.runner, &self.project_dir)
+                        .map(|commit| short(&commit).to_string())
+                })
+                .ok_or("--branch-suffix needs the project to be a git checkout")?;
+            Some(suffix)
+        } else {
+            None
+        };
+        Ok(Installer::new(&self.copy_dir)
+            .keep_previous(self.smoke_test.is_some())
+            .name_suffix(name_suffix))
+    }
+    fn record_ledger(
+        &self,
+        installer: &Installer,
+        binaries: &[BuiltBinary],
+        stripped: &[Stripped],
+        generated: &[Vec<PathBuf>],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let project = self.project_dir.canonicalize()?;
+        let commit = head_commit(&self.runner, &project);
+        let branch = self
+            .branch_suffix
+            .then(|| current_branch(&self.runner, &project))
+            .flatten();
+        let dirty = commit
+            .as_ref()
+            .and_then(|_| is_dirty(&self.runner, &project).ok());
+        let rustc = RustcVersion::load(&self.runner, &project)
+            .ok()
+            .map(|rustc| rustc.version);
+        let installed_at = format_rfc3339(SystemTime::now());
+        let mut ledger = Ledger::load(&self.copy_dir)?;
+        for (i, binary) in binaries.iter().enumerate() {
+            let name = installer.installed_name(&binary.name);
+            let debug_file = stripped
+                .iter()
+                .find(|stripped| stripped.path.file_name() == Some(name.as_ref()))
+                .and_then(|stripped| stripped.debug_file.as_ref())
+                .and_then(|path| path.file_name())
+                .map(|name| name.to_string_lossy().into_owned());
+            let sha256 = sha256_file(Path::new(&self.copy_dir).join(&name))?;
+            let signature = self.sign_key.as_ref().map(|key| key.sign(&name, &sha256));
+            let env_preset = self.env_preset.as_ref().map(|preset| preset.name.clone());
+            // Files of the previous install that were not rewritten stay tracked, so that
+            // `cw prune` still finds them.
+            let mut tracked = generated.get(i).cloned().unwrap_or_default();
+            if let Some(previous) = ledger.get(&name) {
+                for path in &previous.generated {
+                    if path.is_file() && !tracked.contains(path) {
+                        tracked.push(path.clone());
+                    }
+                }
+            }
+            let built_at = binary
+                .path
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or_else(|_| SystemTime::now());
+            BuildInfo {
+                name: name.clone(),
+                bin: binary.bin.clone(),
+                version: binary.version.clone(),
+                commit: commit.clone(),
+                dirty,
+                profile: self.mode.profile().to_string(),
+                features: self.features.clone(),
+                rustc: rustc.clone(),
+                built_at: format_rfc3339(built_at),
+                source: project.display().to_string(),
+                branch: branch.clone(),
+                env_preset: env_preset.clone(),
+            }
+            .save(&self.copy_dir)?;
+            ledger.record(LedgerEntry {
+                name,
+                bin: binary.bin.clone(),
+                project: project.display().to_string(),
+                profile: self.mode.profile().to_string(),
+                installed_at: installed_at.clone(),
+                commit: commit.clone(),
+                branch: branch.clone(),
+                features: self.features.clone(),
+                debug_file,
+                sha256: Some(sha256),
+                signature,
+                env_preset,
+                generated: tracked,
+            });
+        }
+        ledger.save(&self.copy_dir)
+    }
 }
 
 pub enum BuildMode {
     Debug,
     Release,
 }
+impl BuildMode {
+    pub fn from_profile(profile: &str) -> Option<Self> {
+        match profile {
+            "debug" | "dev" => Some(Self::Debug),
+            "release" => Some(Self::Release),
+            _ => None,
+        }
+    }
+    pub fn profile(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Release => "release",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildReport {
+    pub profile: &'static str,
+    pub duration: Duration,
+    /// Packages compiled by this build, fresh ones excluded.
+    pub crates_compiled: usize,
+    pub binaries: Vec<BuiltBinary>,
+    pub installed: Vec<PathBuf>,
+    /// Empty unless installed with `strip`.
+    pub stripped: Vec<Stripped>,
+    /// Installed from the artifact cache without running cargo.
+    pub cached: bool,
+}
+impl BuildReport {
+    /// Where the executable of the `[[bin]]` target `bin` was installed.
+    pub fn installed_bin(&self, bin: &str) -> Option<&Path> {
+        self.binaries
+            .iter()
+            .zip(&self.installed)
+            .find(|(binary, _)| binary.bin == bin)
+            .map(|(_, path)| path.as_path())
+    }
+}
+
+/// An executable in the target directory as reported by cargo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltBinary {
+    /// File name of the executable.
+    pub name: String,
+    /// Name of the `[[bin]]` target.
+    pub bin: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Version of the package the target belongs to.
+    pub version: Option<String>,
+}
+impl BuiltBinary {
+    fn new(bin: &str, path: PathBuf, version: Option<&str>) -> Self {
+        Self {
+            bin: bin.to_string(),
+            version: version.map(str::to_string),
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: path.metadata().map(|m| m.len()).unwrap_or_default(),
+            path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cargo_messages::tests::{artifact_json, compiler_message_json},
+        checksum::sha256_hex,
+        commands::{ls_files, write_file},
+        completions::Shell,
+        config::CompletionsConfig,
+        metadata::tests::metadata_json,
+        runner::{CommandOutput, RecordingCommandRunner},
+        rustc::tests::RUSTC_VV,
+        sign::tests::sign_key,
+    };
+
+    use super::*;
+    #[test]
+    fn releaseビルド後にcargoが報告したexeファイルのみをコピーする() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("release/app");
+        write_file(&app, "binary").unwrap();
+        write_file(target.path().join("release/stale"), "").unwrap();
+        let stdout = [
+            artifact_json("dep", "lib", None, true),
+            artifact_json("app", "bin", app.to_str(), false),
+        ]
+        .join("\n");
+        let runner = RecordingCommandRunner::new()
+            .respond("cargo build", CommandOutput::new(0).stdout(stdout));
+
+        let report = CargoBuildWrapper::with_runner(
+            BuildMode::Release,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .sign_key(Some(sign_key()))
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            runner.command_lines(),
+            vec![
+                "cargo build --release --message-format=json-diagnostic-rendered-ansi",
+                "git rev-parse --verify --quiet HEAD^{commit}",
+                "rustc -vV"
+            ]
+        );
+        assert!(runner.commands()[0].is_quiet_stdout());
+        assert_eq!(
+            ls_files(bin_dir.path()).unwrap(),
+            vec![
+                bin_dir.path().join("app"),
+                bin_dir.path().join("app.cw.json")
+            ]
+        );
+        assert_eq!(report.profile, "release");
+        assert_eq!(report.crates_compiled, 1);
+        assert_eq!(
+            report.binaries,
+            vec![BuiltBinary {
+                name: "app".to_string(),
+                bin: "app".to_string(),
+                path: app,
+                size: 6,
+                version: Some("0.1.0".to_string()),
+            }]
+        );
+        assert_eq!(report.installed, vec![bin_dir.path().join("app")]);
+        let ledger = Ledger::load(bin_dir.path()).unwrap();
+        assert_eq!(ledger.entries().len(), 1);
+        assert_eq!(ledger.entries()[0].bin, "app");
+        assert_eq!(
+            Path::new(&ledger.entries()[0].project),
+            std::env::current_dir().unwrap()
+        );
+        let sha256 = ledger.entries()[0].sha256.clone().unwrap();
+        assert_eq!(sha256, sha256_hex(b"binary"));
+        assert!(sign_key().public_key().verify(
+            "app",
+            &sha256,
+            ledger.entries()[0].signature.as_ref().unwrap()
+        ));
+    }
+    #[test]
+    fn envプリセットの環境でビルドして台帳に記録する() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("release/app");
+        write_file(&app, "binary").unwrap();
+        let runner = RecordingCommandRunner::new().respond(
+            "cargo build",
+            CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false)),
+        );
+
+        CargoBuildWrapper::with_runner(
+            BuildMode::Release,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .env_preset(Some(EnvPreset::find("small", &Default::default()).unwrap()))
+        .build()
+        .unwrap();
+
+        assert!(runner.commands()[0].get_envs().contains(&(
+            "CARGO_PROFILE_RELEASE_OPT_LEVEL".to_string(),
+            "z".to_string()
+        )));
+        let ledger = Ledger::load(bin_dir.path()).unwrap();
+        assert_eq!(ledger.entries()[0].env_preset.as_deref(), Some("small"));
+    }
+    #[test]
+    fn ブランチ名を付けてインストールする() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("release/app");
+        write_file(&app, "binary").unwrap();
+        write_file(bin_dir.path().join("app"), "main").unwrap();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "cargo build",
+                CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false)),
+            )
+            .respond(
+                "git symbolic-ref",
+                CommandOutput::new(0).stdout("feature/x\n"),
+            )
+            .respond(
+                "git rev-parse",
+                CommandOutput::new(0).stdout("0123456789abcdef\n"),
+            );
+
+        let report = CargoBuildWrapper::with_runner(
+            BuildMode::Release,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .branch_suffix(true)
+        .build()
+        .unwrap();
+
+        assert_eq!(report.installed, vec![bin_dir.path().join("app@feature-x")]);
+        assert_eq!(
+            report.installed_bin("app"),
+            Some(bin_dir.path().join("app@feature-x").as_path())
+        );
+        assert_eq!(
+            std::fs::read_to_string(bin_dir.path().join("app")).unwrap(),
+            "main"
+        );
+        let ledger = Ledger::load(bin_dir.path()).unwrap();
+        let entry = ledger.get("app@feature-x").unwrap();
+        assert_eq!(entry.bin, "app");
+        assert_eq!(entry.branch.as_deref(), Some("feature/x"));
+        assert_eq!(entry.commit.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(entry.sha256, Some(sha256_hex(b"binary")));
+        let info = BuildInfo::load(bin_dir.path(), "app@feature-x").unwrap();
+        assert_eq!(info.bin, "app");
+        assert_eq!(info.version.as_deref(), Some("0.1.0"));
+        assert_eq!(info.commit.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(info.dirty, Some(false));
+        assert_eq!(info.branch.as_deref(), Some("feature/x"));
+        assert!(!bin_dir.path().join("app.cw.json").exists());
+    }
+    #[test]
+    fn クリーンなコミットはキャッシュからcargoなしでインストールする() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("release/app");
+        write_file(&app, "binary").unwrap();
+        let runner = |status: &str| {
+            RecordingCommandRunner::new()
+                .respond(
+                    "cargo build",
+                    CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false)),
+                )
+                .respond(
+                    "git rev-parse",
+                    CommandOutput::new(0).stdout("0123456789abcdef\n"),
+                )
+                .respond("git status", CommandOutput::new(0).stdout(status))
+                .respond("rustc -vV", CommandOutput::new(0).stdout(RUSTC_VV))
+        };
+        let build = |runner: &RecordingCommandRunner| {
+            CargoBuildWrapper::with_runner(
+                BuildMode::Release,
+                bin_dir.path().to_str().unwrap(),
+                runner,
+            )
+            .cache(Some(ArtifactCache::new(cache_dir.path())))
+            .build()
+            .unwrap()
+        };
+        let cargo_builds = |runner: &RecordingCommandRunner| {
+            runner
+                .command_lines()
+                .iter()
+                .filter(|line| line.starts_with("cargo build"))
+                .count()
+        };
+
+        let clean = runner("");
+        assert!(!build(&clean).cached);
+        std::fs::remove_file(&app).unwrap();
+        std::fs::remove_file(bin_dir.path().join("app")).unwrap();
+        let report = build(&clean);
+
+        assert!(report.cached);
+        assert_eq!(cargo_builds(&clean), 1);
+        assert_eq!(
+            std::fs::read_to_string(bin_dir.path().join("app")).unwrap(),
+            "binary"
+        );
+        assert_eq!(
+            Ledger::load(bin_dir.path()).unwrap().entries()[0].sha256,
+            Some(sha256_hex(b"binary"))
+        );
+
+        write_file(&app, "binary").unwrap();
+        let dirty = runner(" M src/main.rs\n");
+        assert!(!build(&dirty).cached);
+        assert_eq!(cargo_builds(&dirty), 1);
+        let untracked = runner("?? src/new.rs\n");
+        assert!(!build(&untracked).cached);
+        assert_eq!(cargo_builds(&untracked), 1);
+        assert!(untracked
+            .command_lines()
+            .contains(&"git status --porcelain --untracked-files=normal".to_string()));
+    }
+    #[test]
+    fn 置き換える前にstripし失敗したら前のインストールを残す() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("release/app");
+        let build = |runner: &RecordingCommandRunner, strip: Strip| {
+            CargoBuildWrapper::with_runner(
+                BuildMode::Release,
+                bin_dir.path().to_str().unwrap(),
+                runner,
+            )
+            .strip(Some(strip))
+            .build()
+        };
+        let cargo_build =
+            || CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false));
+        write_file(&app, "binary").unwrap();
+
+        let runner = RecordingCommandRunner::new().respond("cargo build", cargo_build());
+        build(&runner, Strip::new()).unwrap();
+
+        let staging = bin_dir.path().join(".app.cw-tmp");
+        assert!(runner
+            .command_lines()
+            .contains(&format!("strip {}", staging.display())));
+        assert_eq!(
+            std::fs::read_to_string(bin_dir.path().join("app")).unwrap(),
+            "binary"
+        );
+
+        write_file(&app, "new binary").unwrap();
+        let failing = RecordingCommandRunner::new()
+            .respond("cargo build", cargo_build())
+            .fail("strip");
+        assert!(build(&failing, Strip::new().keep_debug(true)).is_err());
+
+        assert_eq!(
+            std::fs::read_to_string(bin_dir.path().join("app")).unwrap(),
+            "binary"
+        );
+        assert_eq!(
+            ls_files(bin_dir.path()).unwrap(),
+            vec![
+                bin_dir.path().join("app"),
+                bin_dir.path().join("app.cw.json")
+            ]
+        );
+        assert!(ls_files(bin_dir.path().join(".cw/staging"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Ledger::load(bin_dir.path()).unwrap().entries()[0].debug_file,
+            None
+        );
+    }
+    #[test]
+    fn インストールしたバイナリの補完とmanページを書いて台帳に記録する() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let docs = tempfile::tempdir().unwrap();
+        let app = target.path().join("release/app");
+        write_file(&app, "binary").unwrap();
+        let installed = bin_dir.path().join("app");
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "cargo build",
+                CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false)),
+            )
+            .respond(
+                format!("{} --help", installed.display()),
+                CommandOutput::new(0)
+                    .stdout("Usage: app [OPTIONS]\n\nOptions:\n  -q, --quiet  Less output\n"),
+            );
+        let config = CompletionsConfig {
+            shells: vec![Shell::Fish],
+            fish_dir: Some(docs.path().join("fish")),
+            man_dir: Some(docs.path().join("man1")),
+            ..CompletionsConfig::default()
+        };
+
+        CargoBuildWrapper::with_runner(
+            BuildMode::Release,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .completions(Some(Completions::from_config(&config)))
+        .build()
+        .unwrap();
+
+        let generated = vec![
+            docs.path().join("fish/app.fish"),
+            docs.path().join("man1/app.1"),
+        ];
+        assert!(std::fs::read_to_string(&generated[0])
+            .unwrap()
+            .contains("-l quiet"));
+        assert!(generated[1].is_file());
+        let ledger = Ledger::load(bin_dir.path()).unwrap();
+        assert_eq!(ledger.entries()[0].generated, generated);
+
+        // Reinstalling without completions keeps track of the files for `cw prune`.
+        CargoBuildWrapper::with_runner(
+            BuildMode::Release,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .build()
+        .unwrap();
+        let ledger = Ledger::load(bin_dir.path()).unwrap();
+        assert_eq!(ledger.entries()[0].generated, generated);
+    }
+    #[test]
+    fn 別々のターゲットディレクトリに二回ビルドして比べる() {
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().canonicalize().unwrap();
+        let root = root.to_str().unwrap();
+        let target_dir = |run: &str| format!("{}/target/cw-reproducible/{}", root, run);
+        let built = |run: &str, content: &str| {
+            let path = format!("{}/built/{}/app", root, run);
+            write_file(&path, content).unwrap();
+            CommandOutput::new(0).stdout(artifact_json("app", "bin", Some(&path), false))
+        };
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "cargo metadata",
+                CommandOutput::new(0).stdout(metadata_json(root, "app", &["app"])),
+            )
+            .respond("git log", CommandOutput::new(0).stdout("1700000000\n"))
+            .respond(
+                format!("cargo build --release --target-dir {}", target_dir("1")),
+                built("1", "binary"),
+            )
+            .respond(
+                format!("cargo build --release --target-dir {}", target_dir("2")),
+                built("2", "binary"),
+            );
+
+        let reproducibility =
+            CargoBuildWrapper::with_runner(BuildMode::Release, "/unused", &runner)
+                .project_dir(project.path())
+                .reproducible_check()
+                .unwrap();
+
+        assert!(reproducibility.is_reproducible());
+        let builds = runner
+            .commands()
+            .into_iter()
+            .filter(|command| command.to_string().starts_with("cargo build"))
+            .collect::<Vec<_>>();
+        assert_eq!(builds.len(), 2);
+        for (build, run) in builds.iter().zip(["1", "2"]) {
+            let env = build.get_envs();
+            assert_eq!(
+                env[0],
+                ("SOURCE_DATE_EPOCH".to_string(), "1700000000".to_string())
+            );
+            assert!(env[1].1.ends_with(&format!(
+                "--remap-path-prefix={}=/cw/target",
+                target_dir(run)
+            )));
+        }
+    }
+    #[test]
+    fn 他のcwがインストール中ならwaitせずに失敗する() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("debug/app");
+        write_file(&app, "binary").unwrap();
+        let runner = RecordingCommandRunner::new().respond(
+            "cargo build",
+            CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false)),
+        );
+        let _lock = InstallLock::acquire(bin_dir.path(), false).unwrap();
+
+        let result = CargoBuildWrapper::with_runner(
+            BuildMode::Debug,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .wait_for_lock(false)
+        .build();
+
+        assert!(result.unwrap_err().is::<crate::lock::LockHeld>());
+        assert!(ls_files(bin_dir.path()).unwrap().is_empty());
+    }
+    #[test]
+    fn deny_warningsの時は警告があればコピーしない() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("debug/app");
+        write_file(&app, "binary").unwrap();
+        let stdout = [
+            compiler_message_json("app", "warning", Some("dead_code"), "src/main.rs", 1),
+            artifact_json("app", "bin", app.to_str(), false),
+        ]
+        .join("\n");
+        let runner = RecordingCommandRunner::new()
+            .respond("cargo build", CommandOutput::new(0).stdout(stdout));
+        let wrapper = CargoBuildWrapper::with_runner(
+            BuildMode::Debug,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        );
+
+        let result = wrapper.deny_warnings(true).build();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "refusing to install: the build has 1 warning"
+        );
+        assert!(ls_files(bin_dir.path()).unwrap().is_empty());
+    }
+    #[test]
+    fn スモークテストが失敗したら以前のバイナリに戻す() {
+        let target = tempfile::tempdir().unwrap();
+        let bin_dir = tempfile::tempdir().unwrap();
+        let app = target.path().join("debug/app");
+        write_file(&app, "broken").unwrap();
+        write_file(bin_dir.path().join("app"), "working").unwrap();
+        let installed = bin_dir.path().join("app");
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "cargo build",
+                CommandOutput::new(0).stdout(artifact_json("app", "bin", app.to_str(), false)),
+            )
+            .fail(installed.to_str().unwrap());
+
+        let result = CargoBuildWrapper::with_runner(
+            BuildMode::Debug,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .smoke_test(Some(SmokeTest::new()))
+        .build();
+
+        assert!(result.unwrap_err().is::<crate::smoke::SmokeTestFailed>());
+        assert_eq!(
+            runner.command_lines()[1],
+            format!("{} --version", installed.display())
+        );
+        assert_eq!(std::fs::read_to_string(&installed).unwrap(), "working");
+        assert!(Ledger::load(bin_dir.path()).unwrap().entries().is_empty());
+    }
+    #[test]
+    fn ビルドが失敗した時はコピーしない() {
+        let bin_dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new().fail("cargo build");
+
+        let result = CargoBuildWrapper::with_runner(
+            BuildMode::Debug,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .build();
+
+        assert!(result.is_err());
+        assert_eq!(
+            runner.command_lines(),
+            vec!["cargo build --message-format=json-diagnostic-rendered-ansi"]
+        );
+        assert!(ls_files(bin_dir.path()).unwrap().is_empty());
+    }
+    #[test]
+    fn ビルドがタイムアウトした時はコピーしない() {
+        let bin_dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new().time_out("cargo build");
+
+        let result = CargoBuildWrapper::with_runner(
+            BuildMode::Debug,
+            bin_dir.path().to_str().unwrap(),
+            &runner,
+        )
+        .timeout(Some(Duration::from_secs(60)))
+        .build();
+
+        assert!(result.is_err());
+        assert_eq!(
+            runner.commands()[0].get_timeout(),
+            Some(Duration::from_secs(60))
+        );
+        assert!(ls_files(bin_dir.path()).unwrap().is_empty());
+    }
+}
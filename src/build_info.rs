@@ -0,0 +1,174 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{git::short, ledger::Ledger};
+
+/// Where an installed binary came from, written next to it as `<name>.cw.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// File name in the install directory.
+    pub name: String,
+    pub bin: String,
+    /// Version of the package in `Cargo.toml`.
+    pub version: Option<String>,
+    pub commit: Option<String>,
+    /// Whether tracked files had uncommitted changes; `None` outside of a git work tree.
+    pub dirty: Option<bool>,
+    pub profile: String,
+    pub features: Vec<String>,
+    /// e.g. `rustc 1.70.0 (90c541806 2023-05-31)`
+    pub rustc: Option<String>,
+    pub built_at: String,
+    /// Canonical path of the project directory.
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_preset: Option<String>,
+}
+
+impl BuildInfo {
+    const SUFFIX: &'static str = ".cw.json";
+    pub fn path(install_dir: impl AsRef<Path>, name: &str) -> PathBuf {
+        install_dir
+            .as_ref()
+            .join(format!("{}{}", name, Self::SUFFIX))
+    }
+    pub fn save(&self, install_dir: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(
+            Self::path(install_dir, &self.name),
+            serde_json::to_string_pretty(self)? + "\n",
+        )?;
+        Ok(())
+    }
+    /// The sidecar of `name`, else what the ledger knows for binaries installed without one.
+    pub fn load(
+        install_dir: impl AsRef<Path>,
+        name: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let install_dir = install_dir.as_ref();
+        let path = Self::path(install_dir, name);
+        if path.is_file() {
+            let content = std::fs::read_to_string(&path)?;
+            return serde_json::from_str(&content)
+                .map_err(|e| format!("{}: {}", path.display(), e).into());
+        }
+        let ledger = Ledger::load(install_dir)?;
+        let entry = ledger
+            .get(name)
+            .ok_or_else(|| format!("{} was not installed by cw", name))?;
+        Ok(Self {
+            name: entry.name.clone(),
+            bin: entry.bin.clone(),
+            version: None,
+            commit: entry.commit.clone(),
+            dirty: None,
+            profile: entry.profile.clone(),
+            features: entry.features.clone(),
+            rustc: None,
+            built_at: entry.installed_at.clone(),
+            source: entry.project.clone(),
+            branch: entry.branch.clone(),
+            env_preset: entry.env_preset.clone(),
+        })
+    }
+}
+impl Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unknown = "unknown";
+        writeln!(
+            f,
+            "{} {}",
+            self.name,
+            self.version.as_deref().unwrap_or(unknown)
+        )?;
+        let commit = match (&self.commit, self.dirty) {
+            (Some(commit), Some(true)) => format!("{} (dirty)", short(commit)),
+            (Some(commit), _) => short(commit).to_string(),
+            (None, _) => "not a git checkout".to_string(),
+        };
+        writeln!(f, "  commit:   {}", commit)?;
+        if let Some(branch) = &self.branch {
+            writeln!(f, "  branch:   {}", branch)?;
+        }
+        writeln!(f, "  profile:  {}", self.profile)?;
+        if !self.features.is_empty() {
+            writeln!(f, "  features: {}", self.features.join(", "))?;
+        }
+        if let Some(env_preset) = &self.env_preset {
+            writeln!(f, "  preset:   {}", env_preset)?;
+        }
+        writeln!(
+            f,
+            "  rustc:    {}",
+            self.rustc.as_deref().unwrap_or(unknown)
+        )?;
+        writeln!(f, "  built:    {}", self.built_at)?;
+        writeln!(f, "  source:   {}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledger::{tests::entry, LedgerEntry};
+
+    use super::*;
+
+    fn info() -> BuildInfo {
+        BuildInfo {
+            name: "app".to_string(),
+            bin: "app".to_string(),
+            version: Some("1.2.3".to_string()),
+            commit: Some("0123456789abcdef".to_string()),
+            dirty: Some(true),
+            profile: "release".to_string(),
+            features: vec!["tls".to_string()],
+            rustc: Some("rustc 1.70.0 (90c541806 2023-05-31)".to_string()),
+            built_at: "2023-05-08T12:34:56Z".to_string(),
+            source: "/work/app".to_string(),
+            branch: None,
+            env_preset: None,
+        }
+    }
+    #[test]
+    fn サイドカーを書いて読み戻す() {
+        let dir = tempfile::tempdir().unwrap();
+        info().save(dir.path()).unwrap();
+
+        assert!(dir.path().join("app.cw.json").is_file());
+        assert_eq!(BuildInfo::load(dir.path(), "app").unwrap(), info());
+        assert_eq!(
+            info().to_string(),
+            "\
+app 1.2.3
+  commit:   0123456789ab (dirty)
+  profile:  release
+  features: tls
+  rustc:    rustc 1.70.0 (90c541806 2023-05-31)
+  built:    2023-05-08T12:34:56Z
+  source:   /work/app
+"
+        );
+    }
+    #[test]
+    fn サイドカーがなければ台帳から答える() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ledger = Ledger::default();
+        ledger.record(LedgerEntry {
+            commit: Some("0123456789abcdef".to_string()),
+            ..entry("old", "/work/old")
+        });
+        ledger.save(dir.path()).unwrap();
+
+        let info = BuildInfo::load(dir.path(), "old").unwrap();
+
+        assert_eq!(info.commit.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(info.version, None);
+        assert_eq!(info.built_at, "2023-05-08T12:34:56Z");
+        assert!(BuildInfo::load(dir.path(), "unknown").is_err());
+    }
+}
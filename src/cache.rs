@@ -0,0 +1,460 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::BuiltBinary,
+    checksum::{sha256_file, sha256_hex},
+    config::CacheConfig,
+    history::format_size,
+    lock::InstallLock,
+    xdg,
+};
+
+/// What a cached build is looked up by.
+///
+/// Besides the project, commit, profile, features and target, everything else that changes
+/// the output of `cargo build` is part of it: the selected bins, the env preset and `RUSTFLAGS`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// Canonical path of the project directory.
+    pub project: String,
+    pub commit: String,
+    pub profile: String,
+    pub features: Vec<String>,
+    pub bins: Vec<String>,
+    /// Host triple of the compiler.
+    pub target: String,
+    /// e.g. `rustc 1.70.0 (90c541806 2023-05-31)`, so that a toolchain update rebuilds.
+    pub rustc: String,
+    pub env_preset: Option<String>,
+    pub rustflags: Option<String>,
+}
+impl CacheKey {
+    /// The order features and bins were given in does not matter.
+    fn normalized(&self) -> Self {
+        let mut key = self.clone();
+        key.features.sort();
+        key.bins.sort();
+        key
+    }
+    fn id(&self) -> String {
+        sha256_hex(&serde_json::to_vec(&self.normalized()).unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    /// Seconds since the epoch; `gc` removes the least recently used entries first.
+    last_used: u64,
+    binaries: Vec<CachedBinary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedBinary {
+    name: String,
+    bin: String,
+    sha256: String,
+    size: u64,
+    #[serde(default)]
+    version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed_entries: usize,
+    pub freed: u64,
+    pub size: u64,
+}
+impl std::fmt::Display for GcReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "removed {} cached builds, freed {}, {} left",
+            self.removed_entries,
+            format_size(self.freed),
+            format_size(self.size)
+        )
+    }
+}
+
+/// Built binaries by the commit they were built from, so that a clean checkout of a commit
+/// built before reinstalls without running cargo.
+///
+/// Binaries are stored once by their SHA-256 in `blobs/`; `entries/` maps each key to them.
+/// Every access holds the same flock as installs (`.cw/lock` in the cache directory), so a
+/// `gc` never removes what a concurrent `put` is writing.
+#[derive(Debug, Clone)]
+pub struct ArtifactCache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+}
+
+impl ArtifactCache {
+    const BLOBS_DIR: &'static str = "blobs";
+    const ENTRIES_DIR: &'static str = "entries";
+    const STAGING_SUFFIX: &'static str = ".cw-tmp";
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_size: None,
+        }
+    }
+    /// `$XDG_CACHE_HOME/cw/artifacts` unless `dir` is set; `None` when disabled.
+    pub fn from_config(config: &CacheConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        config
+            .dir
+            .clone()
+            .or_else(|| xdg::cache_dir().map(|dir| dir.join("artifacts")))
+            .map(|dir| Self {
+                max_size: config.max_size,
+                ..Self::new(dir)
+            })
+    }
+    /// The cached binaries of `key`, at their paths in the store.
+    ///
+    /// A blob that is missing or no longer has its checksum makes the whole entry a miss.
+    pub fn get(
+        &self,
+        key: &CacheKey,
+    ) -> Result<Option<Vec<BuiltBinary>>, Box<dyn std::error::Error>> {
+        let path = self.entry_path(&key.id());
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let _lock = InstallLock::acquire(&self.dir, true)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut entry = serde_json::from_str::<CacheEntry>(&std::fs::read_to_string(&path)?)?;
+        if entry.key != key.normalized() {
+            return Ok(None);
+        }
+        let mut binaries = Vec::new();
+        for binary in &entry.binaries {
+            let blob = self.blob_path(&binary.sha256);
+            if !blob.is_file() || sha256_file(&blob)? != binary.sha256 {
+                return Ok(None);
+            }
+            binaries.push(BuiltBinary {
+                name: binary.name.clone(),
+                bin: binary.bin.clone(),
+                path: blob,
+                size: binary.size,
+                version: binary.version.clone(),
+            });
+        }
+        entry.last_used = now();
+        write_atomic(&path, &serde_json::to_vec_pretty(&entry)?)?;
+        Ok(Some(binaries))
+    }
+    /// Stores the binaries of a build, then shrinks the store to `max_size`.
+    pub fn put(
+        &self,
+        key: &CacheKey,
+        binaries: &[BuiltBinary],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let _lock = InstallLock::acquire(&self.dir, true)?;
+        std::fs::create_dir_all(self.dir.join(Self::BLOBS_DIR))?;
+        std::fs::create_dir_all(self.dir.join(Self::ENTRIES_DIR))?;
+        let mut cached = Vec::new();
+        for binary in binaries {
+            let sha256 = sha256_file(&binary.path)?;
+            let blob = self.blob_path(&sha256);
+            if !blob.is_file() {
+                let staging = staging_path(&blob);
+                std::fs::copy(&binary.path, &staging)?;
+                std::fs::rename(&staging, &blob)?;
+            }
+            cached.push(CachedBinary {
+                name: binary.name.clone(),
+                bin: binary.bin.clone(),
+                size: blob.metadata()?.len(),
+                sha256,
+                version: binary.version.clone(),
+            });
+        }
+        let entry = CacheEntry {
+            key: key.normalized(),
+            last_used: now(),
+            binaries: cached,
+        };
+        write_atomic(
+            &self.entry_path(&key.id()),
+            &serde_json::to_vec_pretty(&entry)?,
+        )?;
+        if let Some(max_size) = self.max_size {
+            self.gc_locked(max_size)?;
+        }
+        Ok(())
+    }
+    /// Removes the least recently used builds until the blobs they use fit in `max_size`,
+    /// then every blob no build uses.
+    pub fn gc(&self, max_size: u64) -> Result<GcReport, Box<dyn std::error::Error>> {
+        let _lock = InstallLock::acquire(&self.dir, true)?;
+        self.gc_locked(max_size)
+    }
+    fn gc_locked(&self, max_size: u64) -> Result<GcReport, Box<dyn std::error::Error>> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+        let mut kept = BTreeMap::new();
+        let mut removed_entries = 0;
+        for (path, entry) in entries {
+            let mut with_entry = kept.clone();
+            for binary in &entry.binaries {
+                with_entry.insert(binary.sha256.clone(), binary.size);
+            }
+            if removed_entries == 0 && with_entry.values().sum::<u64>() <= max_size {
+                kept = with_entry;
+            } else {
+                std::fs::remove_file(path)?;
+                removed_entries += 1;
+            }
+        }
+        let mut freed = 0;
+        let mut size = 0;
+        let blobs_dir = self.dir.join(Self::BLOBS_DIR);
+        if blobs_dir.is_dir() {
+            for blob in std::fs::read_dir(blobs_dir)? {
+                let blob = blob?;
+                if is_staging(&blob.path()) {
+                    continue;
+                }
+                let len = blob.metadata()?.len();
+                if kept.contains_key(blob.file_name().to_string_lossy().as_ref()) {
+                    size += len;
+                } else {
+                    std::fs::remove_file(blob.path())?;
+                    freed += len;
+                }
+            }
+        }
+        Ok(GcReport {
+            removed_entries,
+            freed,
+            size,
+        })
+    }
+    fn entries(&self) -> Result<Vec<(PathBuf, CacheEntry)>, Box<dyn std::error::Error>> {
+        let dir = self.dir.join(Self::ENTRIES_DIR);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        let mut unreadable = Vec::new();
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            if is_staging(&path) {
+                continue;
+            }
+            match std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+            {
+                Some(entry) => entries.push((path, entry)),
+                None => unreadable.push(path),
+            }
+        }
+        for path in unreadable {
+            std::fs::remove_file(path)?;
+        }
+        Ok(entries)
+    }
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir
+            .join(Self::ENTRIES_DIR)
+            .join(format!("{}.json", id))
+    }
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(Self::BLOBS_DIR).join(sha256)
+    }
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(ArtifactCache::STAGING_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// A copy `put` has not renamed into place yet.
+fn is_staging(path: &Path) -> bool {
+    path.to_string_lossy()
+        .ends_with(ArtifactCache::STAGING_SUFFIX)
+}
+
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let staging = staging_path(path);
+    std::fs::write(&staging, content)?;
+    std::fs::rename(staging, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::commands::write_file;
+
+    use super::*;
+
+    pub fn key(commit: &str) -> CacheKey {
+        CacheKey {
+            project: "/work/app".to_string(),
+            commit: commit.to_string(),
+            profile: "release".to_string(),
+            features: vec!["b".to_string(), "a".to_string()],
+            bins: Vec::new(),
+            target: "x86_64-unknown-linux-gnu".to_string(),
+            rustc: "rustc 1.70.0 (90c541806 2023-05-31)".to_string(),
+            env_preset: None,
+            rustflags: None,
+        }
+    }
+    fn built(dir: &Path, name: &str, content: &str) -> BuiltBinary {
+        let path = dir.join(name);
+        write_file(&path, content).unwrap();
+        BuiltBinary {
+            name: name.to_string(),
+            bin: name.to_string(),
+            size: content.len() as u64,
+            path,
+            version: Some("0.1.0".to_string()),
+        }
+    }
+    #[test]
+    fn ビルドを保存し同じキーで取り出せる() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let cache = ArtifactCache::new(dir.path());
+        let binaries = vec![
+            built(target.path(), "app", "binary"),
+            built(target.path(), "helper", "helper"),
+        ];
+
+        assert_eq!(cache.get(&key("aaaa")).unwrap(), None);
+        cache.put(&key("aaaa"), &binaries).unwrap();
+        cache.put(&key("bbbb"), &binaries[..1]).unwrap();
+
+        let cached = cache.get(&key("aaaa")).unwrap().unwrap();
+        assert_eq!(
+            cached
+                .iter()
+                .map(|binary| (
+                    binary.name.as_str(),
+                    std::fs::read_to_string(&binary.path).unwrap()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("app", "binary".to_string()),
+                ("helper", "helper".to_string())
+            ]
+        );
+        assert_eq!(
+            cached[0].path,
+            dir.path().join("blobs").join(sha256_hex(b"binary"))
+        );
+        let reordered = CacheKey {
+            features: vec!["a".to_string(), "b".to_string()],
+            ..key("aaaa")
+        };
+        assert!(cache.get(&reordered).unwrap().is_some());
+        let other_target = CacheKey {
+            target: "aarch64-unknown-linux-gnu".to_string(),
+            ..key("aaaa")
+        };
+        assert_eq!(cache.get(&other_target).unwrap(), None);
+        let other_rustc = CacheKey {
+            rustc: "rustc 1.71.0 (8ede3aae2 2023-07-12)".to_string(),
+            ..key("aaaa")
+        };
+        assert_eq!(cache.get(&other_rustc).unwrap(), None);
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("blobs")).unwrap().count(),
+            2
+        );
+
+        write_file(cached[1].path.clone(), "corrupted").unwrap();
+        assert_eq!(cache.get(&key("aaaa")).unwrap(), None);
+    }
+    #[test]
+    fn 古いものから消して大きさを抑える() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let cache = ArtifactCache::new(dir.path());
+        let mut last_used = 0;
+        for (commit, content) in [
+            ("old", "0123456789"),
+            ("mid", "abcdefghij"),
+            ("new", "ABCDEFGHIJ"),
+        ] {
+            cache
+                .put(&key(commit), &[built(target.path(), "app", content)])
+                .unwrap();
+            let path = cache.entry_path(&key(commit).id());
+            let mut entry: CacheEntry =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            last_used += 1;
+            entry.last_used = last_used;
+            std::fs::write(&path, serde_json::to_vec(&entry).unwrap()).unwrap();
+        }
+
+        let report = cache.gc(25).unwrap();
+
+        assert_eq!(
+            report,
+            GcReport {
+                removed_entries: 1,
+                freed: 10,
+                size: 20
+            }
+        );
+        assert_eq!(cache.get(&key("old")).unwrap(), None);
+        assert!(cache.get(&key("mid")).unwrap().is_some());
+        assert!(cache.get(&key("new")).unwrap().is_some());
+        assert_eq!(cache.gc(0).unwrap().removed_entries, 2);
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("blobs")).unwrap().count(),
+            0
+        );
+    }
+    #[test]
+    fn gcはロックを待ち書き込み中のファイルを消さない() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let cache = ArtifactCache::new(dir.path());
+        cache
+            .put(&key("old"), &[built(target.path(), "app", "0123456789")])
+            .unwrap();
+        let blob_staging = dir.path().join("blobs/0123.cw-tmp");
+        let entry_staging = dir.path().join("entries/0123.json.cw-tmp");
+        write_file(&blob_staging, "partial").unwrap();
+        write_file(&entry_staging, "{").unwrap();
+        let lock = InstallLock::acquire(dir.path(), false).unwrap();
+
+        let gc = {
+            let cache = cache.clone();
+            std::thread::spawn(move || cache.gc(0).unwrap())
+        };
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        assert!(cache.entry_path(&key("old").id()).is_file());
+        drop(lock);
+        let report = gc.join().unwrap();
+
+        assert_eq!(report.removed_entries, 1);
+        assert!(blob_staging.is_file());
+        assert!(entry_staging.is_file());
+        assert!(!cache.entry_path(&key("old").id()).exists());
+    }
+}
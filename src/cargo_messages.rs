@@ -0,0 +1,241 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// One line of `cargo build --message-format=json-diagnostic-rendered-ansi`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum CargoMessage {
+    CompilerArtifact(Artifact),
+    CompilerMessage(CompilerMessage),
+    BuildFinished {
+        success: bool,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Artifact {
+    pub package_id: String,
+    pub target: Target,
+    pub executable: Option<PathBuf>,
+    pub fresh: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CompilerMessage {
+    pub package_id: String,
+    pub target: Target,
+    pub message: Diagnostic,
+}
+
+/// A rustc diagnostic, see rustc's `--error-format=json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub code: Option<DiagnosticCode>,
+    /// `"error"`, `"warning"`, `"note"`, `"failure-note"`, ...
+    pub level: String,
+    pub spans: Vec<DiagnosticSpan>,
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DiagnosticCode {
+    /// Lint name such as `unused_variables`, or an error code such as `E0308`.
+    pub code: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DiagnosticSpan {
+    /// Relative to the workspace root.
+    pub file_name: String,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub is_primary: bool,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.level.starts_with("error")
+    }
+    pub fn is_warning(&self) -> bool {
+        self.level == "warning"
+    }
+    pub fn primary_span(&self) -> Option<&DiagnosticSpan> {
+        self.spans.iter().find(|span| span.is_primary)
+    }
+}
+
+impl Artifact {
+    pub fn bin_executable(&self) -> Option<&PathBuf> {
+        if self.target.kind.iter().any(|kind| kind == "bin") {
+            self.executable.as_ref()
+        } else {
+            None
+        }
+    }
+    /// Version of the package, from either form of the package id:
+    /// `app 0.1.0 (path+file:///app)` or `path+file:///app#0.1.0` (`#app@0.1.0` when renamed).
+    pub fn package_version(&self) -> Option<&str> {
+        match self.package_id.rsplit_once('#') {
+            Some((_, fragment)) => Some(fragment.rsplit_once('@').map_or(fragment, |(_, v)| v)),
+            None => self.package_id.split_whitespace().nth(1),
+        }
+    }
+}
+
+/// Lines that are not cargo messages (e.g. output of build scripts) are skipped.
+pub fn parse_messages(stdout: &str) -> Vec<CargoMessage> {
+    stdout
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+pub fn artifacts(messages: &[CargoMessage]) -> impl Iterator<Item = &Artifact> {
+    messages.iter().filter_map(|message| match message {
+        CargoMessage::CompilerArtifact(artifact) => Some(artifact),
+        _ => None,
+    })
+}
+
+pub fn compiler_messages(messages: &[CargoMessage]) -> impl Iterator<Item = &CompilerMessage> {
+    messages.iter().filter_map(|message| match message {
+        CargoMessage::CompilerMessage(message) => Some(message),
+        _ => None,
+    })
+}
+
+/// The text rustc would have printed for a `compiler-message` line, `None` for other lines.
+///
+/// Used to echo diagnostics while cargo's JSON output itself stays quiet.
+pub fn render_diagnostic(line: &str) -> Option<String> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    match serde_json::from_str(line).ok()? {
+        CargoMessage::CompilerMessage(message) => message.message.rendered,
+        _ => None,
+    }
+}
+
+/// Number of distinct packages that were actually compiled rather than fresh.
+pub fn compiled_crate_count(messages: &[CargoMessage]) -> usize {
+    let mut packages = artifacts(messages)
+        .filter(|artifact| !artifact.fresh)
+        .map(|artifact| artifact.package_id.as_str())
+        .collect::<Vec<_>>();
+    packages.sort();
+    packages.dedup();
+    packages.len()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn artifact_json(name: &str, kind: &str, executable: Option<&str>, fresh: bool) -> String {
+        format!(
+            r#"{{"reason":"compiler-artifact","package_id":"{name} 0.1.0 (path+file:///{name})","manifest_path":"/{name}/Cargo.toml","target":{{"kind":["{kind}"],"crate_types":["{kind}"],"name":"{name}","src_path":"/{name}/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true}},"profile":{{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false}},"features":[],"filenames":[],"executable":{executable},"fresh":{fresh}}}"#,
+            executable = executable
+                .map(|path| format!("\"{}\"", path))
+                .unwrap_or_else(|| "null".to_string()),
+        )
+    }
+
+    pub fn compiler_message_json(
+        crate_name: &str,
+        level: &str,
+        code: Option<&str>,
+        file: &str,
+        line: usize,
+    ) -> String {
+        format!(
+            r#"{{"reason":"compiler-message","package_id":"path+file:///{crate_name}#0.1.0","manifest_path":"/{crate_name}/Cargo.toml","target":{{"kind":["bin"],"crate_types":["bin"],"name":"{crate_name}","src_path":"/{crate_name}/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true}},"message":{{"$message_type":"diagnostic","message":"something is wrong","code":{code},"level":"{level}","spans":[{{"file_name":"{file}","byte_start":0,"byte_end":1,"line_start":{line},"line_end":{line},"column_start":5,"column_end":6,"is_primary":true,"text":[],"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}}],"children":[],"rendered":"{level}: something is wrong\n"}}}}"#,
+            code = code
+                .map(|code| format!(r#"{{"code":"{}","explanation":null}}"#, code))
+                .unwrap_or_else(|| "null".to_string()),
+        )
+    }
+
+    #[test]
+    fn cargoのjsonメッセージを解釈できる() {
+        let stdout = [
+            artifact_json("dep", "lib", None, true),
+            artifact_json("app", "bin", Some("/app/target/debug/app"), false),
+            r#"{"reason":"build-script-executed","package_id":"x"}"#.to_string(),
+            "not json".to_string(),
+            r#"{"reason":"build-finished","success":true}"#.to_string(),
+        ]
+        .join("\n");
+
+        let messages = parse_messages(&stdout);
+
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[2], CargoMessage::Other);
+        assert_eq!(messages[3], CargoMessage::BuildFinished { success: true });
+        let executables = artifacts(&messages)
+            .filter_map(|artifact| artifact.bin_executable())
+            .collect::<Vec<_>>();
+        assert_eq!(executables, vec![&PathBuf::from("/app/target/debug/app")]);
+        assert_eq!(compiled_crate_count(&messages), 1);
+    }
+    #[test]
+    fn パッケージidからバージョンを取り出す() {
+        let artifact = |package_id: &str| Artifact {
+            package_id: package_id.to_string(),
+            target: Target {
+                name: "app".to_string(),
+                kind: vec!["bin".to_string()],
+            },
+            executable: None,
+            fresh: false,
+        };
+        assert_eq!(
+            artifact("app 0.1.0 (path+file:///app)").package_version(),
+            Some("0.1.0")
+        );
+        assert_eq!(
+            artifact("path+file:///work/app#1.2.3").package_version(),
+            Some("1.2.3")
+        );
+        assert_eq!(
+            artifact("path+file:///work/dir#app@1.2.3-rc.1").package_version(),
+            Some("1.2.3-rc.1")
+        );
+    }
+    #[test]
+    fn コンパイラの診断を解釈し表示用の文字列を取り出せる() {
+        let line =
+            compiler_message_json("app", "warning", Some("unused_variables"), "src/main.rs", 3);
+
+        let messages = parse_messages(&line);
+        let message = compiler_messages(&messages).next().unwrap();
+        assert_eq!(message.target.name, "app");
+        assert!(message.message.is_warning());
+        assert_eq!(
+            message.message.code.as_ref().map(|code| code.code.as_str()),
+            Some("unused_variables")
+        );
+        assert_eq!(message.message.primary_span().unwrap().line_start, 3);
+
+        assert_eq!(
+            render_diagnostic(&line),
+            Some("warning: something is wrong\n".to_string())
+        );
+        assert_eq!(
+            render_diagnostic(&artifact_json("app", "bin", None, false)),
+            None
+        );
+        assert_eq!(render_diagnostic("Compiling app"), None);
+    }
+}
@@ -41,7 +41,7 @@ edition = "{}"
 
 [dependencies]{}"#,
             self.name,
-            self.edition.into_str(),
+            self.edition.as_str(),
             self.gen_depends()
         );
         expected
@@ -79,7 +79,7 @@ enum RustEdition {
     V2015,
 }
 impl RustEdition {
-    fn into_str(&self) -> &'static str {
+    fn as_str(&self) -> &'static str {
         match self {
             Self::V2015 => "2015",
             Self::V2018 => "2018",
@@ -97,7 +97,7 @@ impl CargoDepend {
         }
     }
     fn gen_statement(&self) -> String {
-        if self.attr.len() == 0 {
+        if self.attr.is_empty() {
             format!(r#"{} = "{}""#, self.name, self.version)
         } else {
             let features = self.attr.iter().fold(
@@ -108,20 +108,20 @@ impl CargoDepend {
         }
     }
     fn add_attr(&mut self, name: impl Into<String>, value: impl IntoAttrStr) {
-        self.attr.insert(name.into(), value.into_str());
+        self.attr.insert(name.into(), value.to_attr_str());
     }
 }
 
 pub trait IntoAttrStr {
-    fn into_str(&self) -> String;
+    fn to_attr_str(&self) -> String;
 }
 impl IntoAttrStr for &str {
-    fn into_str(&self) -> String {
+    fn to_attr_str(&self) -> String {
         self.to_string()
     }
 }
 impl IntoAttrStr for Vec<&str> {
-    fn into_str(&self) -> String {
+    fn to_attr_str(&self) -> String {
         let inner = self
             .iter()
             .map(|s| format!(r#""{}""#, s))
@@ -0,0 +1,73 @@
+use std::{io::Read, path::Path};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// Lower case hex SHA-256, as printed by `sha256sum`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Lower case hex MD5, only for formats that insist on it such as Debian's `md5sums`.
+pub fn md5_hex(bytes: &[u8]) -> String {
+    hex(&Md5::digest(bytes))
+}
+
+pub fn sha256_file(path: impl AsRef<Path>) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// One `<hash>  <name>` line of a `SHA256SUMS` file.
+pub fn sums_line(hash: &str, name: &str) -> String {
+    format!("{}  {}\n", hash, name)
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::write_file;
+
+    use super::*;
+    #[test]
+    fn sha256を16進数で計算する() {
+        let expected = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_hex(b"abc"), expected);
+
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path().join("abc"), "abc").unwrap();
+        assert_eq!(sha256_file(dir.path().join("abc")).unwrap(), expected);
+    }
+    #[test]
+    fn 十六進数を読み戻せる() {
+        assert_eq!(parse_hex(&hex(&[0, 15, 255])), Some(vec![0, 15, 255]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+    #[test]
+    fn md5を16進数で計算する() {
+        assert_eq!(md5_hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+    }
+}
@@ -1,79 +1,146 @@
 use std::{
     fs::File,
-    io::{BufRead, BufReader, Write},
+    io::Write,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 
-pub fn run_command(program: &str, commands: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
-    let mut cmd = Command::new(program);
-    cmd.args(commands);
+use crate::glob::{Glob, IgnoreRule};
 
-    let child = cmd
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("failed to execute child process");
-
-    if let Some(stdout) = child.stdout {
-        let stdout_reader = BufReader::new(stdout);
-        for line in stdout_reader.lines() {
-            println!("{}", line?);
-        }
-    };
-    if let Some(stderr) = child.stderr {
-        let stderr_reader = BufReader::new(stderr);
-
-        for line in stderr_reader.lines() {
-            println!("{}", line?);
-        }
-    };
+/// Lists the files directly under `dir` in file name order.
+pub fn ls_files(dir: impl AsRef<Path>) -> std::io::Result<Vec<PathBuf>> {
+    FileWalker::new(dir).walk()
+}
 
-    Ok(())
+#[derive(Debug, Clone)]
+pub struct FileWalker {
+    root: PathBuf,
+    max_depth: Option<usize>,
+    gitignore: bool,
+    ignore_rules: Vec<IgnoreRule>,
+    globs: Vec<Glob>,
 }
 
-pub fn ls_files(dir: impl AsRef<Path>) -> Vec<PathBuf> {
-    match std::fs::read_dir(dir.as_ref()) {
-        Ok(root_dir) => root_dir
-            .filter_map(|entry| entry.ok())
-            .filter_map(|entry| match entry.file_type() {
-                Ok(file_type) => Some((file_type, entry.path())),
-                Err(_) => None,
-            })
-            .fold(Vec::new(), |mut acc, (file_type, path)| {
-                if file_type.is_dir() {
-                    return acc;
+impl FileWalker {
+    const GITIGNORE: &'static str = ".gitignore";
+    /// Walks only the files directly under `root` until `max_depth` or `recursive` is set.
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            max_depth: Some(0),
+            gitignore: false,
+            ignore_rules: Vec::new(),
+            globs: Vec::new(),
+        }
+    }
+    /// `0` lists only `root` itself, `1` also lists its sub directories and so on.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+    pub fn recursive(mut self) -> Self {
+        self.max_depth = None;
+        self
+    }
+    /// Skips `.git` and honors every `.gitignore` found while walking.
+    pub fn respect_gitignore(mut self) -> Self {
+        self.gitignore = true;
+        self
+    }
+    /// Adds a `.gitignore` style pattern evaluated relative to `root`.
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.ignore_rules.extend(IgnoreRule::parse(pattern));
+        self
+    }
+    /// Keeps only files matching one of the globs.
+    /// A glob without `/` is matched against the file name, otherwise against the path relative to `root`.
+    pub fn glob(mut self, pattern: &str) -> Self {
+        self.globs.push(Glob::new(pattern));
+        self
+    }
+    pub fn walk(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let root_rules = vec![(PathBuf::new(), self.ignore_rules.clone())];
+        self.walk_dir(&self.root, Path::new(""), 0, &root_rules, &mut files)?;
+        Ok(files)
+    }
+    fn walk_dir(
+        &self,
+        dir: &Path,
+        relative: &Path,
+        depth: usize,
+        rules: &[(PathBuf, Vec<IgnoreRule>)],
+        files: &mut Vec<PathBuf>,
+    ) -> std::io::Result<()> {
+        let mut rules = rules.to_vec();
+        if self.gitignore {
+            let gitignore = dir.join(Self::GITIGNORE);
+            if gitignore.is_file() {
+                let content = std::fs::read_to_string(gitignore)?;
+                rules.push((
+                    relative.to_path_buf(),
+                    content.lines().filter_map(IgnoreRule::parse).collect(),
+                ));
+            }
+        }
+        let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let relative = relative.join(entry.file_name());
+            let is_dir = entry.file_type()?.is_dir();
+            if self.gitignore && is_dir && entry.file_name() == ".git" {
+                continue;
+            }
+            if Self::is_ignored(&rules, &relative, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if self.max_depth.is_none_or(|max| depth < max) {
+                    self.walk_dir(&path, &relative, depth + 1, &rules, files)?;
                 }
-                acc.push(path);
-                acc
-            }),
-        Err(e) => {
-            println!("{}", e.to_string());
-            panic!("not found path = {:?}", dir.as_ref())
+                continue;
+            }
+            if self.is_selected(&relative) {
+                files.push(path);
+            }
         }
+        Ok(())
     }
-}
-
-pub fn get_exe_filepaths(dir: &str) -> Vec<PathBuf> {
-    #[cfg(not(target_os = "windows"))]
-    fn is_exe_file(path: &PathBuf) -> bool {
-        let Some(Some(path))= path.file_name().map(|f|f.to_str()) else {
-                return false
+    fn is_ignored(rules: &[(PathBuf, Vec<IgnoreRule>)], relative: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for (base, rules) in rules {
+            let Ok(path) = relative.strip_prefix(base) else {
+                continue;
             };
-        !path.contains(".")
+            for rule in rules.iter().filter(|rule| rule.is_match(path, is_dir)) {
+                ignored = !rule.is_negated();
+            }
+        }
+        ignored
     }
-    #[cfg(target_os = "windows")]
-    fn is_exe_file(path: &PathBuf) -> bool {
-        path.extension()
-            .map(|extension| extension.to_str().map(|extension| extension))
-            == Some(Some("exe"))
+    fn is_selected(&self, relative: &Path) -> bool {
+        if self.globs.is_empty() {
+            return true;
+        }
+        self.globs.iter().any(|glob| {
+            if glob.has_dir_separator() {
+                glob.is_match(relative)
+            } else {
+                relative
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| glob.is_match_name(name))
+                    .unwrap_or(false)
+            }
+        })
     }
-    ls_files(dir).into_iter().filter(is_exe_file).collect()
-}
-pub fn cp(from: &str, to: &str) -> Result<(), Box<dyn std::error::Error>> {
-    run_command("cp", &["-r", from, to])
 }
+
+/// Creates the parent directories of `path` when they are missing.
 pub fn write_file(path: impl AsRef<Path>, content: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.as_ref().parent() {
+        std::fs::create_dir_all(parent)?;
+    }
     let mut file = File::create(path)?;
     file.write_all(content.as_bytes())?;
     Ok(())
@@ -84,24 +151,90 @@ mod tests {
     use super::*;
     #[test]
     fn for_testからファイルのパスをすべて取得する() {
-        let exes = ls_files("for-test");
-        assert_eq!(exes.len(), 2);
+        let exes = ls_files("for-test").unwrap();
         assert_eq!(
-            exes[0].as_path().as_os_str().to_str().unwrap(),
-            "for-test/test.txt"
+            exes,
+            vec![
+                PathBuf::from("for-test/exe"),
+                PathBuf::from("for-test/test.txt")
+            ]
+        );
+    }
+    #[test]
+    fn 存在しないディレクトリはエラーを返す() {
+        assert!(ls_files("for-test/not-found").is_err());
+    }
+    /// A project tree with nested directories, `.gitignore`s and a `.git` directory.
+    fn tree() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, content) in [
+            ("src/lib.rs", ""),
+            ("src/new/cmd.rs", ""),
+            ("src/new/deep/mod.rs", ""),
+            ("src/new/notes.txt", ""),
+            ("target/debug/build.rs", ""),
+            ("for-test/exe.rs", ""),
+            (".git/hooks/pre-commit.rs", ""),
+            (".gitignore", "/target\n*.txt\n"),
+            ("src/new/.gitignore", "deep/\n"),
+        ] {
+            write_file(root.path().join(path), content).unwrap();
+        }
+        root
+    }
+    #[test]
+    fn 再帰的に深さを指定してファイルを取得する() {
+        let root = tree();
+        let src = root.path().join("src");
+
+        let all = FileWalker::new(&src).recursive().walk().unwrap();
+        assert_eq!(
+            all,
+            vec![
+                src.join("lib.rs"),
+                src.join("new/.gitignore"),
+                src.join("new/cmd.rs"),
+                src.join("new/deep/mod.rs"),
+                src.join("new/notes.txt"),
+            ]
         );
         assert_eq!(
-            exes[1].as_path().as_os_str().to_str().unwrap(),
-            "for-test/exe"
+            FileWalker::new(&src).walk().unwrap(),
+            vec![src.join("lib.rs")]
+        );
+        assert_eq!(
+            FileWalker::new(&src).max_depth(1).walk().unwrap(),
+            vec![
+                src.join("lib.rs"),
+                src.join("new/.gitignore"),
+                src.join("new/cmd.rs"),
+                src.join("new/notes.txt"),
+            ]
         );
     }
     #[test]
-    fn targetからexeファイルのパスのみ取得する() {
-        let exes = get_exe_filepaths("target/debug");
-        assert_eq!(exes.len(), 1);
+    fn globとignoreでファイルを絞り込む() {
+        let root = tree();
+        let root = root.path();
+
+        let files = FileWalker::new(root)
+            .recursive()
+            .respect_gitignore()
+            .ignore("/for-test/")
+            .glob("*.rs")
+            .walk()
+            .unwrap();
         assert_eq!(
-            exes[0].as_path().as_os_str().to_str().unwrap(),
-            "target/debug/cw"
+            files,
+            vec![root.join("src/lib.rs"), root.join("src/new/cmd.rs")]
         );
+
+        let files = FileWalker::new(root)
+            .recursive()
+            .ignore("target/")
+            .glob("src/new/*.rs")
+            .walk()
+            .unwrap();
+        assert_eq!(files, vec![root.join("src/new/cmd.rs")]);
     }
 }
@@ -0,0 +1,719 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{builder::PossibleValuesParser, Arg, ArgAction, Command};
+use serde::Deserialize;
+
+use crate::{
+    commands::write_file,
+    config::CompletionsConfig,
+    runner::{CommandLine, CommandRunner},
+    xdg,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+impl Shell {
+    pub const ALL: [Self; 3] = [Self::Bash, Self::Zsh, Self::Fish];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+        }
+    }
+    /// The name the shell looks the completion script of `bin` up by.
+    fn file_name(&self, bin: &str) -> String {
+        match self {
+            Self::Bash => bin.to_string(),
+            Self::Zsh => format!("_{}", bin),
+            Self::Fish => format!("{}.fish", bin),
+        }
+    }
+    /// Below `$XDG_DATA_HOME`, where bash-completion and fish look without any setup;
+    /// zsh needs the directory in its `fpath`.
+    fn default_dir(&self) -> Option<PathBuf> {
+        let dir = match self {
+            Self::Bash => "bash-completion/completions",
+            Self::Zsh => "zsh/site-functions",
+            Self::Fish => "fish/vendor_completions.d",
+        };
+        xdg::data_home().map(|home| home.join(dir))
+    }
+    /// Tells a completion script from whatever else a binary prints for unknown arguments.
+    fn is_script(&self, output: &str) -> bool {
+        match self {
+            Self::Bash => output.contains("complete "),
+            Self::Zsh => output.contains("#compdef"),
+            Self::Fish => output.contains("complete -c"),
+        }
+    }
+    fn generator(&self) -> clap_complete::Shell {
+        match self {
+            Self::Bash => clap_complete::Shell::Bash,
+            Self::Zsh => clap_complete::Shell::Zsh,
+            Self::Fish => clap_complete::Shell::Fish,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GenerateFailed {
+    /// Files written before the error, which are worth tracking all the same.
+    pub written: Vec<PathBuf>,
+    pub error: Box<dyn std::error::Error>,
+}
+impl Display for GenerateFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+impl std::error::Error for GenerateFailed {}
+
+/// Writes shell completion scripts and a man page for installed binaries.
+///
+/// A binary that prints them itself through the configured subcommands (`completions <shell>`
+/// and `man` by default, usually hidden) is trusted with it; for the others they are
+/// generated from what `--help` lists, which works for clap binaries. Subcommands are only
+/// tried when `--help` shows they cannot be taken for an argument, and every probe runs
+/// with stdin closed in an empty directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completions {
+    shells: Vec<Shell>,
+    command: Vec<String>,
+    man_command: Option<Vec<String>>,
+    bash_dir: Option<PathBuf>,
+    zsh_dir: Option<PathBuf>,
+    fish_dir: Option<PathBuf>,
+    man_dir: Option<PathBuf>,
+    timeout: Option<Duration>,
+}
+
+impl Completions {
+    /// How deep `--help` of subcommands is followed.
+    const MAX_DEPTH: usize = 2;
+    pub fn from_config(config: &CompletionsConfig) -> Self {
+        Self {
+            shells: config.shells.clone(),
+            command: config.command.clone(),
+            man_command: config.man.then(|| config.man_command.clone()),
+            bash_dir: config.bash_dir.clone(),
+            zsh_dir: config.zsh_dir.clone(),
+            fish_dir: config.fish_dir.clone(),
+            man_dir: config.man_dir.clone(),
+            timeout: config.timeout,
+        }
+    }
+    /// Writes the scripts and the man page of `binary`, installed as `name`, and returns
+    /// their paths. On failure the error still carries the files written before it.
+    pub fn generate(
+        &self,
+        runner: &impl CommandRunner,
+        binary: &Path,
+        name: &str,
+    ) -> Result<Vec<PathBuf>, GenerateFailed> {
+        let mut written = Vec::new();
+        match self.write_all(runner, binary, name, &mut written) {
+            Ok(()) => Ok(written),
+            Err(error) => Err(GenerateFailed { written, error }),
+        }
+    }
+    fn write_all(
+        &self,
+        runner: &impl CommandRunner,
+        binary: &Path,
+        name: &str,
+        written: &mut Vec<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let probe = Probe {
+            runner,
+            binary,
+            dir: tempfile::tempdir()?,
+            timeout: self.timeout,
+        };
+        let page = probe.help_page(&[]);
+        let accepts = |command: &[String]| {
+            let first = command.first();
+            page.as_ref()
+                .zip(first)
+                .is_some_and(|(page, first)| page.accepts_subcommand(first))
+        };
+        let from_help = || -> Result<Command, Box<dyn std::error::Error>> {
+            let page = page.as_ref().ok_or_else(|| {
+                format!(
+                    "{} has no completions subcommand and its --help is not clap's",
+                    binary.display()
+                )
+            })?;
+            Ok(probe.help_command(name, &[], page))
+        };
+        let mut from_help_command = None;
+        for shell in &self.shells {
+            let args = self
+                .command
+                .iter()
+                .map(|arg| arg.replace("{shell}", shell.name()))
+                .collect::<Vec<_>>();
+            let script = match accepts(&args)
+                .then(|| probe.print(args))
+                .flatten()
+                .filter(|output| shell.is_script(output))
+            {
+                Some(script) => script,
+                None => {
+                    let mut command = match from_help_command.take() {
+                        Some(command) => command,
+                        None => from_help()?,
+                    };
+                    let mut script = Vec::new();
+                    clap_complete::generate(shell.generator(), &mut command, name, &mut script);
+                    from_help_command = Some(command);
+                    String::from_utf8(script)?
+                }
+            };
+            let dir = match shell {
+                Shell::Bash => self.bash_dir.clone(),
+                Shell::Zsh => self.zsh_dir.clone(),
+                Shell::Fish => self.fish_dir.clone(),
+            };
+            let path = dir
+                .or_else(|| shell.default_dir())
+                .ok_or("could not find the data directory for completions")?
+                .join(shell.file_name(name));
+            write_file(&path, &script)?;
+            written.push(path);
+        }
+        if let Some(man_command) = &self.man_command {
+            let page = match accepts(man_command)
+                .then(|| probe.print(man_command.iter().cloned()))
+                .flatten()
+                .filter(|output| output.contains(".TH"))
+            {
+                Some(page) => page,
+                None => {
+                    let command = match from_help_command {
+                        Some(command) => command,
+                        None => from_help()?,
+                    };
+                    let mut page = Vec::new();
+                    clap_mangen::Man::new(command).render(&mut page)?;
+                    String::from_utf8(page)?
+                }
+            };
+            let path = self
+                .man_dir
+                .clone()
+                .or_else(|| xdg::data_home().map(|home| home.join("man/man1")))
+                .ok_or("could not find the data directory for man pages")?
+                .join(format!("{}.1", name));
+            write_file(&path, &page)?;
+            written.push(path);
+        }
+        Ok(())
+    }
+}
+
+/// Runs the binary to learn about it, with stdin closed and an empty working directory so
+/// that it has nothing to read or to overwrite.
+struct Probe<'a, R> {
+    runner: &'a R,
+    binary: &'a Path,
+    dir: tempfile::TempDir,
+    timeout: Option<Duration>,
+}
+impl<R: CommandRunner> Probe<'_, R> {
+    /// What the binary prints with `args`, when it exits successfully.
+    fn print<I, S>(&self, args: I) -> Option<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let command = CommandLine::new(self.binary.display().to_string())
+            .args(args)
+            .current_dir(self.dir.path())
+            .null_stdin()
+            .quiet_stdout()
+            .quiet_stderr()
+            .timeout(self.timeout);
+        self.runner
+            .run(&command)
+            .ok()
+            .filter(|output| output.success())
+            .map(|output| output.stdout)
+    }
+    /// `--help` of the binary or one of its subcommands, `None` when it is not clap's.
+    fn help_page(&self, subcommands: &[&str]) -> Option<HelpPage> {
+        let args = subcommands.iter().copied().chain(["--help"]);
+        self.print(args).and_then(|help| HelpPage::parse(&help))
+    }
+    /// The command line of the binary as `page` and the `--help` of its subcommands describe it.
+    fn help_command(&self, name: &str, subcommands: &[&str], page: &HelpPage) -> Command {
+        let mut command = page.command(name);
+        if subcommands.is_empty() {
+            // `app 1.2.3`, shown in the header of the man page.
+            let version = self
+                .print(["--version"])
+                .and_then(|output| output.split_whitespace().nth(1).map(String::from));
+            if let Some(version) = version {
+                command = command.version(version);
+            }
+        }
+        for (subcommand, about) in &page.subcommands {
+            let path = subcommands
+                .iter()
+                .copied()
+                .chain([subcommand.as_str()])
+                .collect::<Vec<_>>();
+            let described = (subcommand != "help" && path.len() <= Completions::MAX_DEPTH)
+                .then(|| self.help_page(&path))
+                .flatten()
+                .map(|page| self.help_command(subcommand, &path, &page))
+                .unwrap_or_else(|| Command::new(subcommand.clone()));
+            command = command.subcommand(described.about(about.clone()));
+        }
+        command
+    }
+}
+
+/// What the `--help` of a clap binary lists, in the format of clap 3 or 4.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct HelpPage {
+    about: Option<String>,
+    options: Vec<HelpOption>,
+    positionals: Vec<HelpPositional>,
+    /// Names and descriptions.
+    subcommands: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct HelpOption {
+    short: Option<char>,
+    long: Option<String>,
+    value_name: Option<String>,
+    /// `-v...` or `--bin <BIN>...`
+    multiple: bool,
+    help: String,
+    possible_values: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HelpPositional {
+    name: String,
+    required: bool,
+    multiple: bool,
+    help: String,
+}
+
+impl HelpPage {
+    /// Whether `word` as the first argument would run a subcommand: one that is listed, or
+    /// any when there are neither subcommands nor positionals to take it instead, since
+    /// clap rejects unknown arguments but hidden subcommands are not listed.
+    fn accepts_subcommand(&self, word: &str) -> bool {
+        if self.subcommands.is_empty() {
+            self.positionals.is_empty()
+        } else {
+            self.subcommands.iter().any(|(name, _)| name == word)
+        }
+    }
+    /// `None` without a `Usage:` line, i.e. when the binary is not a clap one.
+    fn parse(help: &str) -> Option<Self> {
+        let lines = help.lines().collect::<Vec<_>>();
+        let usage = lines.iter().position(|line| {
+            let line = line.trim_start();
+            line.starts_with("Usage:") || line.starts_with("USAGE:")
+        })?;
+        let about = lines[..usage]
+            .split(|line| line.trim().is_empty())
+            .rfind(|paragraph| !paragraph.is_empty())
+            .map(|paragraph| {
+                paragraph
+                    .iter()
+                    .map(|line| line.trim())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            });
+        let mut page = Self {
+            about,
+            ..Self::default()
+        };
+        let mut in_commands = false;
+        for line in &lines[usage + 1..] {
+            let item = line.trim();
+            if item.is_empty() {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                let heading = item.trim_end_matches(':').to_ascii_lowercase();
+                in_commands = heading == "commands" || heading == "subcommands";
+                continue;
+            }
+            // Descriptions that do not fit on the line of the item are indented further.
+            if line.len() - line.trim_start().len() > 6 {
+                continue;
+            }
+            let (spec, help) = match item.find("  ") {
+                Some(i) => (&item[..i], item[i..].trim()),
+                None => (item, ""),
+            };
+            if in_commands {
+                let name = spec.split(',').next().unwrap_or(spec).trim();
+                page.subcommands.push((name.to_string(), help.to_string()));
+            } else if spec.starts_with('-') {
+                page.options.push(HelpOption::parse(spec, help));
+            } else if spec.starts_with(['<', '[']) {
+                page.positionals.push(HelpPositional::parse(spec, help));
+            }
+        }
+        Some(page)
+    }
+    /// The command without its subcommands, with arguments clap would reject left out.
+    fn command(&self, name: &str) -> Command {
+        let mut command = Command::new(name.to_string())
+            .disable_help_flag(true)
+            .disable_version_flag(true)
+            .disable_help_subcommand(true);
+        if let Some(about) = &self.about {
+            command = command.about(about.clone());
+        }
+        let mut ids = BTreeSet::new();
+        let mut shorts = BTreeSet::new();
+        for option in &self.options {
+            let Some(id) = option
+                .long
+                .clone()
+                .or_else(|| option.short.map(String::from))
+                .filter(|id| ids.insert(id.clone()))
+            else {
+                continue;
+            };
+            let mut arg = Arg::new(id).help(option.help.clone());
+            if let Some(long) = &option.long {
+                arg = arg.long(long.clone());
+            }
+            if let Some(short) = option.short.filter(|short| shorts.insert(*short)) {
+                arg = arg.short(short);
+            }
+            arg = match &option.value_name {
+                Some(value_name) => {
+                    let arg = arg
+                        .value_name(value_name.clone())
+                        .action(if option.multiple {
+                            ArgAction::Append
+                        } else {
+                            ArgAction::Set
+                        });
+                    if option.possible_values.is_empty() {
+                        arg
+                    } else {
+                        arg.value_parser(PossibleValuesParser::new(option.possible_values.clone()))
+                    }
+                }
+                None if option.multiple => arg.action(ArgAction::Count),
+                None => arg.action(ArgAction::SetTrue),
+            };
+            command = command.arg(arg);
+        }
+        // clap wants required positionals first and only the last one taking many values.
+        let mut required = true;
+        let last = self.positionals.len().saturating_sub(1);
+        for (i, positional) in self.positionals.iter().enumerate() {
+            let id = positional.name.to_ascii_lowercase();
+            if !ids.insert(id.clone()) {
+                continue;
+            }
+            required &= positional.required;
+            let multiple = positional.multiple && i == last;
+            let arg = Arg::new(id)
+                .value_name(positional.name.clone())
+                .help(positional.help.clone())
+                .required(required);
+            command = command.arg(if multiple {
+                arg.num_args(1..).action(ArgAction::Append)
+            } else {
+                arg.action(ArgAction::Set)
+            });
+        }
+        command
+    }
+}
+
+impl HelpOption {
+    /// `spec` is e.g. `-o, --output <FILE>`.
+    fn parse(spec: &str, help: &str) -> Self {
+        let (help, possible_values) = match help.split_once("[possible values: ") {
+            Some((help, rest)) => (
+                help.trim(),
+                rest.split(']')
+                    .next()
+                    .unwrap_or_default()
+                    .split(", ")
+                    .map(String::from)
+                    .collect(),
+            ),
+            None => (help, Vec::new()),
+        };
+        let mut option = Self {
+            help: help.to_string(),
+            possible_values,
+            ..Self::default()
+        };
+        for token in spec
+            .split([' ', ',', '='])
+            .filter(|token| !token.is_empty())
+        {
+            let token = match token.strip_suffix("...") {
+                Some(token) => {
+                    option.multiple = true;
+                    token
+                }
+                None => token,
+            };
+            if let Some(long) = token.strip_prefix("--") {
+                option.long = Some(long.to_string());
+            } else if let Some(short) = token.strip_prefix('-') {
+                option.short = short.chars().next();
+            } else if token.starts_with(['<', '[']) {
+                option.value_name = Some(token.trim_matches(['<', '>', '[', ']']).to_string());
+            }
+        }
+        option
+    }
+}
+
+impl HelpPositional {
+    /// `spec` is e.g. `<FILE>` or `[PATHS]...`.
+    fn parse(spec: &str, help: &str) -> Self {
+        let multiple = spec.ends_with("...");
+        Self {
+            name: spec
+                .trim_end_matches("...")
+                .trim_matches(['<', '>', '[', ']'])
+                .to_string(),
+            required: spec.starts_with('<'),
+            multiple,
+            help: help.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::{CommandOutput, RecordingCommandRunner};
+
+    use super::*;
+
+    const HELP: &str = "\
+Sync files between machines
+
+Usage: app [OPTIONS] <SOURCE> [DEST]... [COMMAND]
+
+Commands:
+  push  Send local changes
+  help  Print this message or the help of the given subcommand(s)
+
+Arguments:
+  <SOURCE>   Where to copy from
+  [DEST]...  Where to copy to
+
+Options:
+  -v, --verbose...       More output
+      --color <WHEN>     When to color [default: auto] [possible values: always, auto, never]
+  -e, --exclude <GLOB>   Skip these files
+  -h, --help             Print help
+  -V, --version          Print version
+";
+
+    fn config(dir: &Path) -> CompletionsConfig {
+        CompletionsConfig {
+            bash_dir: Some(dir.join("bash")),
+            zsh_dir: Some(dir.join("zsh")),
+            fish_dir: Some(dir.join("fish")),
+            man_dir: Some(dir.join("man1")),
+            ..CompletionsConfig::default()
+        }
+    }
+    #[test]
+    fn clapのヘルプを解釈する() {
+        let page = HelpPage::parse(HELP).unwrap();
+
+        assert_eq!(page.about.as_deref(), Some("Sync files between machines"));
+        assert_eq!(
+            page.subcommands
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["push", "help"]
+        );
+        assert_eq!(
+            page.options[0],
+            HelpOption {
+                short: Some('v'),
+                long: Some("verbose".to_string()),
+                multiple: true,
+                help: "More output".to_string(),
+                ..HelpOption::default()
+            }
+        );
+        assert_eq!(page.options[1].value_name.as_deref(), Some("WHEN"));
+        assert_eq!(
+            page.options[1].possible_values,
+            vec!["always", "auto", "never"]
+        );
+        assert_eq!(page.options[1].help, "When to color [default: auto]");
+        assert_eq!(
+            page.positionals,
+            vec![
+                HelpPositional {
+                    name: "SOURCE".to_string(),
+                    required: true,
+                    multiple: false,
+                    help: "Where to copy from".to_string(),
+                },
+                HelpPositional {
+                    name: "DEST".to_string(),
+                    required: false,
+                    multiple: true,
+                    help: "Where to copy to".to_string(),
+                }
+            ]
+        );
+        page.command("app").debug_assert();
+        assert_eq!(HelpPage::parse("Hello, world!\n"), None);
+    }
+    #[test]
+    fn 補完サブコマンドがあればその出力を使いなければヘルプから作る() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "/bin/app completions bash",
+                CommandOutput::new(0).stdout("complete -F _app app\n"),
+            )
+            .respond("/bin/app completions", CommandOutput::new(2))
+            .respond(
+                "/bin/app --help",
+                CommandOutput::new(0).stdout(HELP.replace(
+                    "  push  Send local changes\n",
+                    "  push  Send local changes\n  completions  Print completion scripts\n",
+                )),
+            )
+            .respond(
+                "/bin/app --version",
+                CommandOutput::new(0).stdout("app 1.4.2\n"),
+            )
+            .respond(
+                "/bin/app push --help",
+                CommandOutput::new(0)
+                    .stdout("Usage: push [OPTIONS]\n\nOptions:\n  --force  Overwrite\n"),
+            );
+
+        let written = Completions::from_config(&config(dir.path()))
+            .generate(&runner, Path::new("/bin/app"), "app")
+            .unwrap();
+
+        assert_eq!(
+            written,
+            vec![
+                dir.path().join("bash/app"),
+                dir.path().join("zsh/_app"),
+                dir.path().join("fish/app.fish"),
+                dir.path().join("man1/app.1"),
+            ]
+        );
+        let read = |path: &str| std::fs::read_to_string(dir.path().join(path)).unwrap();
+        assert_eq!(read("bash/app"), "complete -F _app app\n");
+        assert!(read("zsh/_app").starts_with("#compdef app"));
+        assert!(read("fish/app.fish").contains("-l exclude"));
+        assert!(read("fish/app.fish").contains("-l force"));
+        assert!(read("man1/app.1").contains("Sync files between machines"));
+        assert!(read("man1/app.1").contains("app 1.4.2"));
+        assert_eq!(
+            runner
+                .command_lines()
+                .iter()
+                .filter(|line| line.ends_with("--help"))
+                .count(),
+            3
+        );
+        // `man` is not listed, so it could be taken for <SOURCE>.
+        assert!(!runner.command_lines().contains(&"/bin/app man".to_string()));
+        // In a temporary directory, removed again.
+        assert!(runner
+            .commands()
+            .iter()
+            .all(|command| command.is_null_stdin()
+                && command.get_current_dir().is_some_and(|dir| !dir.exists())));
+    }
+    #[test]
+    fn 位置引数に取られるならサブコマンドを試さない() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new().respond(
+            "/bin/cp --help",
+            CommandOutput::new(0).stdout(
+                "Usage: cp <SOURCE> <DEST>\n\nArguments:\n  <SOURCE>  From\n  <DEST>    To\n",
+            ),
+        );
+
+        Completions::from_config(&config(dir.path()))
+            .generate(&runner, Path::new("/bin/cp"), "cp")
+            .unwrap();
+
+        assert_eq!(
+            runner.command_lines(),
+            vec!["/bin/cp --help", "/bin/cp --version"]
+        );
+        assert!(dir.path().join("bash/cp").is_file());
+    }
+    #[test]
+    fn clapでないバイナリには何も書かない() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new().respond(
+            "/bin/hello",
+            CommandOutput::new(0).stdout("Hello, world!\n"),
+        );
+
+        let result = Completions::from_config(&config(dir.path())).generate(
+            &runner,
+            Path::new("/bin/hello"),
+            "hello",
+        );
+
+        assert!(result.is_err());
+        assert!(!dir.path().join("bash").exists());
+    }
+    #[test]
+    fn 途中で失敗しても書いたファイルを返す() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path().join("file"), "").unwrap();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "/bin/app completions bash",
+                CommandOutput::new(0).stdout("complete -F _app app\n"),
+            )
+            .respond("/bin/app completions", CommandOutput::new(2))
+            .respond(
+                "/bin/app --help",
+                CommandOutput::new(0)
+                    .stdout("Usage: app [OPTIONS]\n\nOptions:\n  -q, --quiet  Less output\n"),
+            );
+        let config = CompletionsConfig {
+            zsh_dir: Some(dir.path().join("file/zsh")),
+            ..config(dir.path())
+        };
+
+        let err = Completions::from_config(&config)
+            .generate(&runner, Path::new("/bin/app"), "app")
+            .unwrap_err();
+
+        assert_eq!(err.written, vec![dir.path().join("bash/app")]);
+        assert!(err.written[0].is_file());
+    }
+}
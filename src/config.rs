@@ -0,0 +1,504 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{completions::Shell, env_preset::EnvPreset, package::ArchiveFormat, xdg};
+
+/// Settings read from `cw.toml`.
+///
+/// The file in the project directory wins over `$XDG_CONFIG_HOME/cw/cw.toml`;
+/// the two are not merged.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CwConfig {
+    pub build: BuildConfig,
+    pub log: LogConfig,
+    pub smoke: SmokeConfig,
+    pub strip: StripConfig,
+    pub package: PackageConfig,
+    pub sign: SignConfig,
+    pub cache: CacheConfig,
+    pub completions: CompletionsConfig,
+    /// Presets for `--env-preset` besides the built-in ones, by name.
+    pub env_presets: BTreeMap<String, EnvPreset>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BuildConfig {
+    /// `"90s"`, `"10m"`, `"1h"` or a number of seconds.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    pub forward_signals: bool,
+    /// Refuse to install when the build has warnings.
+    pub deny_warnings: bool,
+    /// Env preset used when `--env-preset` is not given.
+    pub env_preset: Option<String>,
+}
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            forward_signals: true,
+            deny_warnings: false,
+            env_preset: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub enabled: bool,
+    /// Oldest logs are removed once there are more than this many.
+    pub max_files: usize,
+    /// Oldest logs are removed once all logs together are larger, e.g. `"50MB"`.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_size: Option<u64>,
+}
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_files: 100,
+            max_size: None,
+        }
+    }
+}
+
+/// Running installed binaries once after `cw build`, see `--smoke`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SmokeConfig {
+    pub enabled: bool,
+    /// Arguments to run each binary with; `--version`, then `--help` when unset.
+    pub args: Option<Vec<String>>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+}
+impl SmokeConfig {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+}
+impl Default for SmokeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            args: None,
+            timeout: Some(Self::DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+/// Stripping installed binaries, see `--strip`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StripConfig {
+    pub enabled: bool,
+    /// Keep the debug info as `<bin>.debug` next to the binary.
+    pub keep_debug: bool,
+}
+
+/// Signing the checksums recorded for installed binaries.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SignConfig {
+    pub enabled: bool,
+    /// `$XDG_CONFIG_HOME/cw/signing.key` when unset; the public key is `<key>.pub`.
+    pub key: Option<PathBuf>,
+}
+
+/// The artifact cache reinstalling clean commits built before without running cargo.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// `$XDG_CACHE_HOME/cw/artifacts` when unset.
+    pub dir: Option<PathBuf>,
+    /// Least recently used builds are removed once the cache is larger, e.g. `"2GB"`.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_size: Option<u64>,
+}
+impl CacheConfig {
+    pub const DEFAULT_MAX_SIZE: u64 = 2 << 30;
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            max_size: Some(Self::DEFAULT_MAX_SIZE),
+        }
+    }
+}
+
+/// Completion scripts and man pages written after `cw build`, see `--completions`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CompletionsConfig {
+    pub enabled: bool,
+    pub shells: Vec<Shell>,
+    /// Arguments that make a binary print its completion script; `{shell}` is replaced
+    /// by `bash`, `zsh` or `fish`.
+    pub command: Vec<String>,
+    /// Also write a man page.
+    pub man: bool,
+    /// Arguments that make a binary print its man page.
+    pub man_command: Vec<String>,
+    /// `$XDG_DATA_HOME/bash-completion/completions` when unset.
+    pub bash_dir: Option<PathBuf>,
+    /// `$XDG_DATA_HOME/zsh/site-functions` when unset.
+    pub zsh_dir: Option<PathBuf>,
+    /// `$XDG_DATA_HOME/fish/vendor_completions.d` when unset.
+    pub fish_dir: Option<PathBuf>,
+    /// `$XDG_DATA_HOME/man/man1` when unset.
+    pub man_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+}
+impl Default for CompletionsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shells: Shell::ALL.to_vec(),
+            command: vec!["completions".to_string(), "{shell}".to_string()],
+            man: true,
+            man_command: vec!["man".to_string()],
+            bash_dir: None,
+            zsh_dir: None,
+            fish_dir: None,
+            man_dir: None,
+            timeout: Some(SmokeConfig::DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+/// Archives made by `cw package`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PackageConfig {
+    pub formats: Vec<ArchiveFormat>,
+    /// Relative to the project directory.
+    pub out_dir: PathBuf,
+    /// Top directory inside the archives; `{name}`, `{version}` and `{target}` are replaced.
+    pub root: String,
+    /// Directory of the binaries below `root`.
+    pub bin_dir: String,
+    /// Globs of project files to ship; a glob containing `/` also looks into sub directories.
+    pub include: Vec<String>,
+    /// `Maintainer` of the `.deb`; the first of `authors` in `Cargo.toml` when unset.
+    pub maintainer: Option<String>,
+    /// Extra files for the `.deb` and `.rpm`.
+    pub assets: Vec<PackageAsset>,
+    /// Unit files installed into `/usr/lib/systemd/system` by the `.deb` and `.rpm`.
+    pub systemd_units: Vec<PathBuf>,
+}
+impl Default for PackageConfig {
+    fn default() -> Self {
+        Self {
+            formats: vec![ArchiveFormat::TarGz, ArchiveFormat::Zip],
+            out_dir: PathBuf::from("target/dist"),
+            root: "{name}-{version}-{target}".to_string(),
+            bin_dir: String::new(),
+            include: ["README*", "LICENSE*", "COPYING*", "completions/**"]
+                .map(String::from)
+                .to_vec(),
+            maintainer: None,
+            assets: Vec::new(),
+            systemd_units: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PackageAsset {
+    /// Relative to the project directory.
+    pub source: PathBuf,
+    /// Absolute path on the system installing the package.
+    pub dest: String,
+    /// `0o644` when unset.
+    pub mode: Option<u32>,
+}
+
+impl CwConfig {
+    pub const FILE_NAME: &'static str = "cw.toml";
+    pub fn load(project_dir: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        match Self::find(project_dir.as_ref()) {
+            Some(path) => {
+                let content = std::fs::read_to_string(&path)?;
+                Self::from_toml(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+            }
+            None => Ok(Self::default()),
+        }
+    }
+    pub fn from_toml(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+    fn find(project_dir: &Path) -> Option<PathBuf> {
+        std::iter::once(project_dir.join(Self::FILE_NAME))
+            .chain(xdg::config_dir().map(|dir| dir.join(Self::FILE_NAME)))
+            .find(|path| path.is_file())
+    }
+}
+
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .map(|i| value.split_at(i))
+        .unwrap_or((value, "s"));
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("invalid duration: {}", value))?;
+    let secs = match unit.trim() {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        _ => return Err(format!("invalid duration unit: {}", value)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid duration: {}", value))
+}
+
+/// `"512"`, `"64KB"`, `"50MB"` or `"1GB"` in bytes.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let (number, unit) = upper
+        .find(|c: char| !c.is_ascii_digit())
+        .map(|i| upper.split_at(i))
+        .unwrap_or((upper.as_str(), "B"));
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size: {}", value))?;
+    let unit = match unit.trim() {
+        "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(format!("invalid size unit: {}", value)),
+    };
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| format!("invalid size: {}", value))
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Secs(u64),
+        Text(String),
+    }
+    match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::Secs(secs)) => Ok(Some(Duration::from_secs(secs))),
+        Some(Value::Text(text)) => parse_duration(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Value::Text(text)) => parse_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn 時間の文字列を解釈できる() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert!(parse_duration("10 days").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+    }
+    #[test]
+    fn サイズの文字列を解釈できる() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64KB"), Ok(64 * 1024));
+        assert_eq!(parse_size("50mb"), Ok(50 * 1024 * 1024));
+        assert!(parse_size("1TB").is_err());
+        assert!(parse_size("18446744073709551615GB").is_err());
+    }
+    #[test]
+    fn buildの設定を読み込める() {
+        let config = CwConfig::from_toml(
+            r#"
+[build]
+timeout = "10m"
+forward_signals = false
+deny_warnings = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.build.timeout, Some(Duration::from_secs(600)));
+        assert!(!config.build.forward_signals);
+        assert!(config.build.deny_warnings);
+
+        let config = CwConfig::from_toml("[build]\ntimeout = 30").unwrap();
+        assert_eq!(config.build.timeout, Some(Duration::from_secs(30)));
+
+        let config = CwConfig::from_toml("").unwrap();
+        assert_eq!(config.build.timeout, None);
+        assert!(config.build.forward_signals);
+    }
+    #[test]
+    fn envプリセットの設定を読み込める() {
+        let config = CwConfig::from_toml(
+            r#"
+[build]
+env_preset = "lean"
+
+[env_presets.lean]
+rustflags = ["-C", "target-cpu=native"]
+profile = { opt-level = "s" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.build.env_preset.as_deref(), Some("lean"));
+        assert_eq!(
+            config.env_presets["lean"].rustflags,
+            vec!["-C".to_string(), "target-cpu=native".to_string()]
+        );
+        assert!(CwConfig::from_toml("").unwrap().env_presets.is_empty());
+    }
+    #[test]
+    fn cacheの設定を読み込める() {
+        let config = CwConfig::from_toml(
+            r#"
+[cache]
+enabled = true
+dir = "/var/cache/cw"
+max_size = "500MB"
+"#,
+        )
+        .unwrap();
+        assert!(config.cache.enabled);
+        assert_eq!(config.cache.dir, Some(PathBuf::from("/var/cache/cw")));
+        assert_eq!(config.cache.max_size, Some(500 << 20));
+
+        let config = CwConfig::from_toml("").unwrap();
+        assert!(!config.cache.enabled);
+        assert_eq!(config.cache.max_size, Some(CacheConfig::DEFAULT_MAX_SIZE));
+    }
+    #[test]
+    fn completionsの設定を読み込める() {
+        let config = CwConfig::from_toml(
+            r#"
+[completions]
+enabled = true
+shells = ["zsh"]
+command = ["generate", "--shell", "{shell}"]
+man = false
+zsh_dir = "/home/me/.zfunc"
+"#,
+        )
+        .unwrap();
+        assert!(config.completions.enabled);
+        assert_eq!(config.completions.shells, vec![Shell::Zsh]);
+        assert_eq!(
+            config.completions.command,
+            ["generate", "--shell", "{shell}"]
+        );
+        assert!(!config.completions.man);
+        assert_eq!(
+            config.completions.zsh_dir,
+            Some(PathBuf::from("/home/me/.zfunc"))
+        );
+
+        let config = CwConfig::from_toml("").unwrap();
+        assert!(!config.completions.enabled);
+        assert_eq!(config.completions.shells, Shell::ALL);
+        assert_eq!(config.completions.man_command, ["man"]);
+    }
+    #[test]
+    fn smokeの設定を読み込める() {
+        let config = CwConfig::from_toml(
+            r#"
+[smoke]
+enabled = true
+args = ["check", "--quiet"]
+timeout = "3s"
+"#,
+        )
+        .unwrap();
+        assert!(config.smoke.enabled);
+        assert_eq!(
+            config.smoke.args,
+            Some(vec!["check".to_string(), "--quiet".to_string()])
+        );
+        assert_eq!(config.smoke.timeout, Some(Duration::from_secs(3)));
+
+        let config = CwConfig::from_toml("").unwrap();
+        assert!(!config.smoke.enabled);
+        assert_eq!(config.smoke.timeout, Some(SmokeConfig::DEFAULT_TIMEOUT));
+    }
+    #[test]
+    fn packageの設定を読み込める() {
+        let config = CwConfig::from_toml(
+            r#"
+[package]
+formats = ["zip"]
+root = "{name}"
+bin_dir = "bin"
+include = ["README.md", "docs/**"]
+maintainer = "Ops <ops@example.com>"
+systemd_units = ["dist/app.service"]
+
+[[package.assets]]
+source = "dist/app.toml"
+dest = "/etc/app/app.toml"
+mode = 0o600
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.package.formats, vec![ArchiveFormat::Zip]);
+        assert_eq!(config.package.root, "{name}");
+        assert_eq!(config.package.bin_dir, "bin");
+        assert_eq!(config.package.include, vec!["README.md", "docs/**"]);
+        assert_eq!(config.package.out_dir, PathBuf::from("target/dist"));
+        assert_eq!(
+            config.package.maintainer.as_deref(),
+            Some("Ops <ops@example.com>")
+        );
+        assert_eq!(
+            config.package.systemd_units,
+            vec![PathBuf::from("dist/app.service")]
+        );
+        assert_eq!(
+            config.package.assets,
+            vec![PackageAsset {
+                source: PathBuf::from("dist/app.toml"),
+                dest: "/etc/app/app.toml".to_string(),
+                mode: Some(0o600),
+            }]
+        );
+
+        assert!(CwConfig::from_toml("[package]\nformats = [\"rar\"]").is_err());
+    }
+}
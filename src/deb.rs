@@ -0,0 +1,304 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    checksum::md5_hex,
+    package::{read_files, tar_gz, ArchiveEntry, PackageFile, PackageInfo},
+};
+
+/// Debian architecture of a Rust target triple.
+pub fn arch(target: &str) -> &str {
+    let cpu = target.split('-').next().unwrap_or(target);
+    match cpu {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "i386" | "i586" | "i686" => "i386",
+        "armv7" => "armhf",
+        "arm" => "armel",
+        "powerpc64le" => "ppc64el",
+        "riscv64gc" => "riscv64",
+        _ => cpu,
+    }
+}
+
+/// Debian package names are lower case and may not contain `_`.
+pub fn package_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace('_', "-")
+}
+
+/// `1.2.3-rc.1` becomes `1.2.3~rc.1-1` so that the pre-release sorts before `1.2.3-1`.
+pub fn version(version: &str) -> String {
+    format!("{}-1", version.replacen('-', "~", 1))
+}
+
+/// Writes `<name>_<version>_<arch>.deb` into `out_dir`.
+///
+/// A `.deb` is an `ar` archive of `debian-binary`, `control.tar.gz` and `data.tar.gz`,
+/// so dpkg-deb is not needed to build one.
+pub fn write(
+    info: &PackageInfo,
+    target: &str,
+    files: &[PackageFile],
+    out_dir: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let name = package_name(&info.name);
+    let maintainer = info.maintainer.as_deref().ok_or(
+        "a .deb needs a maintainer: set `authors` in Cargo.toml or `package.maintainer` in cw.toml",
+    )?;
+    let mut files = read_files(files)?;
+    if let Some(license) = &info.license {
+        files.push(ArchiveEntry {
+            path: format!("usr/share/doc/{}/copyright", name),
+            content: copyright(info, license).into_bytes(),
+            mode: 0o644,
+        });
+    }
+
+    let mut dirs = BTreeSet::new();
+    let mut md5sums = String::new();
+    let mut conffiles = String::new();
+    for file in &files {
+        let mut dir = Path::new(&file.path).parent();
+        while let Some(parent) = dir.filter(|dir| !dir.as_os_str().is_empty()) {
+            dirs.insert(format!("{}/", parent.display()));
+            dir = parent.parent();
+        }
+        md5sums.push_str(&format!("{}  {}\n", md5_hex(&file.content), file.path));
+        if file.path.starts_with("etc/") {
+            conffiles.push_str(&format!("/{}\n", file.path));
+        }
+    }
+    let installed_size = files
+        .iter()
+        .map(|file| file.content.len() as u64)
+        .sum::<u64>()
+        .div_ceil(1024);
+    let data = std::iter::once("./".to_string())
+        .chain(dirs)
+        .map(|dir| ArchiveEntry {
+            path: dir,
+            content: Vec::new(),
+            mode: 0o755,
+        })
+        .chain(files)
+        .collect::<Vec<_>>();
+
+    let mut control = vec![
+        ArchiveEntry {
+            path: "control".to_string(),
+            content: control_file(info, target, maintainer, installed_size).into_bytes(),
+            mode: 0o644,
+        },
+        ArchiveEntry {
+            path: "md5sums".to_string(),
+            content: md5sums.into_bytes(),
+            mode: 0o644,
+        },
+    ];
+    if !conffiles.is_empty() {
+        control.push(ArchiveEntry {
+            path: "conffiles".to_string(),
+            content: conffiles.into_bytes(),
+            mode: 0o644,
+        });
+    }
+
+    let path = out_dir.join(format!(
+        "{}_{}_{}.deb",
+        name,
+        version(&info.version),
+        arch(target)
+    ));
+    let mut builder = ar::Builder::new(File::create(&path)?);
+    for (member, content) in [
+        ("debian-binary", b"2.0\n".to_vec()),
+        ("control.tar.gz", tar_gz(&control)?),
+        ("data.tar.gz", tar_gz(&data)?),
+    ] {
+        let mut header = ar::Header::new(member.as_bytes().to_vec(), content.len() as u64);
+        header.set_mode(0o100644);
+        builder.append(&header, content.as_slice())?;
+    }
+    Ok(path)
+}
+
+fn control_file(info: &PackageInfo, target: &str, maintainer: &str, installed_size: u64) -> String {
+    let mut control = format!(
+        "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nInstalled-Size: {}\nSection: utils\nPriority: optional\n",
+        package_name(&info.name),
+        version(&info.version),
+        arch(target),
+        maintainer,
+        installed_size
+    );
+    if let Some(homepage) = &info.homepage {
+        control.push_str(&format!("Homepage: {}\n", homepage));
+    }
+    let description = info.description.as_deref().unwrap_or(&info.name).trim();
+    let mut lines = description.lines();
+    control.push_str(&format!(
+        "Description: {}\n",
+        lines.next().unwrap_or_default()
+    ));
+    for line in lines {
+        match line.trim() {
+            "" => control.push_str(" .\n"),
+            line => control.push_str(&format!(" {}\n", line)),
+        }
+    }
+    control
+}
+
+fn copyright(info: &PackageInfo, license: &str) -> String {
+    format!(
+        "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/\nUpstream-Name: {}\n\nFiles: *\nCopyright: {}\nLicense: {}\n",
+        info.name,
+        info.maintainer.as_deref().unwrap_or(&info.name),
+        license
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::commands::write_file;
+
+    use super::*;
+
+    fn unpack_tar_gz(bytes: &[u8]) -> Vec<(String, u32, String)> {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bytes));
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    entry.header().mode().unwrap(),
+                    content,
+                )
+            })
+            .collect()
+    }
+    #[test]
+    fn ターゲットとバージョンをdebianの流儀に直す() {
+        assert_eq!(arch("x86_64-unknown-linux-gnu"), "amd64");
+        assert_eq!(arch("aarch64-unknown-linux-musl"), "arm64");
+        assert_eq!(arch("armv7-unknown-linux-gnueabihf"), "armhf");
+        assert_eq!(version("1.2.3"), "1.2.3-1");
+        assert_eq!(version("1.2.3-rc.1"), "1.2.3~rc.1-1");
+        assert_eq!(package_name("My_Tool"), "my-tool");
+    }
+    #[test]
+    fn debをarとtarで展開できる形で書き出す() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path().join("app"), "binary").unwrap();
+        write_file(dir.path().join("app.toml"), "port = 80").unwrap();
+        let info = PackageInfo {
+            description: Some("Deploys things\n\nSafely.".to_string()),
+            maintainer: Some("Ops <ops@example.com>".to_string()),
+            license: Some("MIT".to_string()),
+            homepage: Some("https://example.com/app".to_string()),
+            ..PackageInfo::new("app", "1.2.3")
+        };
+        let files = vec![
+            PackageFile {
+                source: dir.path().join("app"),
+                path: "usr/bin/app".to_string(),
+                mode: 0o755,
+            },
+            PackageFile {
+                source: dir.path().join("app.toml"),
+                path: "etc/app/app.toml".to_string(),
+                mode: 0o600,
+            },
+        ];
+
+        let path = write(&info, "x86_64-unknown-linux-gnu", &files, dir.path()).unwrap();
+
+        assert_eq!(path, dir.path().join("app_1.2.3-1_amd64.deb"));
+        let mut archive = ar::Archive::new(File::open(&path).unwrap());
+        let mut members = Vec::new();
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry.unwrap();
+            let name = String::from_utf8(entry.header().identifier().to_vec()).unwrap();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            members.push((name, content));
+        }
+        assert_eq!(
+            members
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["debian-binary", "control.tar.gz", "data.tar.gz"]
+        );
+        assert_eq!(members[0].1, b"2.0\n");
+
+        let control = unpack_tar_gz(&members[1].1);
+        assert_eq!(
+            control[0].2,
+            "\
+Package: app
+Version: 1.2.3-1
+Architecture: amd64
+Maintainer: Ops <ops@example.com>
+Installed-Size: 1
+Section: utils
+Priority: optional
+Homepage: https://example.com/app
+Description: Deploys things
+ .
+ Safely.
+"
+        );
+        assert_eq!(
+            control[1].2,
+            format!(
+                "{}  usr/bin/app\n{}  etc/app/app.toml\n{}  usr/share/doc/app/copyright\n",
+                md5_hex(b"binary"),
+                md5_hex(b"port = 80"),
+                md5_hex(copyright(&info, "MIT").as_bytes())
+            )
+        );
+        assert_eq!(control[2].2, "/etc/app/app.toml\n");
+
+        let data = unpack_tar_gz(&members[2].1);
+        assert_eq!(
+            data.iter()
+                .map(|(path, mode, _)| format!("{} {:o}", path, mode))
+                .collect::<Vec<_>>(),
+            vec![
+                "./ 755",
+                "etc/ 755",
+                "etc/app/ 755",
+                "usr/ 755",
+                "usr/bin/ 755",
+                "usr/share/ 755",
+                "usr/share/doc/ 755",
+                "usr/share/doc/app/ 755",
+                "usr/bin/app 755",
+                "etc/app/app.toml 600",
+                "usr/share/doc/app/copyright 644",
+            ]
+        );
+        assert_eq!(data[8].2, "binary");
+    }
+    #[test]
+    fn メンテナがいなければdebを作らない() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = write(
+            &PackageInfo::new("app", "1.2.3"),
+            "x86_64-unknown-linux-gnu",
+            &[],
+            dir.path(),
+        );
+        assert!(result.unwrap_err().to_string().contains("maintainer"));
+    }
+}
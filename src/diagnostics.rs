@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::cargo_messages::{compiler_messages, CargoMessage, CompilerMessage};
+
+/// Warnings and errors of a build grouped by lint, file and crate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiagnosticSummary {
+    pub warnings: usize,
+    pub errors: usize,
+    by_lint: BTreeMap<String, usize>,
+    by_file: BTreeMap<String, usize>,
+    by_crate: BTreeMap<String, usize>,
+    first_error: Option<String>,
+}
+
+impl DiagnosticSummary {
+    const NO_CODE: &'static str = "(no code)";
+    const NO_FILE: &'static str = "(no file)";
+    /// Notes, help and failure notes are not counted.
+    pub fn from_messages(messages: &[CargoMessage]) -> Self {
+        let mut summary = Self::default();
+        for message in compiler_messages(messages) {
+            summary.add(message);
+        }
+        summary
+    }
+    pub fn is_empty(&self) -> bool {
+        self.warnings == 0 && self.errors == 0
+    }
+    fn add(&mut self, message: &CompilerMessage) {
+        let diagnostic = &message.message;
+        if diagnostic.is_error() {
+            self.errors += 1;
+            if self.first_error.is_none() {
+                self.first_error = Some(match diagnostic.primary_span() {
+                    Some(span) => format!(
+                        "{}:{}:{}: {}",
+                        span.file_name, span.line_start, span.column_start, diagnostic.message
+                    ),
+                    None => diagnostic.message.clone(),
+                });
+            }
+        } else if diagnostic.is_warning() {
+            self.warnings += 1;
+        } else {
+            return;
+        }
+        let lint = diagnostic
+            .code
+            .as_ref()
+            .map(|code| code.code.as_str())
+            .unwrap_or(Self::NO_CODE);
+        let file = diagnostic
+            .primary_span()
+            .map(|span| span.file_name.as_str())
+            .unwrap_or(Self::NO_FILE);
+        *self.by_lint.entry(lint.to_string()).or_default() += 1;
+        *self.by_file.entry(file.to_string()).or_default() += 1;
+        *self
+            .by_crate
+            .entry(message.target.name.clone())
+            .or_default() += 1;
+    }
+}
+
+impl Display for DiagnosticSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "cw: {} {}, {} {}",
+            self.warnings,
+            plural(self.warnings, "warning"),
+            self.errors,
+            plural(self.errors, "error")
+        )?;
+        for (title, counts) in [
+            ("by lint", &self.by_lint),
+            ("by file", &self.by_file),
+            ("by crate", &self.by_crate),
+        ] {
+            writeln!(f, "  {}:", title)?;
+            let width = counts.keys().map(|key| key.len()).max().unwrap_or_default();
+            let mut counts = counts.iter().collect::<Vec<_>>();
+            counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            for (key, count) in counts {
+                writeln!(f, "    {:<width$}  {:>3}", key, count, width = width)?;
+            }
+        }
+        if let Some(error) = &self.first_error {
+            writeln!(f, "  first error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_string()
+    } else {
+        format!("{}s", word)
+    }
+}
+
+/// `cw build --deny-warnings` found warnings, so nothing was installed.
+#[derive(Debug)]
+pub struct WarningsDenied {
+    pub warnings: usize,
+}
+impl Display for WarningsDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to install: the build has {} {}",
+            self.warnings,
+            plural(self.warnings, "warning")
+        )
+    }
+}
+impl std::error::Error for WarningsDenied {}
+
+#[cfg(test)]
+mod tests {
+    use crate::cargo_messages::{parse_messages, tests::compiler_message_json};
+
+    use super::*;
+    #[test]
+    fn 診断をlintとファイルとクレートごとに集計する() {
+        let stdout = [
+            compiler_message_json("app", "warning", Some("unused_variables"), "src/main.rs", 1),
+            compiler_message_json("app", "warning", Some("unused_variables"), "src/lib.rs", 2),
+            compiler_message_json("dep", "warning", Some("dead_code"), "dep/src/lib.rs", 3),
+            compiler_message_json("app", "error", Some("E0308"), "src/main.rs", 4),
+            compiler_message_json("app", "error", None, "src/main.rs", 5),
+            compiler_message_json("app", "failure-note", None, "src/main.rs", 6),
+        ]
+        .join("\n");
+
+        let summary = DiagnosticSummary::from_messages(&parse_messages(&stdout));
+
+        assert_eq!(summary.warnings, 3);
+        assert_eq!(summary.errors, 2);
+        assert_eq!(
+            summary.to_string(),
+            "\
+cw: 3 warnings, 2 errors
+  by lint:
+    unused_variables    2
+    (no code)           1
+    E0308               1
+    dead_code           1
+  by file:
+    src/main.rs       3
+    dep/src/lib.rs    1
+    src/lib.rs        1
+  by crate:
+    app    4
+    dep    1
+  first error: src/main.rs:4:5: something is wrong
+"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use serde::Deserialize;
+
+use crate::build::BuildMode;
+
+/// A named set of environment variables for `cargo build`, chosen with `--env-preset`.
+///
+/// `native`, `small` and `debuginfo` are built in; `[env_presets.<name>]` in `cw.toml`
+/// defines more or replaces them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EnvPreset {
+    /// Recorded in the ledger; set by `find`.
+    #[serde(skip)]
+    pub name: String,
+    /// Appended to the `RUSTFLAGS` cw runs with.
+    pub rustflags: Vec<String>,
+    /// Overrides of the profile being built, e.g. `opt-level = "z"` for
+    /// `CARGO_PROFILE_RELEASE_OPT_LEVEL=z`.
+    pub profile: BTreeMap<String, toml::Value>,
+    /// Any other variables.
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct UnknownPreset {
+    pub name: String,
+    pub known: Vec<String>,
+}
+impl Display for UnknownPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown env preset {}, expected one of {}",
+            self.name,
+            self.known.join(", ")
+        )
+    }
+}
+impl std::error::Error for UnknownPreset {}
+
+impl EnvPreset {
+    pub const BUILTIN: [&'static str; 3] = ["native", "small", "debuginfo"];
+    pub fn builtin(name: &str) -> Option<Self> {
+        let profile = |settings: &[(&str, &str)]| {
+            settings
+                .iter()
+                .map(|(key, value)| (key.to_string(), toml::Value::from(*value)))
+                .collect()
+        };
+        match name {
+            "native" => Some(Self {
+                rustflags: vec!["-C".to_string(), "target-cpu=native".to_string()],
+                ..Self::default()
+            }),
+            "small" => Some(Self {
+                profile: profile(&[
+                    ("opt-level", "z"),
+                    ("lto", "true"),
+                    ("codegen-units", "1"),
+                    ("panic", "abort"),
+                ]),
+                ..Self::default()
+            }),
+            "debuginfo" => Some(Self {
+                profile: profile(&[("debug", "true"), ("strip", "none")]),
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+    /// The preset from `cw.toml`, else the built-in one of that name.
+    pub fn find(name: &str, configured: &BTreeMap<String, Self>) -> Result<Self, UnknownPreset> {
+        configured
+            .get(name)
+            .cloned()
+            .or_else(|| Self::builtin(name))
+            .map(|preset| Self {
+                name: name.to_string(),
+                ..preset
+            })
+            .ok_or_else(|| UnknownPreset {
+                name: name.to_string(),
+                known: Self::BUILTIN
+                    .iter()
+                    .map(|name| name.to_string())
+                    .chain(configured.keys().cloned())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+            })
+    }
+    /// `RUSTFLAGS` with the preset's flags after `rustflags`, `None` when both are empty.
+    pub fn rustflags(&self, rustflags: Option<&str>) -> Option<String> {
+        let flags = rustflags
+            .into_iter()
+            .flat_map(str::split_whitespace)
+            .chain(self.rustflags.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        (!flags.is_empty()).then(|| flags.join(" "))
+    }
+    /// Variables to run `cargo build` with; `rustflags` is the `RUSTFLAGS` cw runs with.
+    pub fn env(&self, mode: &BuildMode, rustflags: Option<&str>) -> Vec<(String, String)> {
+        let profile = match mode {
+            BuildMode::Debug => "DEV",
+            BuildMode::Release => "RELEASE",
+        };
+        let mut env = Vec::new();
+        if !self.rustflags.is_empty() {
+            env.extend(
+                self.rustflags(rustflags)
+                    .map(|flags| ("RUSTFLAGS".to_string(), flags)),
+            );
+        }
+        for (key, value) in &self.profile {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            env.push((
+                format!(
+                    "CARGO_PROFILE_{}_{}",
+                    profile,
+                    key.to_ascii_uppercase().replace('-', "_")
+                ),
+                value,
+            ));
+        }
+        env.extend(
+            self.env
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(preset: &EnvPreset, mode: BuildMode, rustflags: Option<&str>) -> Vec<String> {
+        preset
+            .env(&mode, rustflags)
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect()
+    }
+    #[test]
+    fn 組み込みのプリセットを環境変数にする() {
+        let none = BTreeMap::new();
+        assert_eq!(
+            env(
+                &EnvPreset::find("native", &none).unwrap(),
+                BuildMode::Release,
+                Some("-D warnings")
+            ),
+            vec!["RUSTFLAGS=-D warnings -C target-cpu=native"]
+        );
+        assert_eq!(
+            env(
+                &EnvPreset::find("small", &none).unwrap(),
+                BuildMode::Release,
+                Some("-D warnings")
+            ),
+            vec![
+                "CARGO_PROFILE_RELEASE_CODEGEN_UNITS=1",
+                "CARGO_PROFILE_RELEASE_LTO=true",
+                "CARGO_PROFILE_RELEASE_OPT_LEVEL=z",
+                "CARGO_PROFILE_RELEASE_PANIC=abort",
+            ]
+        );
+        assert_eq!(
+            env(
+                &EnvPreset::find("debuginfo", &none).unwrap(),
+                BuildMode::Debug,
+                None
+            ),
+            vec![
+                "CARGO_PROFILE_DEV_DEBUG=true",
+                "CARGO_PROFILE_DEV_STRIP=none"
+            ]
+        );
+        assert_eq!(EnvPreset::find("small", &none).unwrap().name, "small");
+        assert_eq!(
+            EnvPreset::find("fast", &none).unwrap_err().to_string(),
+            "unknown env preset fast, expected one of debuginfo, native, small"
+        );
+    }
+    #[test]
+    fn 設定のプリセットが組み込みより優先される() {
+        let configured = toml::from_str::<BTreeMap<String, EnvPreset>>(
+            r#"
+[small]
+profile = { opt-level = "s", debug = 1 }
+
+[ci]
+rustflags = ["-C", "force-frame-pointers=yes"]
+env = { CC = "clang" }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            env(
+                &EnvPreset::find("small", &configured).unwrap(),
+                BuildMode::Release,
+                None
+            ),
+            vec![
+                "CARGO_PROFILE_RELEASE_DEBUG=1",
+                "CARGO_PROFILE_RELEASE_OPT_LEVEL=s"
+            ]
+        );
+        assert_eq!(
+            env(
+                &EnvPreset::find("ci", &configured).unwrap(),
+                BuildMode::Release,
+                None
+            ),
+            vec!["RUSTFLAGS=-C force-frame-pointers=yes", "CC=clang"]
+        );
+        assert!(EnvPreset::find("fast", &configured)
+            .unwrap_err()
+            .known
+            .contains(&"ci".to_string()));
+    }
+}
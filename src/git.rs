@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use crate::runner::{CommandLine, CommandRunner};
+
+fn git(dir: &Path) -> CommandLine {
+    CommandLine::new("git")
+        .current_dir(dir)
+        .quiet_stdout()
+        .quiet_stderr()
+}
+
+/// Full hash of the commit `rev` points to.
+pub fn rev_parse(
+    runner: &impl CommandRunner,
+    dir: impl AsRef<Path>,
+    rev: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let output = runner.run_success(
+        &git(dir.as_ref())
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(format!("{}^{{commit}}", rev)),
+    )?;
+    Ok(output.stdout.trim().to_string())
+}
+
+/// `None` outside of a git work tree.
+pub fn head_commit(runner: &impl CommandRunner, dir: impl AsRef<Path>) -> Option<String> {
+    rev_parse(runner, dir, "HEAD")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+}
+
+/// Abbreviated commit hash for display.
+pub fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+/// Name of the checked out branch, `None` on a detached `HEAD` or outside of a git work tree.
+pub fn current_branch(runner: &impl CommandRunner, dir: impl AsRef<Path>) -> Option<String> {
+    runner
+        .run_success(&git(dir.as_ref()).args(["symbolic-ref", "--short", "--quiet", "HEAD"]))
+        .ok()
+        .map(|output| output.stdout.trim().to_string())
+        .filter(|branch| !branch.is_empty())
+}
+
+/// Committer time of `HEAD` in seconds since the epoch, `None` outside of a git work tree.
+pub fn head_commit_time(runner: &impl CommandRunner, dir: impl AsRef<Path>) -> Option<u64> {
+    runner
+        .run_success(&git(dir.as_ref()).args(["log", "-1", "--format=%ct"]))
+        .ok()
+        .and_then(|output| output.stdout.trim().parse().ok())
+}
+
+/// Whether tracked files have uncommitted changes; untracked files do not count.
+pub fn is_dirty(
+    runner: &impl CommandRunner,
+    dir: impl AsRef<Path>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let output = runner.run_success(&git(dir.as_ref()).args([
+        "status",
+        "--porcelain",
+        "--untracked-files=no",
+    ]))?;
+    Ok(!output.stdout.trim().is_empty())
+}
+
+/// Whether the work tree differs from HEAD at all, counting untracked files that are not
+/// ignored since cargo may build them too, e.g. a new module or `build.rs` input.
+pub fn has_changes(
+    runner: &impl CommandRunner,
+    dir: impl AsRef<Path>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    // Explicit, so that `status.showUntrackedFiles = no` does not hide them.
+    let output = runner.run_success(&git(dir.as_ref()).args([
+        "status",
+        "--porcelain",
+        "--untracked-files=normal",
+    ]))?;
+    Ok(!output.stdout.trim().is_empty())
+}
+
+pub fn is_ignored(runner: &impl CommandRunner, dir: impl AsRef<Path>, path: &Path) -> bool {
+    runner
+        .run(
+            &git(dir.as_ref())
+                .args(["check-ignore", "--quiet"])
+                .arg(path.display().to_string()),
+        )
+        .map(|output| output.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::{CommandOutput, RecordingCommandRunner};
+
+    use super::*;
+    #[test]
+    fn headのコミットを取得する() {
+        let runner = RecordingCommandRunner::new()
+            .respond("git rev-parse", CommandOutput::new(0).stdout("0123abcd\n"));
+
+        assert_eq!(
+            head_commit(&runner, "/work/app"),
+            Some("0123abcd".to_string())
+        );
+        assert_eq!(
+            runner.command_lines(),
+            vec!["git rev-parse --verify --quiet HEAD^{commit}"]
+        );
+        assert_eq!(
+            head_commit(&RecordingCommandRunner::new().fail("git"), "/work/app"),
+            None
+        );
+    }
+}
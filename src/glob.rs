@@ -0,0 +1,185 @@
+use std::path::{Component, Path};
+
+/// Shell style pattern supporting `*`, `?`, `[abc]`, `[!a-z]` and `**` path segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    segments: Vec<String>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            segments: pattern
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+    pub fn has_dir_separator(&self) -> bool {
+        self.segments.len() > 1
+    }
+    pub fn is_match(&self, path: &Path) -> bool {
+        let components = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => s.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match_segments(&self.segments, &components)
+    }
+    pub fn is_match_name(&self, name: &str) -> bool {
+        match self.segments.as_slice() {
+            [segment] => match_segment(segment.as_bytes(), name.as_bytes()),
+            _ => false,
+        }
+    }
+}
+
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((head, rest)) if head == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((head, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_segment(head.as_bytes(), name.as_bytes()) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((b'[', rest)) => {
+            let Some(close) = rest.iter().skip(1).position(|b| *b == b']').map(|i| i + 1) else {
+                return name.first() == Some(&b'[') && match_segment(rest, &name[1..]);
+            };
+            let Some(c) = name.first() else {
+                return false;
+            };
+            match_class(&rest[..close], *c) && match_segment(&rest[close + 1..], &name[1..])
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            name.first() == Some(escaped) && match_segment(rest, &name[1..])
+        }
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
+
+fn match_class(class: &[u8], c: u8) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some((b'!' | b'^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut i = 0;
+    let mut matched = false;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    matched != negated
+}
+
+/// A single line of a `.gitignore` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreRule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let glob = Glob::new(line);
+        if glob.segments.is_empty() {
+            return None;
+        }
+        Some(Self {
+            glob,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+    /// `path` is relative to the directory that holds the ignore file.
+    pub fn is_match(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.glob.is_match(path)
+        } else {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| self.glob.is_match_name(name))
+                .unwrap_or(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn globのワイルドカードがマッチする() {
+        assert!(Glob::new("*.rs").is_match_name("main.rs"));
+        assert!(!Glob::new("*.rs").is_match_name("main.rs.bk"));
+        assert!(Glob::new("ma?n.*").is_match_name("main.rs"));
+        assert!(Glob::new("[a-c]at").is_match_name("bat"));
+        assert!(!Glob::new("[!a-c]at").is_match_name("bat"));
+    }
+    #[test]
+    fn globの二重アスタリスクは任意の階層にマッチする() {
+        let glob = Glob::new("src/**/*.rs");
+        assert!(glob.is_match(Path::new("src/main.rs")));
+        assert!(glob.is_match(Path::new("src/new/cmd.rs")));
+        assert!(!glob.is_match(Path::new("tests/main.rs")));
+    }
+    #[test]
+    fn gitignoreの行を解釈できる() {
+        assert_eq!(IgnoreRule::parse("# comment"), None);
+        assert_eq!(IgnoreRule::parse(""), None);
+
+        let rule = IgnoreRule::parse("target/").unwrap();
+        assert!(rule.is_match(Path::new("target"), true));
+        assert!(rule.is_match(Path::new("sub/target"), true));
+        assert!(!rule.is_match(Path::new("target"), false));
+
+        let rule = IgnoreRule::parse("/Cargo.lock").unwrap();
+        assert!(rule.is_match(Path::new("Cargo.lock"), false));
+        assert!(!rule.is_match(Path::new("sub/Cargo.lock"), false));
+
+        let rule = IgnoreRule::parse("!keep.rlib").unwrap();
+        assert!(rule.is_negated());
+        assert!(rule.is_match(Path::new("keep.rlib"), false));
+    }
+}
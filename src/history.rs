@@ -0,0 +1,341 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::Write as _,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::BuildReport,
+    time::{format_duration, format_rfc3339},
+    xdg,
+};
+
+/// One successful `cw build`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub project: String,
+    pub started: String,
+    pub timestamp: u64,
+    pub profile: String,
+    pub wall_time_ms: u64,
+    pub crates_compiled: usize,
+    pub binaries: Vec<BinarySize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BinarySize {
+    pub name: String,
+    pub size: u64,
+}
+
+impl BuildRecord {
+    pub fn from_report(
+        project: impl AsRef<Path>,
+        started: SystemTime,
+        report: &BuildReport,
+    ) -> Self {
+        Self {
+            project: canonical(project.as_ref()).display().to_string(),
+            started: format_rfc3339(started),
+            timestamp: started
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            profile: report.profile.to_string(),
+            wall_time_ms: report.duration.as_millis() as u64,
+            crates_compiled: report.crates_compiled,
+            binaries: report
+                .binaries
+                .iter()
+                .map(|binary| BinarySize {
+                    name: binary.name.clone(),
+                    size: binary.size,
+                })
+                .collect(),
+        }
+    }
+    fn wall_time(&self) -> Duration {
+        Duration::from_millis(self.wall_time_ms)
+    }
+}
+
+/// Build records as JSON lines, one file per project.
+#[derive(Debug, Clone)]
+pub struct HistoryStore {
+    dir: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+    /// `$XDG_STATE_HOME/cw/history`.
+    pub fn from_state_dir() -> Option<Self> {
+        xdg::state_dir().map(|dir| Self::new(dir.join("history")))
+    }
+    pub fn record(&self, record: &BuildRecord) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.project_file(Path::new(&record.project)))?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+    /// Oldest first.
+    pub fn load(
+        &self,
+        project: impl AsRef<Path>,
+    ) -> Result<Vec<BuildRecord>, Box<dyn std::error::Error>> {
+        let path = self.project_file(&canonical(project.as_ref()));
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(Into::into))
+            .collect()
+    }
+    fn project_file(&self, project: &Path) -> PathBuf {
+        let name = project
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "root".to_string());
+        self.dir.join(format!(
+            "{}-{:016x}.jsonl",
+            name,
+            fnv1a(project.as_os_str().as_encoded_bytes())
+        ))
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub(crate) fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", size)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+fn percent_change(from: f64, to: f64) -> String {
+    if from == 0.0 {
+        return "-".to_string();
+    }
+    format!("{:+.1}%", (to - from) / from * 100.0)
+}
+
+/// Trends per profile, the slowest builds and binary size growth.
+pub fn render_stats(records: &[BuildRecord]) -> String {
+    const WINDOW: usize = 5;
+    const SLOWEST: usize = 5;
+    let mut out = String::new();
+    let Some(latest) = records.last() else {
+        return "no builds recorded yet\n".to_string();
+    };
+    let _ = writeln!(out, "project: {}", latest.project);
+    let _ = writeln!(out, "builds: {}\n", records.len());
+
+    let mut by_profile = BTreeMap::<&str, Vec<&BuildRecord>>::new();
+    for record in records {
+        by_profile.entry(&record.profile).or_default().push(record);
+    }
+    let _ = writeln!(
+        out,
+        "{:<10} {:>6} {:>9} {:>12} {:>12} {:>8}",
+        "profile", "builds", "last", "avg(last 5)", "avg(prev 5)", "trend"
+    );
+    for (profile, records) in &by_profile {
+        let average = |records: &[&BuildRecord]| {
+            (!records.is_empty()).then(|| {
+                records.iter().map(|r| r.wall_time_ms).sum::<u64>() as f64 / records.len() as f64
+            })
+        };
+        let split = records.len().saturating_sub(WINDOW);
+        let recent = average(&records[split..]);
+        let previous = average(&records[split.saturating_sub(WINDOW)..split]);
+        let show = |ms: Option<f64>| {
+            ms.map(|ms| format_duration(Duration::from_millis(ms as u64)))
+                .unwrap_or_else(|| "-".to_string())
+        };
+        let trend = match (previous, recent) {
+            (Some(previous), Some(recent)) => percent_change(previous, recent),
+            _ => "-".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "{:<10} {:>6} {:>9} {:>12} {:>12} {:>8}",
+            profile,
+            records.len(),
+            records
+                .last()
+                .map(|r| format_duration(r.wall_time()))
+                .unwrap_or_default(),
+            show(recent),
+            show(previous),
+            trend
+        );
+    }
+
+    let mut slowest = records.iter().collect::<Vec<_>>();
+    slowest.sort_by_key(|record| std::cmp::Reverse(record.wall_time_ms));
+    let _ = writeln!(out, "\nslowest builds:");
+    for record in slowest.into_iter().take(SLOWEST) {
+        let _ = writeln!(
+            out,
+            "  {}  {:<8} {:>9}  {} crates compiled",
+            record.started,
+            record.profile,
+            format_duration(record.wall_time()),
+            record.crates_compiled
+        );
+    }
+
+    let mut sizes = BTreeMap::<(&str, &str), Vec<u64>>::new();
+    for record in records {
+        for binary in &record.binaries {
+            sizes
+                .entry((&binary.name, &record.profile))
+                .or_default()
+                .push(binary.size);
+        }
+    }
+    if !sizes.is_empty() {
+        let _ = writeln!(out, "\nbinary sizes:");
+    }
+    for ((name, profile), sizes) in sizes {
+        let (first, last) = (sizes[0], sizes[sizes.len() - 1]);
+        let _ = writeln!(
+            out,
+            "  {} ({})  {} -> {}  ({}) over {} builds",
+            name,
+            profile,
+            format_size(first),
+            format_size(last),
+            percent_change(first as f64, last as f64),
+            sizes.len()
+        );
+    }
+    out
+}
+
+pub fn to_csv(records: &[BuildRecord]) -> String {
+    let mut out = "started,profile,wall_time_ms,crates_compiled,total_size,binaries\n".to_string();
+    for record in records {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            record.started,
+            record.profile,
+            record.wall_time_ms,
+            record.crates_compiled,
+            record.binaries.iter().map(|b| b.size).sum::<u64>(),
+            record
+                .binaries
+                .iter()
+                .map(|b| format!("{}:{}", b.name, b.size))
+                .collect::<Vec<_>>()
+                .join(";")
+        );
+    }
+    out
+}
+
+pub fn to_json(records: &[BuildRecord]) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn record(profile: &str, wall_time_ms: u64, size: u64) -> BuildRecord {
+        BuildRecord {
+            project: "/work/app".to_string(),
+            started: "2023-05-08T12:34:56Z".to_string(),
+            timestamp: 1_683_549_296,
+            profile: profile.to_string(),
+            wall_time_ms,
+            crates_compiled: 3,
+            binaries: vec![BinarySize {
+                name: "app".to_string(),
+                size,
+            }],
+        }
+    }
+    #[test]
+    fn プロジェクトごとに履歴を記録し読み出せる() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path());
+        let mut first = record("debug", 1000, 10);
+        first.project = canonical(project.path()).display().to_string();
+
+        store.record(&first).unwrap();
+        store.record(&first).unwrap();
+
+        assert_eq!(
+            store.load(project.path()).unwrap(),
+            vec![first.clone(), first]
+        );
+        assert!(store.load(dir.path()).unwrap().is_empty());
+    }
+    #[test]
+    fn 傾向と遅いビルドとサイズの増加を表示する() {
+        let records = (1..=10)
+            .map(|i| record("debug", i * 1000, 1024 * i))
+            .chain(std::iter::once(record("release", 60_000, 2048)))
+            .collect::<Vec<_>>();
+
+        let stats = render_stats(&records);
+
+        assert!(stats.contains("builds: 11\n"), "{}", stats);
+        assert!(
+            stats.contains("debug          10     10.0s         8.0s         3.0s  +166.7%"),
+            "{}",
+            stats
+        );
+        assert!(
+            stats.contains("2023-05-08T12:34:56Z  release      1m00s  3 crates compiled"),
+            "{}",
+            stats
+        );
+        assert!(
+            stats.contains("app (debug)  1.0KB -> 10.0KB  (+900.0%) over 10 builds"),
+            "{}",
+            stats
+        );
+    }
+    #[test]
+    fn csvとjsonで出力できる() {
+        let records = vec![record("debug", 1500, 10)];
+        assert_eq!(
+            to_csv(&records),
+            "started,profile,wall_time_ms,crates_compiled,total_size,binaries\n2023-05-08T12:34:56Z,debug,1500,3,10,app:10\n"
+        );
+        let parsed: Vec<BuildRecord> = serde_json::from_str(&to_json(&records).unwrap()).unwrap();
+        assert_eq!(parsed, records);
+    }
+}
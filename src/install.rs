@@ -0,0 +1,371 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    commands::FileWalker,
+    ledger::Ledger,
+    runner::{is_cancelled, Cancelled},
+};
+
+/// Copies build artifacts into the install directory.
+///
+/// Every file is first staged next to its destination and only renamed into place once all
+/// copies succeeded, so an interrupted install never leaves a half written binary behind.
+/// When a rename fails, the files already replaced by the same install are put back.
+#[derive(Debug, Clone)]
+pub struct Installer {
+    dir: PathBuf,
+    keep_previous: bool,
+    name_suffix: Option<String>,
+}
+
+impl Installer {
+    const STAGING_SUFFIX: &'static str = ".cw-tmp";
+    const BACKUP_SUFFIX: &'static str = ".cw-old";
+    const PREVIOUS_DIR: &'static str = "previous";
+    const STAGING_DIR: &'static str = "staging";
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            keep_previous: false,
+            name_suffix: None,
+        }
+    }
+    /// Keeps the files being replaced in `.cw/previous` so that `rollback` can restore them.
+    pub fn keep_previous(mut self, keep_previous: bool) -> Self {
+        self.keep_previous = keep_previous;
+        self
+    }
+    /// Installs `app` as `app@<suffix>` so that it sits next to the unsuffixed one.
+    pub fn name_suffix(mut self, name_suffix: Option<String>) -> Self {
+        self.name_suffix = name_suffix;
+        self
+    }
+    /// Name in the install directory of a file named `name`; the suffix goes before `.exe`.
+    pub fn installed_name(&self, name: &str) -> String {
+        match &self.name_suffix {
+            Some(suffix) => {
+                let exe = std::env::consts::EXE_SUFFIX;
+                let stem = name.strip_suffix(exe).filter(|_| !exe.is_empty());
+                match stem {
+                    Some(stem) => format!("{}@{}{}", stem, suffix, exe),
+                    None => format!("{}@{}", name, suffix),
+                }
+            }
+            None => name.to_string(),
+        }
+    }
+    pub fn install(&self, files: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let files = files
+            .iter()
+            .map(|file| {
+                let name = file
+                    .file_name()
+                    .ok_or_else(|| format!("not a file: {}", file.display()))?;
+                Ok((file.clone(), name.to_string_lossy().into_owned()))
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        self.install_as(&files)
+    }
+    /// Installs each file under the given name, e.g. binaries restored from the artifact cache.
+    pub fn install_as(
+        &self,
+        files: &[(PathBuf, String)],
+    ) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let staged = self.stage_as(files)?;
+        self.commit(&staged)
+    }
+    /// Copies each file next to where it is installed under the given name, without replacing
+    /// anything yet; returns the staged copies with their destinations for `commit`.
+    pub fn stage_as(
+        &self,
+        files: &[(PathBuf, String)],
+    ) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn std::error::Error>> {
+        let mut staged = Vec::new();
+        for (file, name) in files {
+            match self.stage(file, name) {
+                Ok(paths) => staged.push(paths),
+                Err(e) => {
+                    Self::discard(&staged);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(staged)
+    }
+    /// Where files that must keep their installed name while staged are prepared, e.g. debug
+    /// info a binary links to by file name. Created on demand.
+    pub fn staging_dir(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let dir = Ledger::metadata_dir(&self.dir).join(Self::STAGING_DIR);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+    /// Staged copies and backups an interrupted install left in `install_dir`.
+    pub fn leftovers(install_dir: impl AsRef<Path>) -> std::io::Result<Vec<PathBuf>> {
+        let install_dir = install_dir.as_ref();
+        if !install_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        FileWalker::new(install_dir)
+            .max_depth(2)
+            .glob(&format!("*{}", Self::STAGING_SUFFIX))
+            .glob(&format!("*{}", Self::BACKUP_SUFFIX))
+            .glob(&format!("{}/{}/*", Ledger::METADATA_DIR, Self::STAGING_DIR))
+            .walk()
+    }
+    /// Renames the staged files into place, all of them or none.
+    pub fn commit(
+        &self,
+        staged: &[(PathBuf, PathBuf)],
+    ) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        if is_cancelled() {
+            Self::discard(staged);
+            return Err(Box::new(Cancelled));
+        }
+        let mut replaced = Vec::new();
+        for (i, (staging, destination)) in staged.iter().enumerate() {
+            match self.replace(staging, destination) {
+                Ok(backup) => replaced.push((destination.clone(), backup)),
+                Err(e) => {
+                    Self::discard(&staged[i..]);
+                    let restored = match Self::restore(&replaced) {
+                        Ok(()) => String::new(),
+                        Err(restore) => format!(
+                            "; {} were already replaced and could not be restored: {}",
+                            replaced
+                                .iter()
+                                .map(|(path, _)| path.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                            restore
+                        ),
+                    };
+                    return Err(format!(
+                        "failed to install {}: {}{}",
+                        destination.display(),
+                        e,
+                        restored
+                    )
+                    .into());
+                }
+            }
+        }
+        for (_, backup) in replaced {
+            if let Some(backup) = backup {
+                let _ = std::fs::remove_file(backup);
+            }
+        }
+        Ok(staged
+            .iter()
+            .map(|(_, destination)| destination.clone())
+            .collect())
+    }
+    /// Renames `staging` to `destination`, returning a copy of the file it replaced.
+    fn replace(
+        &self,
+        staging: &Path,
+        destination: &Path,
+    ) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        if self.keep_previous {
+            self.keep(destination)?;
+        }
+        let backup = if destination.is_file() {
+            let mut name = destination.as_os_str().to_owned();
+            name.push(Self::BACKUP_SUFFIX);
+            let backup = PathBuf::from(name);
+            let _ = std::fs::remove_file(&backup);
+            if std::fs::hard_link(destination, &backup).is_err() {
+                std::fs::copy(destination, &backup)?;
+            }
+            Some(backup)
+        } else {
+            None
+        };
+        if let Err(e) = std::fs::rename(staging, destination) {
+            if let Some(backup) = &backup {
+                let _ = std::fs::remove_file(backup);
+            }
+            return Err(e.into());
+        }
+        Ok(backup)
+    }
+    /// Undoes `replace`, latest first.
+    fn restore(replaced: &[(PathBuf, Option<PathBuf>)]) -> std::io::Result<()> {
+        for (destination, backup) in replaced.iter().rev() {
+            match backup {
+                Some(backup) => std::fs::rename(backup, destination)?,
+                None => std::fs::remove_file(destination)?,
+            }
+        }
+        Ok(())
+    }
+    /// Puts back what `install` replaced, and removes files that did not exist before.
+    ///
+    /// Only meaningful when the install kept previous files.
+    pub fn rollback(&self, installed: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+        for path in installed {
+            let Some(previous) = self.previous_path(path) else {
+                continue;
+            };
+            if previous.is_file() {
+                std::fs::rename(&previous, path)?;
+            } else {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+    fn keep(&self, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(previous) = self.previous_path(destination) else {
+            return Ok(());
+        };
+        if previous.is_file() {
+            std::fs::remove_file(&previous)?;
+        }
+        if destination.is_file() {
+            std::fs::create_dir_all(self.previous_dir())?;
+            if std::fs::hard_link(destination, &previous).is_err() {
+                std::fs::copy(destination, &previous)?;
+            }
+        }
+        Ok(())
+    }
+    fn previous_dir(&self) -> PathBuf {
+        Ledger::metadata_dir(&self.dir).join(Self::PREVIOUS_DIR)
+    }
+    fn previous_path(&self, installed: &Path) -> Option<PathBuf> {
+        installed
+            .file_name()
+            .map(|name| self.previous_dir().join(name))
+    }
+    fn stage(
+        &self,
+        file: &Path,
+        name: &str,
+    ) -> Result<(PathBuf, PathBuf), Box<dyn std::error::Error>> {
+        let name = self.installed_name(name);
+        let destination = self.dir.join(&name);
+        let staging = self.dir.join(format!(".{}{}", name, Self::STAGING_SUFFIX));
+        std::fs::copy(file, &staging).map_err(|e| {
+            let _ = std::fs::remove_file(&staging);
+            format!(
+                "failed to copy {} to {}: {}",
+                file.display(),
+                staging.display(),
+                e
+            )
+        })?;
+        Ok((staging, destination))
+    }
+    /// Removes staged files that will not be committed.
+    pub fn discard(staged: &[(PathBuf, PathBuf)]) {
+        for (staging, _) in staged {
+            let _ = std::fs::remove_file(staging);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{ls_files, write_file};
+
+    use super::*;
+    #[test]
+    fn ファイルをインストール先にコピーし一時ファイルを残さない() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        write_file(from.path().join("app"), "new").unwrap();
+        write_file(to.path().join("app"), "old").unwrap();
+
+        let installed = Installer::new(to.path())
+            .install(&[from.path().join("app")])
+            .unwrap();
+
+        assert_eq!(installed, vec![to.path().join("app")]);
+        assert_eq!(
+            std::fs::read_to_string(to.path().join("app")).unwrap(),
+            "new"
+        );
+        assert_eq!(ls_files(to.path()).unwrap(), vec![to.path().join("app")]);
+    }
+    #[test]
+    fn 以前のファイルに戻せる() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        write_file(from.path().join("app"), "new").unwrap();
+        write_file(from.path().join("added"), "new").unwrap();
+        write_file(to.path().join("app"), "old").unwrap();
+        let installer = Installer::new(to.path()).keep_previous(true);
+
+        let installed = installer
+            .install(&[from.path().join("app"), from.path().join("added")])
+            .unwrap();
+        installer.rollback(&installed).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(to.path().join("app")).unwrap(),
+            "old"
+        );
+        assert!(!to.path().join("added").exists());
+    }
+    #[test]
+    fn 接尾辞を付けた名前でインストールする() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        write_file(from.path().join("app"), "branch").unwrap();
+        write_file(to.path().join("app"), "main").unwrap();
+
+        let installed = Installer::new(to.path())
+            .name_suffix(Some("feature-x".to_string()))
+            .install(&[from.path().join("app")])
+            .unwrap();
+
+        assert_eq!(installed, vec![to.path().join("app@feature-x")]);
+        assert_eq!(
+            std::fs::read_to_string(to.path().join("app")).unwrap(),
+            "main"
+        );
+        assert_eq!(
+            std::fs::read_to_string(to.path().join("app@feature-x")).unwrap(),
+            "branch"
+        );
+    }
+    #[test]
+    fn コピーに失敗した時は何もインストールしない() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        write_file(from.path().join("a"), "a").unwrap();
+
+        let result = Installer::new(to.path())
+            .install(&[from.path().join("a"), from.path().join("not-found")]);
+
+        assert!(result.is_err());
+        assert!(ls_files(to.path()).unwrap().is_empty());
+    }
+    #[test]
+    fn 置き換えに失敗した時はそれまでに置き換えたファイルを戻す() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            write_file(from.path().join(name), "new").unwrap();
+        }
+        write_file(to.path().join("a"), "old").unwrap();
+        // A directory that is not empty cannot be replaced by a file.
+        write_file(to.path().join("b/keep"), "").unwrap();
+
+        let err = Installer::new(to.path())
+            .install(&[
+                from.path().join("a"),
+                from.path().join("b"),
+                from.path().join("c"),
+            ])
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with(&format!(
+            "failed to install {}",
+            to.path().join("b").display()
+        )));
+        assert_eq!(std::fs::read_to_string(to.path().join("a")).unwrap(), "old");
+        assert_eq!(ls_files(to.path()).unwrap(), vec![to.path().join("a")]);
+        assert!(to.path().join("b/keep").is_file());
+    }
+}
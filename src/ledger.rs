@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// What cw installed into the install directory and where it came from.
+///
+/// Lives in `<install dir>/.cw/ledger.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// File name in the install directory.
+    pub name: String,
+    /// Name of the `[[bin]]` target that produced it.
+    pub bin: String,
+    /// Canonical path of the project directory.
+    pub project: String,
+    pub profile: String,
+    pub installed_at: String,
+    /// Commit the project was at, when it is a git checkout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Branch the project was on, when installed with `--branch-suffix`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// File name of the separated debug info, when installed stripped with `--keep-debug`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_file: Option<String>,
+    /// SHA-256 of the installed file, checked by `cw verify`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// ed25519 signature of `sha256` by the local signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// `--env-preset` the binary was built with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_preset: Option<String>,
+    /// Completion scripts and man page written for the binary, removed with it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generated: Vec<PathBuf>,
+}
+
+impl Ledger {
+    pub const METADATA_DIR: &'static str = ".cw";
+    const FILE_NAME: &'static str = "ledger.json";
+    pub fn metadata_dir(install_dir: impl AsRef<Path>) -> PathBuf {
+        install_dir.as_ref().join(Self::METADATA_DIR)
+    }
+    pub fn load(install_dir: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = Self::path(install_dir.as_ref());
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+    pub fn save(&self, install_dir: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path(install_dir.as_ref());
+        let dir = Self::metadata_dir(install_dir);
+        std::fs::create_dir_all(&dir)?;
+        let staging = dir.join(format!("{}.cw-tmp", Self::FILE_NAME));
+        std::fs::write(&staging, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(staging, path)?;
+        Ok(())
+    }
+    pub fn entries(&self) -> &[LedgerEntry] {
+        self.entries.as_slice()
+    }
+    pub fn get(&self, name: &str) -> Option<&LedgerEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+    /// Replaces the entry of the same installed name.
+    pub fn record(&mut self, entry: LedgerEntry) {
+        self.remove(&entry.name);
+        self.entries.push(entry);
+        self.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    pub fn remove(&mut self, name: &str) -> Option<LedgerEntry> {
+        let index = self.entries.iter().position(|entry| entry.name == name)?;
+        Some(self.entries.remove(index))
+    }
+    fn path(install_dir: &Path) -> PathBuf {
+        Self::metadata_dir(install_dir).join(Self::FILE_NAME)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn entry(name: &str, project: &str) -> LedgerEntry {
+        LedgerEntry {
+            name: name.to_string(),
+            bin: name.to_string(),
+            project: project.to_string(),
+            profile: "release".to_string(),
+            installed_at: "2023-05-08T12:34:56Z".to_string(),
+            commit: None,
+            branch: None,
+            features: Vec::new(),
+            debug_file: None,
+            sha256: None,
+            signature: None,
+            env_preset: None,
+            generated: Vec::new(),
+        }
+    }
+    #[test]
+    fn 台帳を保存し読み込める() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Ledger::load(dir.path()).unwrap(), Ledger::default());
+
+        let mut ledger = Ledger::default();
+        ledger.record(entry("b", "/work/b"));
+        ledger.record(entry("a", "/work/a"));
+        ledger.record(entry("a", "/work/a2"));
+        ledger.save(dir.path()).unwrap();
+
+        let loaded = Ledger::load(dir.path()).unwrap();
+        assert_eq!(
+            loaded.entries(),
+            &[entry("a", "/work/a2"), entry("b", "/work/b")]
+        );
+        assert!(dir.path().join(".cw/ledger.json").is_file());
+    }
+}
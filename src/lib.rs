@@ -1,4 +1,39 @@
+pub mod batch;
+pub mod bloat;
 pub mod build;
+pub mod build_info;
+pub mod cache;
+mod cargo_messages;
 mod cargo_toml;
+mod checksum;
 pub mod commands;
+pub mod completions;
+pub mod config;
+mod deb;
+mod diagnostics;
+pub mod env_preset;
+mod git;
+mod glob;
+pub mod history;
+pub mod install;
+pub mod ledger;
+pub mod list;
+pub mod lock;
+pub mod metadata;
 pub mod new;
+pub mod package;
+pub mod path_check;
+pub mod prune;
+pub mod release;
+pub mod reproducible;
+mod rpm;
+pub mod run_log;
+pub mod runner;
+mod rustc;
+pub mod sign;
+pub mod smoke;
+pub mod strip;
+mod time;
+pub mod toolbox;
+pub mod verify;
+mod xdg;
@@ -0,0 +1,90 @@
+use std::path::Path;
+
+use crate::{git::short, ledger::Ledger};
+
+/// The binaries cw installed into `install_dir` and the commits they were built from.
+pub fn list(install_dir: impl AsRef<Path>) -> Result<String, Box<dyn std::error::Error>> {
+    let ledger = Ledger::load(install_dir)?;
+    if ledger.entries().is_empty() {
+        return Ok("nothing installed by cw\n".to_string());
+    }
+    let rows = ledger
+        .entries()
+        .iter()
+        .map(|entry| {
+            [
+                entry.name.clone(),
+                entry
+                    .commit
+                    .as_deref()
+                    .map(short)
+                    .unwrap_or("-")
+                    .to_string(),
+                entry.profile.clone(),
+                entry.installed_at.clone(),
+                entry.project.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["NAME", "COMMIT", "PROFILE", "INSTALLED", "PROJECT"].map(String::from);
+    let width = |column: usize| {
+        std::iter::once(&header)
+            .chain(rows.iter())
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or_default()
+    };
+    let widths = [width(0), width(1), width(2), width(3)];
+    Ok(std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| {
+            format!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {}\n",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledger::{tests::entry, LedgerEntry};
+
+    use super::*;
+    #[test]
+    fn インストールしたバイナリとコミットを一覧にする() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(list(dir.path()).unwrap(), "nothing installed by cw\n");
+
+        let mut ledger = Ledger::default();
+        ledger.record(LedgerEntry {
+            commit: Some("0123456789abcdef0123".to_string()),
+            ..entry("app", "/work/app")
+        });
+        ledger.record(LedgerEntry {
+            commit: Some("fedcba9876543210fedc".to_string()),
+            branch: Some("feature-x".to_string()),
+            ..entry("app@feature-x", "/work/app")
+        });
+        ledger.record(entry("tool", "/work/tool"));
+        ledger.save(dir.path()).unwrap();
+
+        assert_eq!(
+            list(dir.path()).unwrap(),
+            "\
+NAME           COMMIT        PROFILE  INSTALLED             PROJECT
+app            0123456789ab  release  2023-05-08T12:34:56Z  /work/app
+app@feature-x  fedcba987654  release  2023-05-08T12:34:56Z  /work/app
+tool           -             release  2023-05-08T12:34:56Z  /work/tool
+"
+        );
+    }
+}
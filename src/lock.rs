@@ -0,0 +1,142 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{Read, Seek, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    ledger::Ledger,
+    runner::{is_cancelled, Cancelled},
+};
+
+/// Advisory lock on the install directory's cw metadata, held while installing.
+///
+/// The holder writes its pid into the lock file so others can tell who they wait for.
+/// The lock is released when dropped.
+#[derive(Debug)]
+pub struct InstallLock {
+    _file: File,
+}
+
+#[derive(Debug)]
+pub struct LockHeld {
+    pid: Option<u32>,
+}
+impl Display for LockHeld {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "install directory is locked by pid {}", pid),
+            None => write!(f, "install directory is locked by another cw"),
+        }
+    }
+}
+impl std::error::Error for LockHeld {}
+
+impl InstallLock {
+    const FILE_NAME: &'static str = "lock";
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    /// Fails with `LockHeld` right away when `wait` is false and someone else holds the lock.
+    pub fn acquire(
+        install_dir: impl AsRef<Path>,
+        wait: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = Ledger::metadata_dir(install_dir);
+        std::fs::create_dir_all(&dir)?;
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(Self::FILE_NAME))?;
+        let mut announced = false;
+        while !flock::try_lock(&file)? {
+            let held = LockHeld {
+                pid: Self::holder(&mut file),
+            };
+            if !wait {
+                return Err(Box::new(held));
+            }
+            if !announced {
+                match held.pid {
+                    Some(pid) => eprintln!("cw: waiting for lock held by pid {}", pid),
+                    None => eprintln!("cw: waiting for lock held by another cw"),
+                }
+                announced = true;
+            }
+            if is_cancelled() {
+                return Err(Box::new(Cancelled));
+            }
+            std::thread::sleep(Self::POLL_INTERVAL);
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Self { _file: file })
+    }
+    fn holder(file: &mut File) -> Option<u32> {
+        let mut content = String::new();
+        file.rewind().ok()?;
+        file.read_to_string(&mut content).ok()?;
+        content.trim().parse().ok()
+    }
+}
+
+#[cfg(unix)]
+mod flock {
+    use std::{fs::File, os::unix::io::AsRawFd};
+
+    pub fn try_lock(file: &File) -> std::io::Result<bool> {
+        // SAFETY: the descriptor stays open for the duration of the call.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(true);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() == std::io::ErrorKind::WouldBlock {
+            Ok(false)
+        } else {
+            Err(error)
+        }
+    }
+}
+#[cfg(not(unix))]
+mod flock {
+    use std::fs::File;
+
+    pub fn try_lock(_file: &File) -> std::io::Result<bool> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn 他がロックを持っている時はwaitしないなら即座に失敗する() {
+        let install_dir = tempfile::tempdir().unwrap();
+        let lock = InstallLock::acquire(install_dir.path(), false).unwrap();
+
+        let err = InstallLock::acquire(install_dir.path(), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("install directory is locked by pid {}", std::process::id())
+        );
+
+        drop(lock);
+        assert!(InstallLock::acquire(install_dir.path(), false).is_ok());
+    }
+    #[test]
+    fn ロックが解放されるまで待つ() {
+        let install_dir = tempfile::tempdir().unwrap();
+        let lock = InstallLock::acquire(install_dir.path(), false).unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(lock);
+        });
+
+        assert!(InstallLock::acquire(install_dir.path(), true).is_ok());
+        release.join().unwrap();
+    }
+}
@@ -1,20 +1,159 @@
-use clap::{Parser, Subcommand};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use cw::{
-    build::{BuildMode, CargoBuildWrapper},
+    batch::{find_project_dir, render_status_table, ProjectOutcome},
+    bloat::BloatReport,
+    build::{BuildMode, BuildReport, CargoBuildWrapper},
+    build_info::BuildInfo,
+    cache::ArtifactCache,
+    completions::Completions,
+    config::{parse_duration, parse_size, CacheConfig, CwConfig},
+    env_preset::EnvPreset,
+    history::{render_stats, to_csv, to_json, BuildRecord, HistoryStore},
+    list::list,
+    metadata::CargoMetadata,
     new::cmd::{CargoProjectCreator, RustNewProjectOptions},
+    package::{ArchiveFormat, Packager},
+    path_check::check_path,
+    prune::Pruner,
+    release::{Bump, Release},
+    run_log::{LogStore, LoggingCommandRunner, RunLog},
+    runner::{is_cancelled, SystemCommandRunner},
+    sign::{PublicKey, SignKey},
+    smoke::SmokeTest,
+    strip::Strip,
+    toolbox::{Toolbox, ToolboxChecker},
+    verify::verify,
 };
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cw = Cw::parse();
-    match cw.sub {
-        Sub::Build { release } => {
-            let mode = if release {
+    let config = CwConfig::load(".")?;
+    if let Sub::Log { last, project, id } = cw.sub {
+        return show_logs(&config, last, project, id);
+    }
+    if let Sub::Stats { project, format } = cw.sub {
+        return show_stats(project, format);
+    }
+    if let Sub::List = cw.sub {
+        print!("{}", list(std::env::var("RUST_BIN_PATH")?)?);
+        return Ok(());
+    }
+    if let Sub::Which { bin, json } = &cw.sub {
+        let info = BuildInfo::load(std::env::var("RUST_BIN_PATH")?, bin)?;
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        } else {
+            print!("{}", info);
+        }
+        return Ok(());
+    }
+    let log = RunLog::start(std::env::args(), ".");
+    if let Sub::Run { build, args } = cw.sub {
+        let (installed, result) = match build_for_run(&build, &config, &log) {
+            Ok(installed) => (Some(installed), Ok(())),
+            Err(e) => (None, Err(e)),
+        };
+        save_log(&config, &log, &result);
+        result?;
+        return exec(&installed.unwrap_or_default(), &args);
+    }
+    let result = run(cw.sub, &config, &log);
+    save_log(&config, &log, &result);
+    result
+}
+
+fn save_log(config: &CwConfig, log: &RunLog, result: &Result<(), Box<dyn std::error::Error>>) {
+    if config.log.enabled {
+        if let Some(store) = LogStore::from_config(&config.log) {
+            if let Err(e) = store.save(log, result) {
+                eprintln!("cw: failed to write log: {}", e);
+            }
+        }
+    }
+}
+
+fn run(sub: Sub, config: &CwConfig, log: &RunLog) -> Result<(), Box<dyn std::error::Error>> {
+    match sub {
+        Sub::Build {
+            build,
+            reproducible_check: true,
+        } => check_reproducible(&build, config, log),
+        Sub::Build { build, .. } => build_project(".", &build, config, log).map(|_| ()),
+        Sub::Install { paths, build } => install_projects(&paths, &build, log),
+        Sub::Sync { check, file } => sync_toolbox(check, file, log),
+        Sub::Package {
+            debug,
+            features,
+            bins,
+            out_dir,
+            formats,
+            deb,
+            rpm,
+        } => {
+            let mut package = config.package.clone();
+            if !formats.is_empty() {
+                package.formats = formats;
+            }
+            let out_dir = out_dir.unwrap_or_else(|| package.out_dir.clone());
+            let mode = if debug {
+                BuildMode::Debug
+            } else {
                 BuildMode::Release
+            };
+            let runner = LoggingCommandRunner::new(SystemCommandRunner::new(), log);
+            let report = CargoBuildWrapper::with_runner(mode, "", &runner)
+                .features(features)
+                .bins(bins)
+                .timeout(config.build.timeout)
+                .deny_warnings(config.build.deny_warnings)
+                .compile()?;
+            if report.binaries.is_empty() {
+                return Err("nothing to package: cargo built no bin targets".into());
+            }
+            let packager = Packager::for_project(&runner, ".", &report.binaries, package)?;
+            let mut written = Vec::new();
+            if deb || rpm {
+                let files = packager.system_files(".", &report.binaries)?;
+                if deb {
+                    written.push(packager.write_deb(&files, &out_dir)?);
+                }
+                if rpm {
+                    written.push(packager.write_rpm(&files, &out_dir)?);
+                }
             } else {
-                BuildMode::Debug
+                let files = packager.files(".", &report.binaries)?;
+                written = packager.write(&files, &out_dir)?;
+            }
+            for path in written {
+                println!("{}", path.display());
+            }
+            Ok(())
+        }
+        Sub::Release { bump, dry_run } => {
+            let args = BuildArgs {
+                release: true,
+                ..Default::default()
             };
-            let wrapper = CargoBuildWrapper::from_env(mode).unwrap();
-            wrapper.build()
+            let plan = Release::new(
+                ".",
+                LoggingCommandRunner::new(SystemCommandRunner::new(), log),
+            )
+            .dry_run(dry_run)
+            .run(&bump, |_| {
+                build_project(".", &args, config, log).map(|_| ())
+            })?;
+            if dry_run {
+                println!("would release {}", plan);
+            } else {
+                println!("released {}", plan);
+            }
+            Ok(())
         }
         Sub::New {
             cli,
@@ -30,9 +169,458 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 remote_client,
                 docker_file,
             };
-            CargoProjectCreator::create_project_from_options(options)
+            CargoProjectCreator::create_project_from_options_with_runner(
+                options,
+                LoggingCommandRunner::new(SystemCommandRunner::new(), log),
+            )
+        }
+        Sub::Prune { yes, no_wait } => {
+            let install_dir = std::env::var("RUST_BIN_PATH")?;
+            let pruner = Pruner::new(
+                &install_dir,
+                LoggingCommandRunner::new(SystemCommandRunner::new(), log),
+            )
+            .wait_for_lock(!no_wait);
+            let stale = pruner.find_stale()?;
+            let leftovers = pruner.find_leftovers()?;
+            if stale.is_empty() && leftovers.is_empty() {
+                println!("nothing to prune");
+                return Ok(());
+            }
+            for binary in &stale {
+                println!(
+                    "{}  ({}, from {})",
+                    binary.path.display(),
+                    binary.reason,
+                    binary.entry.project
+                );
+            }
+            for path in &leftovers {
+                println!("{}  (left by an interrupted install)", path.display());
+            }
+            if !yes
+                && !confirm(&format!(
+                    "remove {} binaries and {} leftover files?",
+                    stale.len(),
+                    leftovers.len()
+                ))?
+            {
+                return Ok(());
+            }
+            pruner.remove(&stale)?;
+            pruner.remove_leftovers(&leftovers)?;
+            for binary in &stale {
+                log.record_removal(&binary.path);
+            }
+            Ok(())
+        }
+        Sub::Keygen => {
+            let path = sign_key_path(config)?;
+            let key = SignKey::generate(&path)?;
+            println!("wrote {}", path.display());
+            println!("public key: {}", key.public_key());
+            Ok(())
+        }
+        Sub::Bloat {
+            bin,
+            debug,
+            features,
+            top,
+            json,
+        } => {
+            let mode = if debug {
+                BuildMode::Debug
+            } else {
+                BuildMode::Release
+            };
+            let runner = LoggingCommandRunner::new(SystemCommandRunner::new(), log);
+            let built = CargoBuildWrapper::with_runner(mode, "", &runner)
+                .features(features)
+                .bins(bin.into_iter().collect())
+                .timeout(config.build.timeout)
+                .compile()?;
+            let install_dir = std::env::var_os("RUST_BIN_PATH").map(PathBuf::from);
+            let reports = built
+                .binaries
+                .iter()
+                .map(|binary| BloatReport::new(binary, install_dir.as_deref(), top))
+                .collect::<Result<Vec<_>, _>>()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&reports)?);
+            } else {
+                for report in reports {
+                    print!("{}", report);
+                }
+            }
+            Ok(())
+        }
+        Sub::Cache {
+            command: CacheCommand::Gc { max_size },
+        } => {
+            let cache = ArtifactCache::from_config(&CacheConfig {
+                enabled: true,
+                dir: config.cache.dir.clone(),
+                max_size: None,
+            })
+            .ok_or("no cache directory: set HOME or XDG_CACHE_HOME")?;
+            let max_size = max_size
+                .or(config.cache.max_size)
+                .unwrap_or(CacheConfig::DEFAULT_MAX_SIZE);
+            println!("{}", cache.gc(max_size)?);
+            Ok(())
+        }
+        Sub::Verify { bin } => {
+            let install_dir = std::env::var("RUST_BIN_PATH")?;
+            let public_path = SignKey::public_path(sign_key_path(config)?);
+            let public_key = if public_path.is_file() {
+                Some(PublicKey::load(&public_path)?)
+            } else {
+                None
+            };
+            let verifications = verify(
+                &install_dir,
+                bin.as_deref(),
+                public_key.as_ref(),
+                config.sign.enabled,
+            )?;
+            for verification in &verifications {
+                println!("{}", verification);
+            }
+            let tampered = verifications
+                .iter()
+                .filter(|verification| verification.verdict.is_tampered())
+                .count();
+            if tampered > 0 {
+                return Err(format!(
+                    "{} of {} binaries do not match what cw installed",
+                    tampered,
+                    verifications.len()
+                )
+                .into());
+            }
+            Ok(())
+        }
+        Sub::Log { .. } | Sub::Stats { .. } | Sub::List | Sub::Which { .. } | Sub::Run { .. } => {
+            unreachable!("handled in main")
+        }
+    }
+}
+
+fn check_reproducible(
+    args: &BuildArgs,
+    config: &CwConfig,
+    log: &RunLog,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mode = if args.release {
+        BuildMode::Release
+    } else {
+        BuildMode::Debug
+    };
+    let runner = LoggingCommandRunner::new(SystemCommandRunner::new(), log);
+    let reproducibility = CargoBuildWrapper::with_runner(mode, "", &runner)
+        .features(args.features.clone())
+        .bins(args.bins.clone())
+        .timeout(args.timeout.or(config.build.timeout))
+        .env_preset(env_preset(args, config)?)
+        .reproducible_check()?;
+    print!("{}", reproducibility);
+    if !reproducibility.is_reproducible() {
+        return Err("the two builds differ".into());
+    }
+    Ok(())
+}
+
+fn env_preset(
+    args: &BuildArgs,
+    config: &CwConfig,
+) -> Result<Option<EnvPreset>, Box<dyn std::error::Error>> {
+    let name = args
+        .env_preset
+        .as_ref()
+        .or(config.build.env_preset.as_ref());
+    Ok(name
+        .map(|name| EnvPreset::find(name, &config.env_presets))
+        .transpose()?)
+}
+
+fn build_project(
+    project_dir: impl AsRef<Path>,
+    args: &BuildArgs,
+    config: &CwConfig,
+    log: &RunLog,
+) -> Result<BuildReport, Box<dyn std::error::Error>> {
+    let project_dir = project_dir.as_ref();
+    let mode = if args.release {
+        BuildMode::Release
+    } else {
+        BuildMode::Debug
+    };
+    let runner = SystemCommandRunner::new()
+        .forward_signals(config.build.forward_signals && !args.no_forward_signals);
+    let copy_dir = std::env::var("RUST_BIN_PATH")?;
+    let wrapper =
+        CargoBuildWrapper::with_runner(mode, &copy_dir, LoggingCommandRunner::new(runner, log))
+            .project_dir(project_dir)
+            .features(args.features.clone())
+            .bins(args.bins.clone())
+            .timeout(args.timeout.or(config.build.timeout))
+            .wait_for_lock(!args.no_wait)
+            .deny_warnings(args.deny_warnings || config.build.deny_warnings)
+            .env_preset(env_preset(args, config)?)
+            .branch_suffix(args.branch_suffix)
+            .strip(
+                (args.strip || args.keep_debug || config.strip.enabled)
+                    .then(|| Strip::new().keep_debug(args.keep_debug || config.strip.keep_debug)),
+            )
+            .smoke_test(
+                (args.smoke || config.smoke.enabled).then(|| SmokeTest::from_config(&config.smoke)),
+            )
+            .sign_key(if config.sign.enabled {
+                Some(SignKey::load(sign_key_path(config)?)?)
+            } else {
+                None
+            })
+            .completions(
+                (args.completions || config.completions.enabled)
+                    .then(|| Completions::from_config(&config.completions)),
+            )
+            .cache(if args.no_cache {
+                None
+            } else {
+                ArtifactCache::from_config(&config.cache)
+            });
+    let started = SystemTime::now();
+    let report = wrapper.build()?;
+    if report.cached {
+        eprintln!("cw: installed from the artifact cache without running cargo");
+    }
+    for path in &report.installed {
+        log.record_install(path);
+    }
+    for stripped in &report.stripped {
+        eprintln!("cw: {}", stripped);
+    }
+    if let Some(path_var) = std::env::var_os("PATH") {
+        for warning in check_path(&copy_dir, &report.installed, &path_var) {
+            eprintln!("cw: warning: {}", warning);
+        }
+    }
+    if let Some(history) = HistoryStore::from_state_dir().filter(|_| !report.cached) {
+        if let Err(e) = history.record(&BuildRecord::from_report(project_dir, started, &report)) {
+            eprintln!("cw: failed to record build history: {}", e);
+        }
+    }
+    Ok(report)
+}
+
+/// Builds and installs only the bin `cargo run` would run, returning its installed path.
+fn build_for_run(
+    args: &BuildArgs,
+    config: &CwConfig,
+    log: &RunLog,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if args.bins.len() > 1 {
+        return Err("cw run takes at most one --bin".into());
+    }
+    let metadata = CargoMetadata::load(
+        &LoggingCommandRunner::new(SystemCommandRunner::new(), log),
+        ".",
+    )?;
+    let bin = metadata.run_target(".", args.bins.first().map(String::as_str))?;
+    let args = BuildArgs {
+        bins: vec![bin.clone()],
+        ..args.clone()
+    };
+    let report = build_project(".", &args, config, log)?;
+    report
+        .installed_bin(&bin)
+        .map(Path::to_path_buf)
+        .ok_or_else(|| format!("cargo did not build {}", bin).into())
+}
+
+/// Replaces cw with the installed binary, so that its exit code and signals are its own.
+#[cfg(unix)]
+fn exec(path: &Path, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::process::CommandExt;
+    let e = std::process::Command::new(path).args(args).exec();
+    Err(format!("failed to run {}: {}", path.display(), e).into())
+}
+
+#[cfg(not(unix))]
+fn exec(path: &Path, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let status = std::process::Command::new(path)
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to run {}: {}", path.display(), e))?;
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+fn sign_key_path(config: &CwConfig) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    config
+        .sign
+        .key
+        .clone()
+        .or_else(SignKey::default_path)
+        .ok_or_else(|| "could not find the config directory for the signing key".into())
+}
+
+/// Builds every project with its own `cw.toml`, carrying on after failures.
+fn install_projects(
+    paths: &[PathBuf],
+    args: &BuildArgs,
+    log: &RunLog,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut outcomes = Vec::new();
+    for path in paths {
+        eprintln!("cw: installing {}", path.display());
+        let result = find_project_dir(path).and_then(|project| {
+            let config = CwConfig::load(&project)?;
+            build_project(project, args, &config, log)
+        });
+        if let Err(e) = &result {
+            eprintln!("cw: {}: {}", path.display(), e);
         }
+        outcomes.push(ProjectOutcome {
+            project: path.clone(),
+            result: result.map_err(|e| e.to_string()),
+        });
+        if is_cancelled() {
+            break;
+        }
+    }
+    print!("{}", render_status_table(&outcomes));
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+    if failed > 0 {
+        return Err(format!("{} of {} projects failed", failed, outcomes.len()).into());
+    }
+    Ok(())
+}
+
+fn sync_toolbox(
+    check: bool,
+    file: Option<PathBuf>,
+    log: &RunLog,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = file
+        .or_else(|| Toolbox::find("."))
+        .ok_or("no toolbox.toml in the current directory or the config directory")?;
+    let toolbox = Toolbox::load(&file)?;
+    let install_dir = std::env::var("RUST_BIN_PATH")?;
+    let statuses = ToolboxChecker::new(
+        &install_dir,
+        LoggingCommandRunner::new(SystemCommandRunner::new(), log),
+    )
+    .check(&toolbox)?;
+    let drifted = statuses
+        .iter()
+        .filter(|status| !status.drifts.is_empty())
+        .collect::<Vec<_>>();
+    for status in &drifted {
+        for drift in &status.drifts {
+            println!("{}: {}", status.tool.path.display(), drift);
+        }
+    }
+    if drifted.is_empty() {
+        println!("all {} tools are up to date", statuses.len());
+        return Ok(());
+    }
+    if check {
+        return Err(format!("{} of {} tools have drifted", drifted.len(), statuses.len()).into());
+    }
+    let mut outcomes = Vec::new();
+    for status in drifted {
+        let tool = &status.tool;
+        eprintln!("cw: syncing {}", tool.path.display());
+        let result = match status.drifts.iter().find(|drift| !drift.is_fixable()) {
+            Some(drift) => Err(drift.to_string().into()),
+            None => BuildMode::from_profile(&tool.profile)
+                .ok_or_else(|| format!("unsupported profile: {}", tool.profile).into())
+                .and_then(|mode| {
+                    let args = BuildArgs {
+                        release: matches!(mode, BuildMode::Release),
+                        features: tool.features.clone(),
+                        bins: tool.bins.clone(),
+                        ..Default::default()
+                    };
+                    build_project(&tool.path, &args, &CwConfig::load(&tool.path)?, log)
+                }),
+        };
+        outcomes.push(ProjectOutcome {
+            project: tool.path.clone(),
+            result: result.map_err(|e: Box<dyn std::error::Error>| e.to_string()),
+        });
+        if is_cancelled() {
+            break;
+        }
+    }
+    print!("{}", render_status_table(&outcomes));
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+    if failed > 0 {
+        return Err(format!("{} of {} tools failed to sync", failed, outcomes.len()).into());
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> std::io::Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn show_logs(
+    config: &CwConfig,
+    last: bool,
+    project: Option<PathBuf>,
+    id: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store = LogStore::from_config(&config.log).ok_or("could not find the state directory")?;
+    if let Some(id) = id {
+        print!("{}", store.read(&id)?);
+        return Ok(());
     }
+    let summaries = match project {
+        Some(project) => store.list_project(project)?,
+        None => store.list()?,
+    };
+    if last {
+        let summary = summaries.last().ok_or("no logs yet")?;
+        print!("{}", std::fs::read_to_string(&summary.path)?);
+        return Ok(());
+    }
+    for summary in summaries {
+        println!(
+            "{}  {}  {:>7}  {}  {}  ({})",
+            summary.id,
+            summary.started,
+            summary.duration,
+            summary.status,
+            summary.command,
+            summary.project
+        );
+    }
+    Ok(())
+}
+
+fn show_stats(
+    project: Option<PathBuf>,
+    format: StatsFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let history = HistoryStore::from_state_dir().ok_or("could not find the state directory")?;
+    let records = history.load(project.unwrap_or_else(|| PathBuf::from(".")))?;
+    match format {
+        StatsFormat::Text => print!("{}", render_stats(&records)),
+        StatsFormat::Csv => print!("{}", to_csv(&records)),
+        StatsFormat::Json => println!("{}", to_json(&records)?),
+    }
+    Ok(())
 }
 
 #[derive(Parser)]
@@ -44,8 +632,27 @@ struct Cw {
 #[derive(Subcommand)]
 enum Sub {
     Build {
-        #[clap(short, long)]
-        release: bool,
+        #[clap(flatten)]
+        build: BuildArgs,
+        /// Build twice in a normalized environment and compare the binaries instead of installing
+        #[clap(long)]
+        reproducible_check: bool,
+    },
+    /// Build and install the bin `cargo run` would pick, then run the installed copy
+    Run {
+        #[clap(flatten)]
+        build: BuildArgs,
+        /// Arguments for the binary, after `--`
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+    /// Build and install other local projects, each with its own `cw.toml`
+    Install {
+        /// Project directories or their `Cargo.toml`
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+        #[clap(flatten)]
+        build: BuildArgs,
     },
     New {
         name: String,
@@ -58,4 +665,174 @@ enum Sub {
         #[clap(short, long)]
         docker_file: bool,
     },
+    /// List past runs, or show one of them
+    Log {
+        /// Show the most recent log
+        #[clap(long)]
+        last: bool,
+        /// Only logs of runs in this project directory
+        #[clap(long)]
+        project: Option<PathBuf>,
+        /// Log id, or a unique prefix of it
+        #[clap(conflicts_with = "last")]
+        id: Option<String>,
+    },
+    /// Remove installed binaries whose bin target or project is gone
+    Prune {
+        /// Remove without asking
+        #[clap(short, long)]
+        yes: bool,
+        /// Fail instead of waiting when another cw is installing
+        #[clap(long)]
+        no_wait: bool,
+    },
+    /// Install the tools of `toolbox.toml` that are missing or outdated
+    Sync {
+        /// Only report drift, exiting with an error when there is any
+        #[clap(long)]
+        check: bool,
+        /// The toolbox file, `./toolbox.toml` or the one in the config directory by default
+        #[clap(long)]
+        file: Option<PathBuf>,
+    },
+    /// Bump the version, commit and tag it, then build and install the release
+    Release {
+        /// `major`, `minor`, `patch` or an exact version such as `1.4.0`
+        bump: Bump,
+        /// Show the new version and tag without changing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Build a release and pack the binaries, README, LICENSE and completions into archives
+    Package {
+        /// Package a debug build instead
+        #[clap(long)]
+        debug: bool,
+        /// Cargo features to enable, comma separated
+        #[clap(long, value_delimiter = ',')]
+        features: Vec<String>,
+        /// Only package this bin target
+        #[clap(long = "bin")]
+        bins: Vec<String>,
+        /// Where to write the archives, `target/dist` by default
+        #[clap(long)]
+        out_dir: Option<PathBuf>,
+        /// `tar.gz` or `zip`, both by default or as set in `cw.toml`
+        #[clap(long = "format", conflicts_with_all = ["deb", "rpm"])]
+        formats: Vec<ArchiveFormat>,
+        /// Build a `.deb` with the binaries in `/usr/bin` instead of archives
+        #[clap(long)]
+        deb: bool,
+        /// Build an `.rpm` with the binaries in `/usr/bin` instead of archives
+        #[clap(long)]
+        rpm: bool,
+    },
+    /// Create the ed25519 key that signs installed binaries when `sign.enabled` is set
+    Keygen,
+    /// List the binaries cw installed and the commits they came from
+    List,
+    /// Show how an installed binary was built
+    Which {
+        /// Name of the binary in the install directory
+        bin: String,
+        /// Print the build metadata as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Manage the artifact cache of built commits
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Re-hash installed binaries and check them against what cw recorded
+    Verify {
+        /// Only this installed binary
+        bin: Option<String>,
+    },
+    /// Break the size of a release binary down by crate and function
+    Bloat {
+        /// The bin target to analyze, all of them by default
+        bin: Option<String>,
+        /// Analyze the debug build instead
+        #[clap(long)]
+        debug: bool,
+        /// Cargo features to enable, comma separated
+        #[clap(long, value_delimiter = ',')]
+        features: Vec<String>,
+        /// How many of the largest functions to list
+        #[clap(long, default_value_t = 20)]
+        top: usize,
+        /// Print JSON instead of tables
+        #[clap(long)]
+        json: bool,
+    },
+    /// Show build time and binary size trends of a project
+    Stats {
+        /// Project directory, the current one by default
+        project: Option<PathBuf>,
+        #[clap(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+}
+
+#[derive(Args, Clone, Default)]
+struct BuildArgs {
+    #[clap(short, long)]
+    release: bool,
+    /// Cargo features to enable, comma separated
+    #[clap(long, value_delimiter = ',')]
+    features: Vec<String>,
+    /// Only build and install this bin target
+    #[clap(long = "bin")]
+    bins: Vec<String>,
+    /// Kill `cargo build` after this long, e.g. `90s`, `10m`
+    #[clap(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+    /// Let the terminal deliver Ctrl-C to cargo directly
+    #[clap(long)]
+    no_forward_signals: bool,
+    /// Fail instead of waiting when another cw is installing
+    #[clap(long)]
+    no_wait: bool,
+    /// Do not install when the build has warnings
+    #[clap(long)]
+    deny_warnings: bool,
+    /// Always run cargo, even when the commit is in the artifact cache
+    #[clap(long)]
+    no_cache: bool,
+    /// Install as `<bin>@<branch>` next to the main build
+    #[clap(long)]
+    branch_suffix: bool,
+    /// Build with the named env preset: native, small, debuginfo or one from `cw.toml`
+    #[clap(long)]
+    env_preset: Option<String>,
+    /// Run each installed binary once and roll back if it fails
+    #[clap(long)]
+    smoke: bool,
+    /// Strip the installed copies of the binaries
+    #[clap(long)]
+    strip: bool,
+    /// Strip, but keep the debug info as `<bin>.debug` next to each binary
+    #[clap(long)]
+    keep_debug: bool,
+    /// Write shell completions and a man page for each installed binary
+    #[clap(long)]
+    completions: bool,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove the least recently used builds until the cache fits its size limit
+    Gc {
+        /// Size to shrink to, e.g. `500MB`; `cache.max_size` in `cw.toml` by default
+        #[clap(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StatsFormat {
+    Text,
+    Csv,
+    Json,
 }
@@ -0,0 +1,260 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::runner::{CommandLine, CommandRunner};
+
+/// The parts of `cargo metadata --format-version 1 --no-deps` cw cares about.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CargoMetadata {
+    pub packages: Vec<Package>,
+    pub workspace_root: PathBuf,
+    pub target_directory: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub manifest_path: PathBuf,
+    pub targets: Vec<Target>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    /// The `default-run` key of `[package]`.
+    pub default_run: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+}
+
+/// Why `cw run` could not tell which bin to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunTargetError {
+    NoBin,
+    NotFound { bin: String, available: Vec<String> },
+    Ambiguous { available: Vec<String> },
+}
+impl Display for RunTargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoBin => write!(f, "the project has no bin target to run"),
+            Self::NotFound { bin, available } => write!(
+                f,
+                "no bin target named {}, available: {}",
+                bin,
+                available.join(", ")
+            ),
+            Self::Ambiguous { available } => write!(
+                f,
+                "could not determine which bin to run, use --bin or the `default-run` key of \
+                 Cargo.toml; available: {}",
+                available.join(", ")
+            ),
+        }
+    }
+}
+impl std::error::Error for RunTargetError {}
+
+impl CargoMetadata {
+    pub fn load(
+        runner: &impl CommandRunner,
+        project_dir: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = runner.run_success(
+            &CommandLine::new("cargo")
+                .args(["metadata", "--format-version", "1", "--no-deps"])
+                .current_dir(project_dir)
+                .quiet_stdout(),
+        )?;
+        Self::from_json(&output.stdout)
+    }
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(json)?)
+    }
+    /// Names of every `[[bin]]` target in the workspace.
+    pub fn bin_names(&self) -> Vec<&str> {
+        self.packages
+            .iter()
+            .flat_map(|package| package.targets.iter())
+            .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+            .map(|target| target.name.as_str())
+            .collect()
+    }
+    /// The bin `cargo run` would pick from `dir`: `bin` when given, else the `default-run` of
+    /// the package, else the only bin there is. Like cargo, only the package whose manifest is
+    /// in `dir` is considered when there is one, and the whole workspace otherwise.
+    pub fn run_target(
+        &self,
+        dir: impl AsRef<Path>,
+        bin: Option<&str>,
+    ) -> Result<String, RunTargetError> {
+        let dir = dir.as_ref();
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let current = self
+            .packages
+            .iter()
+            .filter(|package| package.manifest_path.parent() == Some(dir.as_path()))
+            .collect::<Vec<_>>();
+        let packages = if current.is_empty() {
+            self.packages.iter().collect()
+        } else {
+            current
+        };
+        let available = packages
+            .iter()
+            .flat_map(|package| package.targets.iter())
+            .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+            .map(|target| target.name.clone())
+            .collect::<Vec<_>>();
+        if let Some(bin) = bin {
+            return if available.iter().any(|name| name == bin) {
+                Ok(bin.to_string())
+            } else {
+                Err(RunTargetError::NotFound {
+                    bin: bin.to_string(),
+                    available,
+                })
+            };
+        }
+        let default_runs = packages
+            .iter()
+            .filter_map(|package| package.default_run.clone())
+            .collect::<Vec<_>>();
+        match (default_runs.as_slice(), available.as_slice()) {
+            ([default_run], _) => Ok(default_run.clone()),
+            (_, [only]) => Ok(only.clone()),
+            (_, []) => Err(RunTargetError::NoBin),
+            _ => Err(RunTargetError::Ambiguous { available }),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn metadata_json(root: &str, package: &str, bins: &[&str]) -> String {
+        let targets = bins
+            .iter()
+            .map(|bin| {
+                format!(
+                    r#"{{"name":"{}","kind":["bin"],"src_path":"{}/src/main.rs"}}"#,
+                    bin, root
+                )
+            })
+            .chain(std::iter::once(format!(
+                r#"{{"name":"{}","kind":["lib"],"src_path":"{}/src/lib.rs"}}"#,
+                package, root
+            )))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            r#"{{"packages":[{{"name":"{package}","version":"0.1.0","id":"{package}","manifest_path":"{root}/Cargo.toml","targets":[{targets}]}}],"workspace_members":[],"resolve":null,"target_directory":"{root}/target","version":1,"workspace_root":"{root}"}}"#
+        )
+    }
+    #[test]
+    fn binターゲットの名前を取得できる() {
+        let metadata =
+            CargoMetadata::from_json(&metadata_json("/work/app", "app", &["app", "helper"]))
+                .unwrap();
+        assert_eq!(metadata.bin_names(), vec!["app", "helper"]);
+        assert_eq!(metadata.packages[0].version, "0.1.0");
+        assert_eq!(metadata.target_directory, PathBuf::from("/work/app/target"));
+    }
+    #[test]
+    fn cargo_runと同じようにbinを選ぶ() {
+        let metadata = |bins: &[&str], default_run: Option<&str>| {
+            let mut metadata =
+                CargoMetadata::from_json(&metadata_json("/work/app", "app", bins)).unwrap();
+            metadata.packages[0].default_run = default_run.map(String::from);
+            metadata
+        };
+
+        assert_eq!(
+            metadata(&["app"], None)
+                .run_target("/work/app", None)
+                .unwrap(),
+            "app"
+        );
+        assert_eq!(
+            metadata(&["app", "helper"], Some("helper"))
+                .run_target("/work/app", None)
+                .unwrap(),
+            "helper"
+        );
+        assert_eq!(
+            metadata(&["app", "helper"], Some("helper"))
+                .run_target("/work/app", Some("app"))
+                .unwrap(),
+            "app"
+        );
+        assert_eq!(
+            metadata(&["app", "helper"], None)
+                .run_target("/work/app", None)
+                .unwrap_err(),
+            RunTargetError::Ambiguous {
+                available: vec!["app".to_string(), "helper".to_string()]
+            }
+        );
+        assert_eq!(
+            metadata(&["app"], None)
+                .run_target("/work/app", Some("other"))
+                .unwrap_err()
+                .to_string(),
+            "no bin target named other, available: app"
+        );
+        assert_eq!(
+            metadata(&[], None)
+                .run_target("/work/app", None)
+                .unwrap_err(),
+            RunTargetError::NoBin
+        );
+    }
+    #[test]
+    fn ワークスペースではカレントディレクトリのパッケージからbinを選ぶ() {
+        let package = |root: &str, name: &str| {
+            CargoMetadata::from_json(&metadata_json(root, name, &[name]))
+                .unwrap()
+                .packages
+                .remove(0)
+        };
+        let metadata = CargoMetadata {
+            packages: vec![
+                package("/work/ws/cli", "cli"),
+                package("/work/ws/server", "server"),
+            ],
+            ..CargoMetadata::from_json(&metadata_json("/work/ws", "ws", &[])).unwrap()
+        };
+
+        assert_eq!(
+            metadata.run_target("/work/ws/server", None).unwrap(),
+            "server"
+        );
+        assert_eq!(
+            metadata
+                .run_target("/work/ws/server", Some("cli"))
+                .unwrap_err(),
+            RunTargetError::NotFound {
+                bin: "cli".to_string(),
+                available: vec!["server".to_string()]
+            }
+        );
+        assert_eq!(
+            metadata.run_target("/work/ws", None).unwrap_err(),
+            RunTargetError::Ambiguous {
+                available: vec!["cli".to_string(), "server".to_string()]
+            }
+        );
+        assert_eq!(metadata.run_target("/work/ws", Some("cli")).unwrap(), "cli");
+    }
+}
@@ -1,9 +1,10 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::{
     cargo_toml::CargoTomlContent,
-    commands::{run_command, write_file},
+    commands::{write_file, FileWalker},
     new::struct_builder::StructBuilder,
+    runner::{CommandLine, CommandRunner, SystemCommandRunner},
 };
 
 use super::{code_builder::MainRsBuilder, fn_builder::FnBuilder};
@@ -17,23 +18,36 @@ pub struct RustNewProjectOptions {
     pub docker_file: bool,
 }
 #[derive(Debug)]
-pub struct CargoProjectCreator {
+pub struct CargoProjectCreator<R: CommandRunner = SystemCommandRunner> {
     name: String,
+    base_dir: PathBuf,
     cargo_toml_content: CargoTomlContent,
     depend_store: Vec<String>,
     main_rs: MainRsBuilder,
+    runner: R,
 }
 
 impl CargoProjectCreator {
+    pub fn create_project_from_options(
+        options: RustNewProjectOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::create_project_from_options_with_runner(options, SystemCommandRunner::new())
+    }
+    pub fn new(name: impl Into<String>) -> Self {
+        Self::with_runner(name, SystemCommandRunner::new())
+    }
+}
+impl<R: CommandRunner> CargoProjectCreator<R> {
     const CLAP_VERSION: &'static str = "4.2.1";
     const ACTIX_WEB_VERSION: &'static str = "4.3.1";
     const TOKIO_VERSION: &'static str = "1";
     const REQWEST_VERSION: &'static str = "0.11";
     const SERDE_VERSION: &'static str = "1";
-    pub fn create_project_from_options(
+    pub fn create_project_from_options_with_runner(
         options: RustNewProjectOptions,
+        runner: R,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut creator = Self::new(options.name);
+        let mut creator = Self::with_runner(options.name, runner);
         if options.cli {
             creator = creator.cli();
         }
@@ -49,15 +63,22 @@ impl CargoProjectCreator {
             creator.create_new_project()
         }
     }
-    pub fn new(name: impl Into<String>) -> Self {
+    pub fn with_runner(name: impl Into<String>, runner: R) -> Self {
         let name = name.into();
         Self {
             depend_store: Vec::new(),
             cargo_toml_content: CargoTomlContent::new(name.as_str()),
             name,
+            base_dir: PathBuf::from("."),
             main_rs: MainRsBuilder::new(),
+            runner,
         }
     }
+    /// The directory in which `cargo new` is run.
+    pub fn base_dir(mut self, base_dir: impl AsRef<Path>) -> Self {
+        self.base_dir = base_dir.as_ref().to_path_buf();
+        self
+    }
     pub fn web_server(mut self) -> Self {
         self.add_actix_web();
         let fn_builder = FnBuilder::new("index")
@@ -120,18 +141,41 @@ impl CargoProjectCreator {
         self
     }
     pub fn create_new_project(self) -> Result<(), Box<dyn std::error::Error>> {
-        let project_root: &Path = self.name.as_ref();
+        let project_root = self.base_dir.join(&self.name);
         let cargo_toml_content = self.cargo_toml_content.gen();
         let main_rs = self.main_rs.build();
-        run_command("cargo", &["new", self.name.as_str()])?;
+        Self::check_not_overwriting(&project_root)?;
+        self.runner.run_success(
+            &CommandLine::new("cargo")
+                .args(["new", self.name.as_str()])
+                .current_dir(&self.base_dir),
+        )?;
         write_file(project_root.join("Cargo.toml"), &cargo_toml_content)?;
         write_file(project_root.join("src/main.rs"), &main_rs)?;
         Ok(())
     }
+    /// Refuses to scaffold into a directory that already has files, before `cargo new` runs.
+    fn check_not_overwriting(project_root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if !project_root.is_dir() {
+            return Ok(());
+        }
+        let files = FileWalker::new(project_root).recursive().walk()?;
+        if let Some(file) = files.first() {
+            return Err(format!(
+                "{} already exists and has {} files (e.g. {}); not overwriting it",
+                project_root.display(),
+                files.len(),
+                file.display()
+            )
+            .into());
+        }
+        Ok(())
+    }
     pub fn create_new_project_with_docker(self) -> Result<(), Box<dyn std::error::Error>> {
+        let project_root = self.base_dir.join(&self.name);
         let name = self.name.clone();
         self.create_new_project()?;
-        Self::docker_file(name.as_str())
+        Self::docker_file(&project_root, name.as_str())
     }
     pub fn add_tokio(&mut self) {
         if self.depend_store.contains(&"tokio".to_string()) {
@@ -187,7 +231,7 @@ impl CargoProjectCreator {
         self.cargo_toml_content
             .add_depend("serde_json", Self::SERDE_VERSION);
     }
-    fn docker_file(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    fn docker_file(project_root: &Path, name: &str) -> Result<(), Box<dyn std::error::Error>> {
         let content = format!(
             r#"FROM ekidd/rust-musl-builder:1.51.0 AS builder
 ADD --chown=rust:rust . ./
@@ -199,8 +243,7 @@ COPY --from=builder /home/rust/src/target/x86_64-unknown-linux-musl/release/{} .
 CMD ["./{}"]"#,
             name, name, name
         );
-        let path: &Path = name.as_ref();
-        write_file(path.join("Dockerfile"), &content)?;
+        write_file(project_root.join("Dockerfile"), &content)?;
         Ok(())
     }
 }
@@ -208,8 +251,59 @@ CMD ["./{}"]"#,
 #[cfg(test)]
 mod tests {
 
+    use crate::runner::RecordingCommandRunner;
+
     use super::*;
     #[test]
+    fn cargo_newを実行してからファイルを書き込む() {
+        let base_dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new();
+
+        CargoProjectCreator::with_runner("sample", &runner)
+            .base_dir(base_dir.path())
+            .cli()
+            .create_new_project_with_docker()
+            .unwrap();
+
+        let commands = runner.commands();
+        assert_eq!(runner.command_lines(), vec!["cargo new sample"]);
+        assert_eq!(commands[0].get_current_dir(), Some(base_dir.path()));
+        let project_root = base_dir.path().join("sample");
+        let cargo_toml = std::fs::read_to_string(project_root.join("Cargo.toml")).unwrap();
+        assert!(cargo_toml.contains(r#"clap = { version = "4.2.1", features = ["derive"] }"#));
+        assert!(project_root.join("src/main.rs").is_file());
+        assert!(project_root.join("Dockerfile").is_file());
+    }
+    #[test]
+    fn cargo_newが失敗した時はファイルを書き込まない() {
+        let base_dir = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new().fail("cargo new");
+
+        let result = CargoProjectCreator::with_runner("sample", &runner)
+            .base_dir(base_dir.path())
+            .create_new_project();
+
+        assert!(result.is_err());
+        assert!(!base_dir.path().join("sample").exists());
+    }
+    #[test]
+    fn ファイルのあるディレクトリには作らない() {
+        let base_dir = tempfile::tempdir().unwrap();
+        write_file(base_dir.path().join("sample/src/main.rs"), "fn main() {}").unwrap();
+        let runner = RecordingCommandRunner::new();
+
+        let result = CargoProjectCreator::with_runner("sample", &runner)
+            .base_dir(base_dir.path())
+            .create_new_project();
+
+        assert!(result.unwrap_err().to_string().contains("not overwriting"));
+        assert!(runner.command_lines().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(base_dir.path().join("sample/src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+    }
+    #[test]
     fn remote_client_snap_shot_test() {
         let remote_client = CargoProjectCreator::new("remote_client")
             .remote_client()
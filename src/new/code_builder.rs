@@ -111,18 +111,23 @@ impl MainRsBuilder {
             .collect::<Vec<String>>()
             .join("\n");
         let mut result = self.inner.build();
-        if depend.len() > 0 {
+        if !depend.is_empty() {
             result = format!("{}\n\n{}", depend, result)
         }
-        if structs.len() > 0 {
+        if !structs.is_empty() {
             result = format!("{}\n\n{}", result, structs)
         }
-        if fns.len() > 0 {
+        if !fns.is_empty() {
             result = format!("{}\n\n{}", result, fns)
         }
         result
     }
 }
+impl Default for MainRsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 #[cfg(test)]
 mod tests {
     use crate::new::{fn_builder::FnBuilder, struct_builder::StructBuilder};
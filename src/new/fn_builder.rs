@@ -51,6 +51,12 @@ impl MainBuilder {
     }
 }
 
+impl Default for MainBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 type Arg = String;
 type Type = String;
 #[derive(Debug)]
@@ -138,7 +144,7 @@ impl FnBuilder {
     fn create_attr(&self) -> String {
         self.attr
             .as_ref()
-            .map(|attr| format!("{}\n", attr.to_string()))
+            .map(|attr| format!("{}\n", attr))
             .unwrap_or_default()
     }
     fn create_prefix_fn(&self) -> String {
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 pub fn add_rust_line(acc: &str, line: &str) -> String {
     format!("{}\n    {}", acc, line)
 }
@@ -18,16 +20,16 @@ impl Attribute {
     pub fn add(&mut self, value: impl Into<String>) {
         self.values.push(value.into());
     }
-    pub fn to_string(&self) -> String {
-        if self.values.len() > 0 {
-            self.values
-                .iter()
-                .map(|s| format!("#[{}]", s))
-                .reduce(|acc, s| format!("{}\n{}", acc, s))
-                .unwrap()
-        } else {
-            String::new()
-        }
+}
+impl Display for Attribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let attrs = self
+            .values
+            .iter()
+            .map(|s| format!("#[{}]", s))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(f, "{}", attrs)
     }
 }
 pub trait IntoAttr {
@@ -60,17 +62,13 @@ impl Derive {
     pub fn add(&mut self, value: impl Into<String>) {
         self.values.push(value.into());
     }
-    pub fn to_string(self) -> String {
-        if self.values.len() > 0 {
-            format!(
-                "#[derive({})]",
-                self.values
-                    .into_iter()
-                    .reduce(|acc, s| format!("{},{}", acc, s))
-                    .unwrap()
-            )
+}
+impl Display for Derive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.values.is_empty() {
+            write!(f, "#[derive({})]", self.values.join(","))
         } else {
-            String::new()
+            Ok(())
         }
     }
 }
@@ -106,7 +106,7 @@ impl StructBuilder {
     }
     fn create_derives(&self) -> String {
         let derive = self.derives.clone().to_string();
-        if derive.len() > 0 {
+        if !derive.is_empty() {
             format!("{}\n", derive)
         } else {
             "".to_string()
@@ -147,7 +147,7 @@ impl Filed {
     }
     fn create_fields(&self) -> String {
         if self.is_enum {
-            if self.type_.len() > 0 {
+            if !self.type_.is_empty() {
                 add_rust_line(
                     &self.create_attr(),
                     &format!("{}({}),", self.key, self.type_),
@@ -0,0 +1,568 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    build::BuiltBinary,
+    checksum::{sha256_file, sha256_hex, sums_line},
+    commands::FileWalker,
+    config::PackageConfig,
+    deb,
+    metadata::CargoMetadata,
+    rpm,
+    runner::CommandRunner,
+    rustc::RustcVersion,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ArchiveFormat {
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::TarGz => "tar.gz",
+            Self::Zip => "zip",
+        }
+    }
+}
+impl FromStr for ArchiveFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tar.gz" | "tgz" => Ok(Self::TarGz),
+            "zip" => Ok(Self::Zip),
+            _ => Err(format!(
+                "unknown archive format {}, expected tar.gz or zip",
+                s
+            )),
+        }
+    }
+}
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// A file to put into the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFile {
+    pub source: PathBuf,
+    /// Path inside the archive below its root directory, always with `/`.
+    pub path: String,
+    pub mode: u32,
+}
+
+/// One file or, with a trailing `/`, directory of an archive being written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ArchiveEntry {
+    pub path: String,
+    pub content: Vec<u8>,
+    pub mode: u32,
+}
+
+/// What `Cargo.toml` says about the package, for the package managers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub maintainer: Option<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+}
+impl PackageInfo {
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Lays out built binaries and project files into `name-version-target` archives.
+#[derive(Debug, Clone)]
+pub struct Packager {
+    info: PackageInfo,
+    target: String,
+    config: PackageConfig,
+}
+
+impl Packager {
+    const SUMS_FILE: &'static str = "SHA256SUMS";
+    const BIN_DIR: &'static str = "usr/bin";
+    const SYSTEMD_DIR: &'static str = "usr/lib/systemd/system";
+    pub fn new(info: PackageInfo, target: impl Into<String>, config: PackageConfig) -> Self {
+        Self {
+            info,
+            target: target.into(),
+            config,
+        }
+    }
+    /// The package in `project_dir`, for the host target. In a virtual workspace it is the
+    /// member the `binaries` were built from.
+    pub fn for_project(
+        runner: &impl CommandRunner,
+        project_dir: impl AsRef<Path>,
+        binaries: &[BuiltBinary],
+        config: PackageConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let project_dir = project_dir.as_ref();
+        let metadata = CargoMetadata::load(runner, project_dir)?;
+        let manifest = project_dir.canonicalize()?.join("Cargo.toml");
+        let package = match metadata
+            .packages
+            .iter()
+            .find(|package| package.manifest_path == manifest)
+        {
+            Some(package) => package,
+            None => {
+                let owners = metadata
+                    .packages
+                    .iter()
+                    .filter(|package| {
+                        package.targets.iter().any(|target| {
+                            target.kind.iter().any(|kind| kind == "bin")
+                                && binaries.iter().any(|binary| binary.bin == target.name)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                match owners.as_slice() {
+                    [package] => *package,
+                    [] => return Err("no package to package".into()),
+                    _ => {
+                        return Err(format!(
+                            "the binaries come from several packages ({}); select one with --bin \
+                             or run cw package in its directory",
+                            owners
+                                .iter()
+                                .map(|package| package.name.as_str())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                        .into())
+                    }
+                }
+            }
+        };
+        let info = PackageInfo {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            description: package.description.clone(),
+            maintainer: config
+                .maintainer
+                .clone()
+                .or_else(|| package.authors.first().cloned()),
+            license: package.license.clone(),
+            homepage: package.homepage.clone().or(package.repository.clone()),
+        };
+        let rustc = RustcVersion::load(runner, project_dir)?;
+        Ok(Self::new(info, rustc.host, config))
+    }
+    /// `name-version-target`, also the top directory inside the archives unless configured.
+    pub fn base_name(&self) -> String {
+        format!("{}-{}-{}", self.info.name, self.info.version, self.target)
+    }
+    fn root(&self) -> String {
+        self.config
+            .root
+            .replace("{name}", &self.info.name)
+            .replace("{version}", &self.info.version)
+            .replace("{target}", &self.target)
+    }
+    /// Binaries first, then the project files matching `include`.
+    pub fn files(
+        &self,
+        project_dir: impl AsRef<Path>,
+        binaries: &[BuiltBinary],
+    ) -> Result<Vec<PackageFile>, Box<dyn std::error::Error>> {
+        let project_dir = project_dir.as_ref();
+        let mut files = binaries
+            .iter()
+            .map(|binary| PackageFile {
+                source: binary.path.clone(),
+                path: join(&self.config.bin_dir, &binary.name),
+                mode: 0o755,
+            })
+            .collect::<Vec<_>>();
+        for pattern in &self.config.include {
+            let walker = FileWalker::new(project_dir).glob(pattern);
+            let walker = if pattern.contains('/') {
+                walker.recursive().respect_gitignore()
+            } else {
+                walker
+            };
+            for source in walker.walk()? {
+                let path = source
+                    .strip_prefix(project_dir)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if files.iter().all(|file| file.path != path) {
+                    files.push(PackageFile {
+                        source,
+                        path,
+                        mode: 0o644,
+                    });
+                }
+            }
+        }
+        Ok(files)
+    }
+    /// Writes one archive per format into `out_dir` and a `SHA256SUMS` of the archives.
+    pub fn write(
+        &self,
+        files: &[PackageFile],
+        out_dir: impl AsRef<Path>,
+    ) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let out_dir = out_dir.as_ref();
+        std::fs::create_dir_all(out_dir)?;
+        let mut entries = read_files(files)?;
+        let mut sums = String::new();
+        for entry in &mut entries {
+            sums.push_str(&sums_line(&sha256_hex(&entry.content), &entry.path));
+            entry.path = join(&self.root(), &entry.path);
+        }
+        entries.push(ArchiveEntry {
+            path: join(&self.root(), Self::SUMS_FILE),
+            content: sums.into_bytes(),
+            mode: 0o644,
+        });
+
+        let mut archives = Vec::new();
+        for format in &self.config.formats {
+            let path = out_dir.join(format!("{}.{}", self.base_name(), format.extension()));
+            match format {
+                ArchiveFormat::TarGz => std::fs::write(&path, tar_gz(&entries)?)?,
+                ArchiveFormat::Zip => write_zip(&path, &entries)?,
+            }
+            archives.push(path);
+        }
+        let mut sums = String::new();
+        for archive in &archives {
+            let name = archive.file_name().unwrap_or_default().to_string_lossy();
+            sums.push_str(&sums_line(&sha256_file(archive)?, &name));
+        }
+        std::fs::write(out_dir.join(Self::SUMS_FILE), sums)?;
+        Ok(archives)
+    }
+    /// Where the files go on a system installing the `.deb` or `.rpm`:
+    /// binaries in `/usr/bin`, then the configured assets and systemd units.
+    pub fn system_files(
+        &self,
+        project_dir: impl AsRef<Path>,
+        binaries: &[BuiltBinary],
+    ) -> Result<Vec<PackageFile>, Box<dyn std::error::Error>> {
+        let project_dir = project_dir.as_ref();
+        let mut files = binaries
+            .iter()
+            .map(|binary| PackageFile {
+                source: binary.path.clone(),
+                path: join(Self::BIN_DIR, &binary.name),
+                mode: 0o755,
+            })
+            .collect::<Vec<_>>();
+        for asset in &self.config.assets {
+            files.push(PackageFile {
+                source: project_dir.join(&asset.source),
+                path: asset.dest.trim_start_matches('/').to_string(),
+                mode: asset.mode.unwrap_or(0o644),
+            });
+        }
+        for unit in &self.config.systemd_units {
+            let name = unit
+                .file_name()
+                .ok_or_else(|| format!("not a systemd unit file: {}", unit.display()))?;
+            files.push(PackageFile {
+                source: project_dir.join(unit),
+                path: join(Self::SYSTEMD_DIR, &name.to_string_lossy()),
+                mode: 0o644,
+            });
+        }
+        Ok(files)
+    }
+    pub fn write_deb(
+        &self,
+        files: &[PackageFile],
+        out_dir: impl AsRef<Path>,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(out_dir.as_ref())?;
+        deb::write(&self.info, &self.target, files, out_dir.as_ref())
+    }
+    pub fn write_rpm(
+        &self,
+        files: &[PackageFile],
+        out_dir: impl AsRef<Path>,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(out_dir.as_ref())?;
+        rpm::write(&self.info, &self.target, files, out_dir.as_ref())
+    }
+}
+
+/// Reads the files, naming each after its `path`; errors name the missing source.
+pub(crate) fn read_files(
+    files: &[PackageFile],
+) -> Result<Vec<ArchiveEntry>, Box<dyn std::error::Error>> {
+    files
+        .iter()
+        .map(|file| {
+            let content = std::fs::read(&file.source)
+                .map_err(|e| format!("{}: {}", file.source.display(), e))?;
+            Ok(ArchiveEntry {
+                path: file.path.clone(),
+                content,
+                mode: file.mode,
+            })
+        })
+        .collect()
+}
+
+fn join(dir: &str, name: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Entries get fixed owners and timestamps so that the same files give the same archive.
+pub(crate) fn tar_gz(entries: &[ArchiveEntry]) -> std::io::Result<Vec<u8>> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    let mut builder = tar::Builder::new(encoder);
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        if entry.path.ends_with('/') {
+            header.set_entry_type(tar::EntryType::Directory);
+        }
+        header.set_size(entry.content.len() as u64);
+        header.set_mode(entry.mode);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        builder.append_data(&mut header, &entry.path, entry.content.as_slice())?;
+    }
+    builder.into_inner()?.finish()
+}
+
+fn write_zip(path: &Path, entries: &[ArchiveEntry]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = zip::ZipWriter::new(File::create(path)?);
+    for entry in entries {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .last_modified_time(zip::DateTime::DEFAULT)
+            .unix_permissions(entry.mode);
+        writer.start_file(entry.path.as_str(), options)?;
+        writer.write_all(&entry.content)?;
+    }
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::{
+        commands::write_file,
+        metadata::tests::metadata_json,
+        runner::{CommandOutput, RecordingCommandRunner},
+        rustc::tests::RUSTC_VV,
+    };
+
+    use super::*;
+
+    fn project() -> (tempfile::TempDir, Vec<BuiltBinary>) {
+        let project = tempfile::tempdir().unwrap();
+        for (path, content) in [
+            ("README.md", "readme"),
+            ("LICENSE", "license"),
+            ("completions/app.bash", "complete -F _app app"),
+            ("src/main.rs", "fn main() {}"),
+            ("target/release/app", "binary"),
+        ] {
+            write_file(project.path().join(path), content).unwrap();
+        }
+        let binaries = vec![BuiltBinary {
+            name: "app".to_string(),
+            bin: "app".to_string(),
+            path: project.path().join("target/release/app"),
+            size: 6,
+            version: None,
+        }];
+        (project, binaries)
+    }
+    #[test]
+    fn パッケージ名とバージョンとホストのターゲットを使う() {
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().canonicalize().unwrap();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "cargo metadata",
+                CommandOutput::new(0).stdout(metadata_json(
+                    root.to_str().unwrap(),
+                    "app",
+                    &["app"],
+                )),
+            )
+            .respond("rustc -vV", CommandOutput::new(0).stdout(RUSTC_VV));
+
+        let packager =
+            Packager::for_project(&runner, project.path(), &[], PackageConfig::default()).unwrap();
+
+        assert_eq!(packager.base_name(), "app-0.1.0-x86_64-unknown-linux-gnu");
+    }
+    #[test]
+    fn 仮想ワークスペースではビルドしたbinのパッケージを使う() {
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().canonicalize().unwrap();
+        let mut metadata = serde_json::from_str::<serde_json::Value>(&metadata_json(
+            root.to_str().unwrap(),
+            "ws",
+            &[],
+        ))
+        .unwrap();
+        metadata["packages"] = ["cli", "server"]
+            .iter()
+            .map(|name| {
+                let member = root.join(name);
+                serde_json::from_str::<serde_json::Value>(&metadata_json(
+                    member.to_str().unwrap(),
+                    name,
+                    &[name],
+                ))
+                .unwrap()["packages"][0]
+                    .clone()
+            })
+            .collect();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "cargo metadata",
+                CommandOutput::new(0).stdout(metadata.to_string()),
+            )
+            .respond("rustc -vV", CommandOutput::new(0).stdout(RUSTC_VV));
+        let (_, mut binaries) = self::project();
+        binaries[0].bin = "server".to_string();
+
+        let packager =
+            Packager::for_project(&runner, project.path(), &binaries, PackageConfig::default())
+                .unwrap();
+        assert_eq!(
+            packager.base_name(),
+            "server-0.1.0-x86_64-unknown-linux-gnu"
+        );
+
+        let mut both = binaries.clone();
+        both.push(BuiltBinary {
+            bin: "cli".to_string(),
+            ..binaries[0].clone()
+        });
+        let error = Packager::for_project(&runner, project.path(), &both, PackageConfig::default())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("several packages (cli, server)"));
+    }
+    #[test]
+    fn バイナリとreadmeなどをtar_gzとzipにまとめる() {
+        let (project, binaries) = project();
+        let out_dir = project.path().join("target/dist");
+        let packager = Packager::new(
+            PackageInfo::new("app", "1.2.3"),
+            "x86_64-unknown-linux-gnu",
+            PackageConfig::default(),
+        );
+
+        let files = packager.files(project.path(), &binaries).unwrap();
+        let archives = packager.write(&files, &out_dir).unwrap();
+
+        assert_eq!(
+            files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+            vec!["app", "README.md", "LICENSE", "completions/app.bash"]
+        );
+        assert_eq!(
+            archives,
+            vec![
+                out_dir.join("app-1.2.3-x86_64-unknown-linux-gnu.tar.gz"),
+                out_dir.join("app-1.2.3-x86_64-unknown-linux-gnu.zip"),
+            ]
+        );
+
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(
+            File::open(&archives[0]).unwrap(),
+        ));
+        let mut entries = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            entries.push((
+                entry.path().unwrap().display().to_string(),
+                entry.header().mode().unwrap(),
+                content,
+            ));
+        }
+        let root = "app-1.2.3-x86_64-unknown-linux-gnu";
+        assert_eq!(
+            entries[0],
+            (format!("{}/app", root), 0o755, "binary".to_string())
+        );
+        assert_eq!(entries[4].0, format!("{}/SHA256SUMS", root));
+        assert!(entries[4]
+            .2
+            .contains(&sums_line(&sha256_hex(b"readme"), "README.md")));
+
+        let mut zip = zip::ZipArchive::new(File::open(&archives[1]).unwrap()).unwrap();
+        assert_eq!(zip.len(), 5);
+        let mut content = String::new();
+        zip.by_name(&format!("{}/LICENSE", root))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "license");
+
+        let sums = std::fs::read_to_string(out_dir.join("SHA256SUMS")).unwrap();
+        assert!(sums.contains(&sums_line(
+            &sha256_file(&archives[1]).unwrap(),
+            "app-1.2.3-x86_64-unknown-linux-gnu.zip"
+        )));
+    }
+    #[test]
+    fn 設定で構成と含めるファイルを変えられる() {
+        let (project, binaries) = project();
+        let config = PackageConfig {
+            formats: vec![ArchiveFormat::Zip],
+            root: "{name}".to_string(),
+            bin_dir: "bin".to_string(),
+            include: vec!["src/*.rs".to_string()],
+            ..Default::default()
+        };
+        let packager = Packager::new(
+            PackageInfo::new("app", "1.2.3"),
+            "x86_64-unknown-linux-gnu",
+            config,
+        );
+
+        let files = packager.files(project.path(), &binaries).unwrap();
+        let archives = packager.write(&files, project.path().join("dist")).unwrap();
+
+        assert_eq!(
+            files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+            vec!["bin/app", "src/main.rs"]
+        );
+        let zip = zip::ZipArchive::new(File::open(&archives[0]).unwrap()).unwrap();
+        assert_eq!(zip.file_names().collect::<Vec<_>>().len(), 3);
+        assert!(zip.file_names().any(|name| name == "app/bin/app"));
+    }
+}
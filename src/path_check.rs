@@ -0,0 +1,141 @@
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// Something about `$PATH` that keeps an installed binary from being the one a shell runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathWarning {
+    NotOnPath {
+        install_dir: PathBuf,
+    },
+    Shadowed {
+        installed: PathBuf,
+        /// What `$PATH` resolves the name to instead.
+        winner: PathBuf,
+    },
+}
+impl Display for PathWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotOnPath { install_dir } => {
+                write!(f, "{} is not in PATH", install_dir.display())
+            }
+            Self::Shadowed { installed, winner } => write!(
+                f,
+                "{} is shadowed by {} earlier in PATH",
+                installed.display(),
+                winner.display()
+            ),
+        }
+    }
+}
+
+/// Resolves each installed file name against `path_var` the way a shell would.
+pub fn check_path(
+    install_dir: impl AsRef<Path>,
+    installed: &[PathBuf],
+    path_var: &OsStr,
+) -> Vec<PathWarning> {
+    let install_dir = install_dir.as_ref();
+    let dirs = std::env::split_paths(path_var).collect::<Vec<_>>();
+    let mut warnings = Vec::new();
+    if !dirs.iter().any(|dir| same_file(dir, install_dir)) {
+        warnings.push(PathWarning::NotOnPath {
+            install_dir: install_dir.to_path_buf(),
+        });
+    }
+    for path in installed {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let winner = dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|candidate| is_executable(candidate));
+        match winner {
+            Some(winner) if !same_file(&winner, path) => warnings.push(PathWarning::Shadowed {
+                installed: path.clone(),
+                winner,
+            }),
+            _ => {}
+        }
+    }
+    warnings
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::write_file;
+
+    use super::*;
+
+    fn executable(path: PathBuf) -> PathBuf {
+        write_file(&path, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        path
+    }
+    #[test]
+    fn path上で先に見つかる別のファイルを警告する() {
+        let cargo_bin = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let old = executable(cargo_bin.path().join("app"));
+        let app = executable(install_dir.path().join("app"));
+        let tool = executable(install_dir.path().join("tool"));
+        let path_var = std::env::join_paths([cargo_bin.path(), install_dir.path()]).unwrap();
+
+        let warnings = check_path(install_dir.path(), &[app.clone(), tool], &path_var);
+
+        assert_eq!(
+            warnings,
+            vec![PathWarning::Shadowed {
+                installed: app,
+                winner: old
+            }]
+        );
+    }
+    #[test]
+    fn インストール先がpathにない時は警告する() {
+        let other = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let app = executable(install_dir.path().join("app"));
+        let path_var = std::env::join_paths([other.path()]).unwrap();
+
+        let warnings = check_path(install_dir.path(), &[app], &path_var);
+
+        assert_eq!(
+            warnings,
+            vec![PathWarning::NotOnPath {
+                install_dir: install_dir.path().to_path_buf()
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            format!("{} is not in PATH", install_dir.path().display())
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    build_info::BuildInfo,
+    install::Installer,
+    ledger::{Ledger, LedgerEntry},
+    lock::InstallLock,
+    metadata::CargoMetadata,
+    runner::CommandRunner,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleBinary {
+    pub entry: LedgerEntry,
+    pub path: PathBuf,
+    pub reason: StaleReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StaleReason {
+    ProjectRemoved,
+    BinRemoved,
+}
+impl Display for StaleReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ProjectRemoved => write!(f, "project directory no longer exists"),
+            Self::BinRemoved => write!(f, "project no longer has this bin target"),
+        }
+    }
+}
+
+/// Finds installed binaries whose source is gone, using the ledger and `cargo metadata`.
+pub struct Pruner<R: CommandRunner> {
+    install_dir: PathBuf,
+    wait_for_lock: bool,
+    runner: R,
+}
+
+impl<R: CommandRunner> Pruner<R> {
+    const MANIFEST: &'static str = "Cargo.toml";
+    pub fn new(install_dir: impl AsRef<Path>, runner: R) -> Self {
+        Self {
+            install_dir: install_dir.as_ref().to_path_buf(),
+            wait_for_lock: true,
+            runner,
+        }
+    }
+    pub fn wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+        self.wait_for_lock = wait_for_lock;
+        self
+    }
+    pub fn find_stale(&self) -> Result<Vec<StaleBinary>, Box<dyn std::error::Error>> {
+        let ledger = Ledger::load(&self.install_dir)?;
+        let mut bins_by_project = BTreeMap::<&str, Option<Vec<String>>>::new();
+        let mut stale = Vec::new();
+        for entry in ledger.entries() {
+            let project = Path::new(&entry.project);
+            let bins = bins_by_project
+                .entry(entry.project.as_str())
+                .or_insert_with(|| {
+                    if !project.join(Self::MANIFEST).is_file() {
+                        return None;
+                    }
+                    match CargoMetadata::load(&self.runner, project) {
+                        Ok(metadata) => Some(
+                            metadata
+                                .bin_names()
+                                .into_iter()
+                                .map(|name| name.to_string())
+                                .collect(),
+                        ),
+                        Err(e) => {
+                            eprintln!("cw: skipping {}: {}", project.display(), e);
+                            Some(vec![entry.bin.clone()])
+                        }
+                    }
+                });
+            let reason = match bins {
+                None => StaleReason::ProjectRemoved,
+                Some(bins) if !bins.contains(&entry.bin) => StaleReason::BinRemoved,
+                Some(_) => continue,
+            };
+            stale.push(StaleBinary {
+                entry: entry.clone(),
+                path: self.install_dir.join(&entry.name),
+                reason,
+            });
+        }
+        Ok(stale)
+    }
+    /// Deletes the binaries and forgets them in the ledger.
+    pub fn remove(&self, stale: &[StaleBinary]) -> Result<(), Box<dyn std::error::Error>> {
+        let _lock = InstallLock::acquire(&self.install_dir, self.wait_for_lock)?;
+        let mut ledger = Ledger::load(&self.install_dir)?;
+        for binary in stale {
+            let debug_file = binary
+                .entry
+                .debug_file
+                .as_ref()
+                .map(|name| self.install_dir.join(name));
+            let build_info = BuildInfo::path(&self.install_dir, &binary.entry.name);
+            for path in [&binary.path, &build_info]
+                .into_iter()
+                .chain(debug_file.as_ref())
+                .chain(&binary.entry.generated)
+            {
+                match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            ledger.remove(&binary.entry.name);
+        }
+        ledger.save(&self.install_dir)
+    }
+    /// Staged copies and backups an interrupted install left behind.
+    pub fn find_leftovers(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        Ok(Installer::leftovers(&self.install_dir)?)
+    }
+    /// Deletes the leftovers, holding the install lock so no running install loses its staged files.
+    pub fn remove_leftovers(
+        &self,
+        leftovers: &[PathBuf],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let _lock = InstallLock::acquire(&self.install_dir, self.wait_for_lock)?;
+        for path in leftovers {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commands::write_file,
+        ledger::tests::entry,
+        metadata::tests::metadata_json,
+        runner::{CommandOutput, RecordingCommandRunner},
+    };
+
+    use super::*;
+    #[test]
+    fn 消えたプロジェクトやbinのバイナリを見つけて削除する() {
+        let install_dir = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        write_file(project.path().join("Cargo.toml"), "").unwrap();
+        let project_path = project.path().to_str().unwrap();
+        let mut ledger = Ledger::default();
+        for (name, project) in [
+            ("current", project_path),
+            ("renamed", project_path),
+            ("orphan", "/no/such/project"),
+        ] {
+            write_file(install_dir.path().join(name), "").unwrap();
+            write_file(install_dir.path().join(format!("{}.cw.json", name)), "{}").unwrap();
+            let man_page = install_dir.path().join(format!("man/{}.1", name));
+            write_file(&man_page, "").unwrap();
+            ledger.record(LedgerEntry {
+                generated: vec![man_page],
+                ..entry(name, project)
+            });
+        }
+        ledger.save(install_dir.path()).unwrap();
+        let runner = RecordingCommandRunner::new().respond(
+            "cargo metadata",
+            CommandOutput::new(0).stdout(metadata_json(project_path, "app", &["current"])),
+        );
+        let pruner = Pruner::new(install_dir.path(), &runner);
+
+        let stale = pruner.find_stale().unwrap();
+
+        assert_eq!(
+            stale
+                .iter()
+                .map(|s| (s.entry.name.as_str(), s.reason.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("orphan", StaleReason::ProjectRemoved),
+                ("renamed", StaleReason::BinRemoved)
+            ]
+        );
+        assert_eq!(runner.command_lines().len(), 1);
+
+        pruner.remove(&stale).unwrap();
+
+        assert!(install_dir.path().join("current").exists());
+        assert!(!install_dir.path().join("renamed").exists());
+        assert!(!install_dir.path().join("orphan").exists());
+        assert!(!install_dir.path().join("orphan.cw.json").exists());
+        assert!(install_dir.path().join("current.cw.json").exists());
+        assert!(!install_dir.path().join("man/orphan.1").exists());
+        assert!(install_dir.path().join("man/current.1").exists());
+        let names = Ledger::load(install_dir.path())
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|e| e.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["current"]);
+    }
+    #[test]
+    fn 中断したインストールの残骸を見つけて削除する() {
+        let install_dir = tempfile::tempdir().unwrap();
+        for path in [
+            "app",
+            ".app.cw-tmp",
+            "app.cw-old",
+            "man/app.1",
+            ".cw/staging/app.debug",
+            ".cw/previous/app",
+        ] {
+            write_file(install_dir.path().join(path), "").unwrap();
+        }
+        let pruner = Pruner::new(install_dir.path(), RecordingCommandRunner::new());
+
+        let leftovers = pruner.find_leftovers().unwrap();
+
+        assert_eq!(
+            leftovers,
+            vec![
+                install_dir.path().join(".app.cw-tmp"),
+                install_dir.path().join(".cw/staging/app.debug"),
+                install_dir.path().join("app.cw-old"),
+            ]
+        );
+        pruner.remove_leftovers(&leftovers).unwrap();
+        assert!(pruner.find_leftovers().unwrap().is_empty());
+        assert!(install_dir.path().join("app").is_file());
+        assert!(install_dir.path().join(".cw/previous/app").is_file());
+    }
+}
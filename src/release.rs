@@ -0,0 +1,462 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use semver::Version;
+use toml_edit::DocumentMut;
+
+use crate::{
+    git::{is_dirty, is_ignored, rev_parse},
+    metadata::CargoMetadata,
+    runner::{CommandLine, CommandRunner},
+};
+
+/// The argument of `cw release`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+    Exact(Version),
+}
+impl FromStr for Bump {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            "patch" => Ok(Self::Patch),
+            _ => Version::parse(s.trim_start_matches('v'))
+                .map(Self::Exact)
+                .map_err(|e| format!("expected major, minor, patch or a version: {}", e)),
+        }
+    }
+}
+impl Bump {
+    pub fn apply(&self, current: &Version) -> Version {
+        match self {
+            Self::Major => Version::new(current.major + 1, 0, 0),
+            Self::Minor => Version::new(current.major, current.minor + 1, 0),
+            Self::Patch if !current.pre.is_empty() => {
+                Version::new(current.major, current.minor, current.patch)
+            }
+            Self::Patch => Version::new(current.major, current.minor, current.patch + 1),
+            Self::Exact(version) => version.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DirtyWorkTree;
+impl Display for DirtyWorkTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the work tree has uncommitted changes; commit or stash them before releasing"
+        )
+    }
+}
+impl std::error::Error for DirtyWorkTree {}
+
+/// What `cw release` does or, with `--dry-run`, would do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleasePlan {
+    pub package: String,
+    pub current: Version,
+    pub next: Version,
+    pub tag: String,
+    pub manifest: PathBuf,
+}
+impl Display for ReleasePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} -> {} (tag {})",
+            self.package, self.current, self.next, self.tag
+        )
+    }
+}
+
+/// Reads `package.version` of a manifest.
+pub fn read_version(manifest: &str) -> Result<Version, Box<dyn std::error::Error>> {
+    let document = manifest.parse::<DocumentMut>()?;
+    let version = document
+        .get("package")
+        .and_then(|package| package.get("version"))
+        .ok_or("Cargo.toml has no package.version")?;
+    let version = version
+        .as_str()
+        .ok_or("package.version is inherited from the workspace; release the workspace instead")?;
+    Ok(Version::parse(version)?)
+}
+
+/// Rewrites `package.version`, keeping the rest of the manifest as it was written.
+pub fn set_version(
+    manifest: &str,
+    version: &Version,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut document = manifest.parse::<DocumentMut>()?;
+    read_version(manifest)?;
+    let old = document["package"]["version"].clone();
+    let mut new = toml_edit::value(version.to_string());
+    if let (Some(old), Some(new)) = (old.as_value(), new.as_value_mut()) {
+        *new.decor_mut() = old.decor().clone();
+    }
+    document["package"]["version"] = new;
+    Ok(document.to_string())
+}
+
+/// How far `Release::run` got.
+#[derive(Debug, Default)]
+struct Progress {
+    committed: bool,
+    tagged: bool,
+}
+
+/// Bumps the version of a package, updates `Cargo.lock`, commits, tags and builds it.
+///
+/// Any failure up to and including the build undoes the release, so no tag is left for a
+/// version that does not build.
+pub struct Release<R: CommandRunner> {
+    project_dir: PathBuf,
+    dry_run: bool,
+    runner: R,
+}
+
+impl<R: CommandRunner> Release<R> {
+    const MANIFEST: &'static str = "Cargo.toml";
+    const LOCK_FILE: &'static str = "Cargo.lock";
+    pub fn new(project_dir: impl AsRef<Path>, runner: R) -> Self {
+        Self {
+            project_dir: project_dir.as_ref().to_path_buf(),
+            dry_run: false,
+            runner,
+        }
+    }
+    /// Only works out the plan; nothing is written, committed or tagged.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+    /// `build` runs once the release is tagged, so that the binary is built from its commit.
+    pub fn run(
+        &self,
+        bump: &Bump,
+        build: impl FnOnce(&ReleasePlan) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<ReleasePlan, Box<dyn std::error::Error>> {
+        if is_dirty(&self.runner, &self.project_dir)? {
+            return Err(Box::new(DirtyWorkTree));
+        }
+        let manifest_path = self.project_dir.join(Self::MANIFEST);
+        let manifest = std::fs::read_to_string(&manifest_path)?;
+        let document = manifest.parse::<DocumentMut>()?;
+        let package = document
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(|name| name.as_str())
+            .ok_or("Cargo.toml has no package.name")?
+            .to_string();
+        let current = read_version(&manifest)?;
+        let next = bump.apply(&current);
+        if next <= current {
+            return Err(format!("{} is not newer than the current {}", next, current).into());
+        }
+        let tag = format!("v{}", next);
+        if rev_parse(&self.runner, &self.project_dir, &tag).is_ok() {
+            return Err(format!("tag {} already exists", tag).into());
+        }
+        let plan = ReleasePlan {
+            package,
+            current,
+            next,
+            tag,
+            manifest: manifest_path,
+        };
+        if self.dry_run {
+            return Ok(plan);
+        }
+
+        let lock = CargoMetadata::load(&self.runner, &self.project_dir)?
+            .workspace_root
+            .join(Self::LOCK_FILE);
+        let original_lock = match std::fs::read(&lock) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        std::fs::write(&plan.manifest, set_version(&manifest, &plan.next)?)?;
+        let mut progress = Progress::default();
+        let released = self
+            .commit_and_tag(&plan, &lock, &mut progress)
+            .and_then(|()| build(&plan));
+        if let Err(e) = released {
+            self.roll_back(&plan, &manifest, &lock, original_lock.as_deref(), &progress)
+                .map_err(|undo| format!("{}; rolling the release back failed too: {}", e, undo))?;
+            eprintln!("cw: rolled back to {} {}", plan.package, plan.current);
+            return Err(e);
+        }
+        Ok(plan)
+    }
+    /// Records in `progress` what exists so far, so that a later failure can undo it.
+    fn commit_and_tag(
+        &self,
+        plan: &ReleasePlan,
+        lock: &Path,
+        progress: &mut Progress,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.runner.run_success(
+            &CommandLine::new("cargo")
+                .args(["update", "--workspace"])
+                .current_dir(&self.project_dir),
+        )?;
+        let mut files = vec![plan.manifest.clone()];
+        if lock.is_file() && !is_ignored(&self.runner, &self.project_dir, lock) {
+            files.push(lock.to_path_buf());
+        }
+        self.runner.run_success(
+            &self
+                .git()
+                .arg("add")
+                .args(files.iter().map(|file| file.display().to_string())),
+        )?;
+        self.runner.run_success(&self.git().args([
+            "commit".to_string(),
+            "-m".to_string(),
+            format!("Release {} {}", plan.package, plan.next),
+        ]))?;
+        progress.committed = true;
+        self.runner.run_success(&self.git().args([
+            "tag".to_string(),
+            "-a".to_string(),
+            plan.tag.clone(),
+            "-m".to_string(),
+            format!("{} {}", plan.package, plan.next),
+        ]))?;
+        progress.tagged = true;
+        Ok(())
+    }
+    /// Puts the manifest and the lock file back and drops the release tag and commit, if any.
+    ///
+    /// The work tree was clean before the release, so resetting to the parent of the
+    /// release commit loses nothing else.
+    fn roll_back(
+        &self,
+        plan: &ReleasePlan,
+        manifest: &str,
+        lock: &Path,
+        original_lock: Option<&[u8]>,
+        progress: &Progress,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if progress.tagged {
+            self.runner
+                .run_success(&self.git().args(["tag", "-d", plan.tag.as_str()]))?;
+        }
+        if progress.committed {
+            self.runner
+                .run_success(&self.git().args(["reset", "--hard", "HEAD~1"]))?;
+        }
+        std::fs::write(&plan.manifest, manifest)?;
+        match original_lock {
+            Some(content) => std::fs::write(lock, content)?,
+            None if lock.is_file() => std::fs::remove_file(lock)?,
+            None => {}
+        }
+        self.runner.run_success(
+            &self
+                .git()
+                .args(["reset", "--quiet", "--"])
+                .args([&plan.manifest, &lock.to_path_buf()].map(|file| file.display().to_string())),
+        )?;
+        Ok(())
+    }
+    fn git(&self) -> CommandLine {
+        CommandLine::new("git").current_dir(&self.project_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commands::write_file,
+        metadata::tests::metadata_json,
+        runner::{CommandOutput, RecordingCommandRunner},
+    };
+
+    use super::*;
+
+    const MANIFEST: &str = r#"[package]
+name = "app"
+version = "1.2.3"   # bumped by cw release
+edition = "2021"
+
+[dependencies]
+serde = "1"
+"#;
+
+    #[test]
+    fn バージョンの上げ方を解釈して適用する() {
+        let current = Version::parse("1.2.3").unwrap();
+        assert_eq!(
+            "major".parse::<Bump>().unwrap().apply(&current).to_string(),
+            "2.0.0"
+        );
+        assert_eq!(
+            "minor".parse::<Bump>().unwrap().apply(&current).to_string(),
+            "1.3.0"
+        );
+        assert_eq!(
+            "patch".parse::<Bump>().unwrap().apply(&current).to_string(),
+            "1.2.4"
+        );
+        assert_eq!(
+            "patch"
+                .parse::<Bump>()
+                .unwrap()
+                .apply(&Version::parse("1.3.0-rc.1").unwrap())
+                .to_string(),
+            "1.3.0"
+        );
+        assert_eq!(
+            "v1.5.0"
+                .parse::<Bump>()
+                .unwrap()
+                .apply(&current)
+                .to_string(),
+            "1.5.0"
+        );
+        assert!("huge".parse::<Bump>().is_err());
+    }
+    #[test]
+    fn コメントや書式を残してバージョンを書き換える() {
+        let edited = set_version(MANIFEST, &Version::new(1, 3, 0)).unwrap();
+
+        assert_eq!(
+            edited,
+            MANIFEST.replace(r#"version = "1.2.3""#, r#"version = "1.3.0""#)
+        );
+        assert!(read_version("[package]\nversion.workspace = true\n").is_err());
+    }
+    #[test]
+    fn バージョンを上げてコミットしタグを付ける() {
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().to_str().unwrap();
+        write_file(project.path().join("Cargo.toml"), MANIFEST).unwrap();
+        write_file(project.path().join("Cargo.lock"), "").unwrap();
+        let runner = RecordingCommandRunner::new()
+            .fail("git rev-parse")
+            .fail("git check-ignore")
+            .respond(
+                "cargo metadata",
+                CommandOutput::new(0).stdout(metadata_json(root, "app", &["app"])),
+            );
+
+        let plan = Release::new(project.path(), &runner)
+            .run(&Bump::Minor, |_| Ok(()))
+            .unwrap();
+
+        assert_eq!(plan.to_string(), "app 1.2.3 -> 1.3.0 (tag v1.3.0)");
+        assert_eq!(
+            read_version(&std::fs::read_to_string(project.path().join("Cargo.toml")).unwrap())
+                .unwrap(),
+            Version::new(1, 3, 0)
+        );
+        assert_eq!(
+            runner.command_lines(),
+            vec![
+                "git status --porcelain --untracked-files=no".to_string(),
+                "git rev-parse --verify --quiet v1.3.0^{commit}".to_string(),
+                "cargo metadata --format-version 1 --no-deps".to_string(),
+                "cargo update --workspace".to_string(),
+                format!("git check-ignore --quiet {}/Cargo.lock", root),
+                format!("git add {}/Cargo.toml {}/Cargo.lock", root, root),
+                "git commit -m Release app 1.3.0".to_string(),
+                "git tag -a v1.3.0 -m app 1.3.0".to_string(),
+            ]
+        );
+    }
+    #[test]
+    fn 変更が残っている時やdry_runの時は何も書き換えない() {
+        let project = tempfile::tempdir().unwrap();
+        write_file(project.path().join("Cargo.toml"), MANIFEST).unwrap();
+
+        let dirty = RecordingCommandRunner::new().respond(
+            "git status",
+            CommandOutput::new(0).stdout(" M src/main.rs\n"),
+        );
+        let result = Release::new(project.path(), &dirty).run(&Bump::Patch, |_| Ok(()));
+        assert!(result.unwrap_err().is::<DirtyWorkTree>());
+
+        let runner = RecordingCommandRunner::new().fail("git rev-parse");
+        let plan = Release::new(project.path(), &runner)
+            .dry_run(true)
+            .run(&Bump::Major, |_| panic!("built in a dry run"))
+            .unwrap();
+        assert_eq!(plan.next, Version::new(2, 0, 0));
+        assert_eq!(
+            std::fs::read_to_string(project.path().join("Cargo.toml")).unwrap(),
+            MANIFEST
+        );
+        assert_eq!(runner.command_lines().len(), 2);
+    }
+    #[test]
+    fn 途中で失敗したらマニフェストとコミットを元に戻す() {
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().to_str().unwrap();
+        write_file(project.path().join("Cargo.toml"), MANIFEST).unwrap();
+        let release = |failing: &str, build_fails: bool| {
+            write_file(project.path().join("Cargo.lock"), "locked").unwrap();
+            let runner = RecordingCommandRunner::new()
+                .fail("git rev-parse")
+                .fail("git check-ignore")
+                .respond(
+                    "cargo metadata",
+                    CommandOutput::new(0).stdout(metadata_json(root, "app", &["app"])),
+                )
+                .fail(failing);
+            let result = Release::new(project.path(), &runner).run(&Bump::Minor, |plan| {
+                assert_eq!(plan.tag, "v1.3.0");
+                if build_fails {
+                    Err("could not compile `app`".into())
+                } else {
+                    Ok(())
+                }
+            });
+            assert!(result.is_err());
+            assert_eq!(
+                std::fs::read_to_string(project.path().join("Cargo.toml")).unwrap(),
+                MANIFEST
+            );
+            assert_eq!(
+                std::fs::read_to_string(project.path().join("Cargo.lock")).unwrap(),
+                "locked"
+            );
+            runner.command_lines()
+        };
+
+        let update_failed = release("cargo update", false);
+        assert!(!update_failed
+            .iter()
+            .any(|line| line.starts_with("git commit")));
+        assert!(!update_failed.iter().any(|line| line.contains("--hard")));
+
+        let tag_failed = release("git tag", false);
+        assert!(tag_failed.contains(&"git reset --hard HEAD~1".to_string()));
+        assert!(!tag_failed.iter().any(|line| line.starts_with("git tag -d")));
+        assert_eq!(
+            tag_failed.last().unwrap(),
+            &format!(
+                "git reset --quiet -- {}/Cargo.toml {}/Cargo.lock",
+                root, root
+            )
+        );
+
+        let build_failed = release("none", true);
+        let undo = build_failed
+            .iter()
+            .position(|line| line == "git tag -d v1.3.0")
+            .unwrap();
+        assert_eq!(build_failed[undo + 1], "git reset --hard HEAD~1");
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::Path,
+};
+
+use object::{Object, ObjectSection};
+
+use crate::{build::BuiltBinary, checksum::sha256_hex};
+
+/// Where the paths of the two builds are remapped to, so that both embed the same ones.
+const REMAPPED_SOURCE: &str = "/cw/src";
+const REMAPPED_TARGET: &str = "/cw/target";
+const REMAPPED_CARGO_HOME: &str = "/cw/cargo";
+
+/// Environment for one of the builds of `--reproducible-check`.
+///
+/// `rustflags` is the `RUSTFLAGS` the user already has; the remappings are appended so that
+/// the target directory, which differs between the builds, comes last and wins.
+pub fn normalized_env(
+    project_dir: &Path,
+    target_dir: &Path,
+    cargo_home: Option<&Path>,
+    source_date_epoch: u64,
+    rustflags: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut flags: Vec<_> = rustflags
+        .map(|flags| flags.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    if let Some(cargo_home) = cargo_home {
+        flags.push(remap(cargo_home, REMAPPED_CARGO_HOME));
+    }
+    flags.push(remap(project_dir, REMAPPED_SOURCE));
+    flags.push(remap(target_dir, REMAPPED_TARGET));
+    vec![
+        (
+            "SOURCE_DATE_EPOCH".to_string(),
+            source_date_epoch.to_string(),
+        ),
+        ("RUSTFLAGS".to_string(), flags.join(" ")),
+        ("CARGO_INCREMENTAL".to_string(), "0".to_string()),
+        ("TZ".to_string(), "UTC".to_string()),
+        ("LC_ALL".to_string(), "C".to_string()),
+    ]
+}
+
+fn remap(from: &Path, to: &str) -> String {
+    format!("--remap-path-prefix={}={}", from.display(), to)
+}
+
+/// How the two builds of a binary differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// ELF sections whose contents, size or address differ, or that only one build has.
+    Sections(Vec<String>),
+    /// Every section matches; the headers or the padding between sections differ.
+    OutsideSections,
+    NotElf,
+    /// Only one of the builds produced the binary.
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryComparison {
+    pub name: String,
+    /// SHA-256 of the first build.
+    pub sha256: String,
+    pub difference: Option<Difference>,
+}
+impl Display for BinaryComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.difference {
+            None => write!(f, "{}: reproducible (sha256 {})", self.name, self.sha256),
+            Some(Difference::Sections(sections)) => {
+                write!(f, "{}: differs in {}", self.name, sections.join(", "))
+            }
+            Some(Difference::OutsideSections) => write!(
+                f,
+                "{}: differs outside of the sections (headers or padding)",
+                self.name
+            ),
+            Some(Difference::NotElf) => write!(f, "{}: differs (not an ELF file)", self.name),
+            Some(Difference::Missing) => write!(f, "{}: only built once", self.name),
+        }
+    }
+}
+
+/// Result of building twice and comparing the binaries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reproducibility {
+    pub binaries: Vec<BinaryComparison>,
+}
+impl Reproducibility {
+    pub fn compare(
+        first: &[BuiltBinary],
+        second: &[BuiltBinary],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let names = first
+            .iter()
+            .chain(second)
+            .map(|binary| binary.name.as_str())
+            .collect::<BTreeSet<_>>();
+        let find = |binaries: &[BuiltBinary], name: &str| {
+            binaries
+                .iter()
+                .find(|binary| binary.name == name)
+                .map(|binary| std::fs::read(&binary.path))
+                .transpose()
+        };
+        let mut binaries = Vec::new();
+        for name in names {
+            let (first, second) = (find(first, name)?, find(second, name)?);
+            let sha256 = first
+                .as_deref()
+                .or(second.as_deref())
+                .map(sha256_hex)
+                .unwrap_or_default();
+            let difference = match (first, second) {
+                (Some(first), Some(second)) if first == second => None,
+                (Some(first), Some(second)) => Some(match differing_sections(&first, &second) {
+                    Some(sections) if sections.is_empty() => Difference::OutsideSections,
+                    Some(sections) => Difference::Sections(sections),
+                    None => Difference::NotElf,
+                }),
+                _ => Some(Difference::Missing),
+            };
+            binaries.push(BinaryComparison {
+                name: name.to_string(),
+                sha256,
+                difference,
+            });
+        }
+        Ok(Self { binaries })
+    }
+    pub fn is_reproducible(&self) -> bool {
+        self.binaries
+            .iter()
+            .all(|binary| binary.difference.is_none())
+    }
+}
+impl Display for Reproducibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for binary in &self.binaries {
+            writeln!(f, "{}", binary)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names of the sections that differ between two ELF files, `None` when either is not one.
+pub fn differing_sections(first: &[u8], second: &[u8]) -> Option<Vec<String>> {
+    let first = object::File::parse(first).ok()?;
+    let second = object::File::parse(second).ok()?;
+    let first = sections(&first);
+    let second = sections(&second);
+    let by_name = second
+        .iter()
+        .map(|(name, contents)| (name.as_str(), contents))
+        .collect::<BTreeMap<_, _>>();
+    let mut names = Vec::new();
+    for (name, contents) in &first {
+        if by_name.get(name.as_str()) != Some(&contents) {
+            names.push(name.clone());
+        }
+    }
+    let first_names = first
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<BTreeSet<_>>();
+    for (name, _) in &second {
+        if !first_names.contains(name.as_str()) {
+            names.push(name.clone());
+        }
+    }
+    Some(names)
+}
+
+/// Address, size and contents of a section.
+type SectionContents<'data> = (u64, u64, &'data [u8]);
+
+fn sections<'data>(file: &object::File<'data>) -> Vec<(String, SectionContents<'data>)> {
+    file.sections()
+        .map(|section| {
+            let name = section.name().unwrap_or_default().to_string();
+            let contents = (
+                section.address(),
+                section.size(),
+                section.data().unwrap_or_default(),
+            );
+            (name, contents)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::write_file;
+
+    use super::*;
+    #[test]
+    fn パスを固定した環境を作る() {
+        let env = normalized_env(
+            Path::new("/work/app"),
+            Path::new("/work/app/target/cw-reproducible/1"),
+            Some(Path::new("/home/me/.cargo")),
+            1700000000,
+            Some("-C target-cpu=native"),
+        );
+        assert_eq!(
+            env[..2],
+            [
+                ("SOURCE_DATE_EPOCH".to_string(), "1700000000".to_string()),
+                (
+                    "RUSTFLAGS".to_string(),
+                    "-C target-cpu=native --remap-path-prefix=/home/me/.cargo=/cw/cargo --remap-path-prefix=/work/app=/cw/src --remap-path-prefix=/work/app/target/cw-reproducible/1=/cw/target"
+                        .to_string()
+                )
+            ]
+        );
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn 違うelfセクションを報告する() {
+        let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let (offset, _) = object::File::parse(exe.as_slice())
+            .unwrap()
+            .section_by_name(".rodata")
+            .unwrap()
+            .file_range()
+            .unwrap();
+        let mut changed = exe.clone();
+        changed[offset as usize] ^= 0xff;
+
+        assert_eq!(differing_sections(&exe, &exe), Some(Vec::new()));
+        assert_eq!(
+            differing_sections(&exe, &changed),
+            Some(vec![".rodata".to_string()])
+        );
+        assert_eq!(differing_sections(b"text", b"txet"), None);
+    }
+    #[test]
+    fn 二回のビルドのバイナリを比べる() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = |build: &str, name: &str, content: &str| {
+            let path = dir.path().join(build).join(name);
+            write_file(&path, content).unwrap();
+            BuiltBinary {
+                name: name.to_string(),
+                bin: name.to_string(),
+                path,
+                size: content.len() as u64,
+                version: None,
+            }
+        };
+        let first = vec![
+            binary("1", "same", "same"),
+            binary("1", "text", "one"),
+            binary("1", "once", "once"),
+        ];
+        let second = vec![binary("2", "same", "same"), binary("2", "text", "two")];
+
+        let reproducibility = Reproducibility::compare(&first, &second).unwrap();
+
+        assert!(!reproducibility.is_reproducible());
+        assert_eq!(
+            reproducibility.to_string(),
+            format!(
+                "once: only built once\nsame: reproducible (sha256 {})\ntext: differs (not an ELF file)\n",
+                sha256_hex(b"same")
+            )
+        );
+    }
+}
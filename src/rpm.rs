@@ -0,0 +1,590 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use md5::Md5;
+use sha2::Digest;
+
+use crate::{
+    checksum::sha256_hex,
+    package::{read_files, ArchiveEntry, PackageFile, PackageInfo},
+};
+
+/// rpm architecture of a Rust target triple.
+pub fn arch(target: &str) -> &str {
+    let cpu = target.split('-').next().unwrap_or(target);
+    match cpu {
+        "armv7" => "armv7hl",
+        "powerpc64le" => "ppc64le",
+        "riscv64gc" => "riscv64",
+        _ => cpu,
+    }
+}
+
+/// rpm versions may not contain `-`; `~` keeps a pre-release sorting before the release.
+pub fn version(version: &str) -> String {
+    version.replace('-', "~")
+}
+
+const RELEASE: &str = "1";
+
+mod tag {
+    pub const HEADER_SIGNATURES: u32 = 62;
+    pub const HEADER_IMMUTABLE: u32 = 63;
+    pub const HEADER_I18N_TABLE: u32 = 100;
+
+    pub const SIG_SHA256: u32 = 273;
+    pub const SIG_SIZE: u32 = 1000;
+    pub const SIG_MD5: u32 = 1004;
+    pub const SIG_PAYLOAD_SIZE: u32 = 1007;
+
+    pub const NAME: u32 = 1000;
+    pub const VERSION: u32 = 1001;
+    pub const RELEASE: u32 = 1002;
+    pub const SUMMARY: u32 = 1004;
+    pub const DESCRIPTION: u32 = 1005;
+    pub const BUILD_TIME: u32 = 1006;
+    pub const SIZE: u32 = 1009;
+    pub const LICENSE: u32 = 1014;
+    pub const PACKAGER: u32 = 1015;
+    pub const GROUP: u32 = 1016;
+    pub const URL: u32 = 1020;
+    pub const OS: u32 = 1021;
+    pub const ARCH: u32 = 1022;
+    pub const FILE_SIZES: u32 = 1028;
+    pub const FILE_MODES: u32 = 1030;
+    pub const FILE_RDEVS: u32 = 1033;
+    pub const FILE_MTIMES: u32 = 1034;
+    pub const FILE_DIGESTS: u32 = 1035;
+    pub const FILE_LINKTOS: u32 = 1036;
+    pub const FILE_FLAGS: u32 = 1037;
+    pub const FILE_USERNAME: u32 = 1039;
+    pub const FILE_GROUPNAME: u32 = 1040;
+    pub const SOURCE_RPM: u32 = 1044;
+    pub const FILE_VERIFY_FLAGS: u32 = 1045;
+    pub const PROVIDE_NAME: u32 = 1047;
+    pub const REQUIRE_FLAGS: u32 = 1048;
+    pub const REQUIRE_NAME: u32 = 1049;
+    pub const REQUIRE_VERSION: u32 = 1050;
+    pub const FILE_DEVICES: u32 = 1095;
+    pub const FILE_INODES: u32 = 1096;
+    pub const FILE_LANGS: u32 = 1097;
+    pub const PROVIDE_FLAGS: u32 = 1112;
+    pub const PROVIDE_VERSION: u32 = 1113;
+    pub const DIR_INDEXES: u32 = 1116;
+    pub const BASENAMES: u32 = 1117;
+    pub const DIRNAMES: u32 = 1118;
+    pub const PAYLOAD_FORMAT: u32 = 1124;
+    pub const PAYLOAD_COMPRESSOR: u32 = 1125;
+    pub const PAYLOAD_FLAGS: u32 = 1126;
+    pub const FILE_DIGEST_ALGO: u32 = 5011;
+    pub const PAYLOAD_DIGEST: u32 = 5092;
+    pub const PAYLOAD_DIGEST_ALGO: u32 = 5093;
+}
+
+const SENSE_LESS: u32 = 1 << 1;
+const SENSE_EQUAL: u32 = 1 << 3;
+const SENSE_RPMLIB: u32 = 1 << 24;
+const FILE_CONFIG_NOREPLACE: u32 = 1 | 1 << 4;
+const DIGEST_SHA256: u32 = 8;
+const S_IFREG: u32 = 0o100000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    String(String),
+    Bin(Vec<u8>),
+    StringArray(Vec<String>),
+    I18nString(String),
+}
+impl Value {
+    fn strings<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::StringArray(values.into_iter().map(String::from).collect())
+    }
+    fn kind(&self) -> u32 {
+        match self {
+            Self::Int16(_) => 3,
+            Self::Int32(_) => 4,
+            Self::String(_) => 6,
+            Self::Bin(_) => 7,
+            Self::StringArray(_) => 8,
+            Self::I18nString(_) => 9,
+        }
+    }
+    fn count(&self) -> usize {
+        match self {
+            Self::Int16(values) => values.len(),
+            Self::Int32(values) => values.len(),
+            Self::Bin(bytes) => bytes.len(),
+            Self::StringArray(values) => values.len(),
+            Self::String(_) | Self::I18nString(_) => 1,
+        }
+    }
+    fn alignment(&self) -> usize {
+        match self {
+            Self::Int16(_) => 2,
+            Self::Int32(_) => 4,
+            _ => 1,
+        }
+    }
+    fn write_to(&self, store: &mut Vec<u8>) {
+        match self {
+            Self::Int16(values) => values
+                .iter()
+                .for_each(|value| store.extend(value.to_be_bytes())),
+            Self::Int32(values) => values
+                .iter()
+                .for_each(|value| store.extend(value.to_be_bytes())),
+            Self::Bin(bytes) => store.extend(bytes),
+            Self::String(value) | Self::I18nString(value) => {
+                store.extend(value.as_bytes());
+                store.push(0);
+            }
+            Self::StringArray(values) => values.iter().for_each(|value| {
+                store.extend(value.as_bytes());
+                store.push(0);
+            }),
+        }
+    }
+}
+
+/// An rpm header: an index sorted by tag pointing into a data store,
+/// sealed by a region tag as rpm itself writes them.
+#[derive(Debug, Default)]
+struct Header {
+    entries: BTreeMap<u32, Value>,
+}
+impl Header {
+    const MAGIC: [u8; 8] = [0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+    fn set(&mut self, tag: u32, value: Value) {
+        self.entries.insert(tag, value);
+    }
+    fn to_bytes(&self, region: u32) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        for (tag, value) in &self.entries {
+            while store.len() % value.alignment() != 0 {
+                store.push(0);
+            }
+            index.push((*tag, value.kind(), store.len() as u32, value.count() as u32));
+            value.write_to(&mut store);
+        }
+        let entries = index.len() as i32 + 1;
+        index.insert(0, (region, 7, store.len() as u32, 16));
+        for field in [region, 7, (-entries * 16) as u32, 16] {
+            store.extend(field.to_be_bytes());
+        }
+
+        let mut bytes = Self::MAGIC.to_vec();
+        bytes.extend((index.len() as u32).to_be_bytes());
+        bytes.extend((store.len() as u32).to_be_bytes());
+        for (tag, kind, offset, count) in index {
+            for field in [tag, kind, offset, count] {
+                bytes.extend(field.to_be_bytes());
+            }
+        }
+        bytes.extend(store);
+        bytes
+    }
+}
+
+/// Writes `<name>-<version>-<release>.<arch>.rpm` into `out_dir`.
+///
+/// The package is a lead, a signature header with digests, the header
+/// and a gzip compressed cpio payload, so rpmbuild is not needed.
+pub fn write(
+    info: &PackageInfo,
+    target: &str,
+    files: &[PackageFile],
+    out_dir: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let files = read_files(files)?;
+    if files.is_empty() {
+        return Err("no files to put into the .rpm".into());
+    }
+    let version = version(&info.version);
+    let arch = arch(target);
+    let full_name = format!("{}-{}-{}", info.name, version, RELEASE);
+
+    let cpio = cpio(&files);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&cpio)?;
+    let payload = encoder.finish()?;
+
+    let header = header(info, &version, arch, &files, &payload).to_bytes(tag::HEADER_IMMUTABLE);
+    let mut signed = header.clone();
+    signed.extend(&payload);
+    let mut signature = Header::default();
+    signature.set(tag::SIG_SHA256, Value::String(sha256_hex(&header)));
+    signature.set(tag::SIG_SIZE, Value::Int32(vec![signed.len() as u32]));
+    signature.set(tag::SIG_MD5, Value::Bin(Md5::digest(&signed).to_vec()));
+    signature.set(tag::SIG_PAYLOAD_SIZE, Value::Int32(vec![cpio.len() as u32]));
+    let mut signature = signature.to_bytes(tag::HEADER_SIGNATURES);
+    signature.resize(signature.len().div_ceil(8) * 8, 0);
+
+    let path = out_dir.join(format!("{}.{}.rpm", full_name, arch));
+    let mut rpm = lead(&full_name, arch);
+    rpm.extend(signature);
+    rpm.extend(signed);
+    std::fs::write(&path, rpm)?;
+    Ok(path)
+}
+
+fn lead(name: &str, arch: &str) -> Vec<u8> {
+    let arch_number: u16 = match arch {
+        "x86_64" | "i386" | "i586" | "i686" => 1,
+        "aarch64" => 19,
+        _ => 0,
+    };
+    let mut lead = vec![0xed, 0xab, 0xee, 0xdb, 3, 0];
+    lead.extend(0u16.to_be_bytes());
+    lead.extend(arch_number.to_be_bytes());
+    let mut name_field = [0; 66];
+    let name = &name.as_bytes()[..name.len().min(65)];
+    name_field[..name.len()].copy_from_slice(name);
+    lead.extend(name_field);
+    // Linux, and a signature in header format.
+    lead.extend(1u16.to_be_bytes());
+    lead.extend(5u16.to_be_bytes());
+    lead.extend([0; 16]);
+    lead
+}
+
+fn header(
+    info: &PackageInfo,
+    version: &str,
+    arch: &str,
+    files: &[ArchiveEntry],
+    payload: &[u8],
+) -> Header {
+    let description = info.description.as_deref().unwrap_or(&info.name).trim();
+    let summary = description.lines().next().unwrap_or_default();
+    let mut dirnames = Vec::<String>::new();
+    let mut dir_indexes = Vec::new();
+    let mut basenames = Vec::new();
+    for file in files {
+        let (dir, base) = match file.path.rsplit_once('/') {
+            Some((dir, base)) => (format!("/{}/", dir), base),
+            None => ("/".to_string(), file.path.as_str()),
+        };
+        let index = dirnames
+            .iter()
+            .position(|name| *name == dir)
+            .unwrap_or_else(|| {
+                dirnames.push(dir);
+                dirnames.len() - 1
+            });
+        dir_indexes.push(index as u32);
+        basenames.push(base);
+    }
+    let per_file = |value: &str| Value::strings(files.iter().map(|_| value));
+    let rpmlib = [
+        ("rpmlib(CompressedFileNames)", "3.0.4-1"),
+        ("rpmlib(PayloadFilesHavePrefix)", "4.0-1"),
+        ("rpmlib(FileDigests)", "4.6.0-1"),
+    ];
+
+    let mut header = Header::default();
+    header.set(tag::HEADER_I18N_TABLE, Value::strings(["C"]));
+    header.set(tag::NAME, Value::String(info.name.clone()));
+    header.set(tag::VERSION, Value::String(version.to_string()));
+    header.set(tag::RELEASE, Value::String(RELEASE.to_string()));
+    header.set(tag::SUMMARY, Value::I18nString(summary.to_string()));
+    header.set(tag::DESCRIPTION, Value::I18nString(description.to_string()));
+    header.set(tag::BUILD_TIME, Value::Int32(vec![0]));
+    header.set(
+        tag::SIZE,
+        Value::Int32(vec![files
+            .iter()
+            .map(|file| file.content.len() as u32)
+            .sum()]),
+    );
+    if let Some(license) = &info.license {
+        header.set(tag::LICENSE, Value::String(license.clone()));
+    }
+    if let Some(maintainer) = &info.maintainer {
+        header.set(tag::PACKAGER, Value::String(maintainer.clone()));
+    }
+    header.set(tag::GROUP, Value::I18nString("Unspecified".to_string()));
+    if let Some(homepage) = &info.homepage {
+        header.set(tag::URL, Value::String(homepage.clone()));
+    }
+    header.set(tag::OS, Value::String("linux".to_string()));
+    header.set(tag::ARCH, Value::String(arch.to_string()));
+    header.set(
+        tag::FILE_SIZES,
+        Value::Int32(files.iter().map(|file| file.content.len() as u32).collect()),
+    );
+    header.set(
+        tag::FILE_MODES,
+        Value::Int16(
+            files
+                .iter()
+                .map(|file| (S_IFREG | file.mode) as u16)
+                .collect(),
+        ),
+    );
+    header.set(tag::FILE_RDEVS, Value::Int16(vec![0; files.len()]));
+    header.set(tag::FILE_MTIMES, Value::Int32(vec![0; files.len()]));
+    header.set(
+        tag::FILE_DIGESTS,
+        Value::StringArray(files.iter().map(|file| sha256_hex(&file.content)).collect()),
+    );
+    header.set(tag::FILE_LINKTOS, per_file(""));
+    header.set(
+        tag::FILE_FLAGS,
+        Value::Int32(
+            files
+                .iter()
+                .map(|file| {
+                    if file.path.starts_with("etc/") {
+                        FILE_CONFIG_NOREPLACE
+                    } else {
+                        0
+                    }
+                })
+                .collect(),
+        ),
+    );
+    header.set(tag::FILE_USERNAME, per_file("root"));
+    header.set(tag::FILE_GROUPNAME, per_file("root"));
+    header.set(
+        tag::SOURCE_RPM,
+        Value::String(format!("{}-{}-{}.src.rpm", info.name, version, RELEASE)),
+    );
+    header.set(
+        tag::FILE_VERIFY_FLAGS,
+        Value::Int32(vec![u32::MAX; files.len()]),
+    );
+    header.set(tag::PROVIDE_NAME, Value::strings([info.name.as_str()]));
+    header.set(tag::PROVIDE_FLAGS, Value::Int32(vec![SENSE_EQUAL]));
+    header.set(
+        tag::PROVIDE_VERSION,
+        Value::StringArray(vec![format!("{}-{}", version, RELEASE)]),
+    );
+    header.set(
+        tag::REQUIRE_NAME,
+        Value::strings(rpmlib.iter().map(|(name, _)| *name)),
+    );
+    header.set(
+        tag::REQUIRE_FLAGS,
+        Value::Int32(vec![SENSE_LESS | SENSE_EQUAL | SENSE_RPMLIB; rpmlib.len()]),
+    );
+    header.set(
+        tag::REQUIRE_VERSION,
+        Value::strings(rpmlib.iter().map(|(_, version)| *version)),
+    );
+    header.set(tag::FILE_DEVICES, Value::Int32(vec![1; files.len()]));
+    header.set(
+        tag::FILE_INODES,
+        Value::Int32((1..=files.len() as u32).collect()),
+    );
+    header.set(tag::FILE_LANGS, per_file(""));
+    header.set(tag::DIR_INDEXES, Value::Int32(dir_indexes));
+    header.set(tag::BASENAMES, Value::strings(basenames));
+    header.set(tag::DIRNAMES, Value::StringArray(dirnames));
+    header.set(tag::PAYLOAD_FORMAT, Value::String("cpio".to_string()));
+    header.set(tag::PAYLOAD_COMPRESSOR, Value::String("gzip".to_string()));
+    header.set(tag::PAYLOAD_FLAGS, Value::String("9".to_string()));
+    header.set(tag::FILE_DIGEST_ALGO, Value::Int32(vec![DIGEST_SHA256]));
+    header.set(
+        tag::PAYLOAD_DIGEST,
+        Value::strings([sha256_hex(payload).as_str()]),
+    );
+    header.set(tag::PAYLOAD_DIGEST_ALGO, Value::Int32(vec![DIGEST_SHA256]));
+    header
+}
+
+/// The payload in the `newc` cpio format, with the inode numbers the header uses.
+fn cpio(files: &[ArchiveEntry]) -> Vec<u8> {
+    let mut cpio = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        cpio_entry(
+            &mut cpio,
+            i as u32 + 1,
+            S_IFREG | file.mode,
+            &format!("./{}", file.path),
+            &file.content,
+        );
+    }
+    cpio_entry(&mut cpio, 0, 0, "TRAILER!!!", &[]);
+    cpio
+}
+
+fn cpio_entry(cpio: &mut Vec<u8>, inode: u32, mode: u32, name: &str, content: &[u8]) {
+    let fields = [
+        inode,
+        mode,
+        0,
+        0,
+        1,
+        0,
+        content.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    cpio.extend(b"070701");
+    for field in fields {
+        cpio.extend(format!("{:08x}", field).as_bytes());
+    }
+    cpio.extend(name.as_bytes());
+    cpio.push(0);
+    cpio.resize(cpio.len().div_ceil(4) * 4, 0);
+    cpio.extend(content);
+    cpio.resize(cpio.len().div_ceil(4) * 4, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::commands::write_file;
+
+    use super::*;
+
+    /// Entries of the header at the start of `bytes` and the length of the header.
+    fn parse_header(bytes: &[u8]) -> (BTreeMap<u32, (u32, Vec<String>)>, usize) {
+        let int = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+        assert_eq!(bytes[..8], Header::MAGIC);
+        let (count, store_len) = (int(8) as usize, int(12) as usize);
+        let store = &bytes[16 + count * 16..16 + count * 16 + store_len];
+        let mut entries = BTreeMap::new();
+        for i in 0..count {
+            let at = 16 + i * 16;
+            let (tag, kind, offset, count) = (int(at), int(at + 4), int(at + 8), int(at + 12));
+            let data = &store[offset as usize..];
+            let values = match kind {
+                4 => (0..count as usize)
+                    .map(|i| {
+                        u32::from_be_bytes(data[i * 4..i * 4 + 4].try_into().unwrap()).to_string()
+                    })
+                    .collect(),
+                6 | 8 | 9 => data
+                    .split(|byte| *byte == 0)
+                    .take(count as usize)
+                    .map(|value| String::from_utf8(value.to_vec()).unwrap())
+                    .collect(),
+                _ => vec![format!("{:?}", &data[..count as usize])],
+            };
+            entries.insert(tag, (i as u32, values));
+        }
+        (entries, 16 + count * 16 + store_len)
+    }
+    fn values(entries: &BTreeMap<u32, (u32, Vec<String>)>, tag: u32) -> Vec<String> {
+        entries[&tag].1.clone()
+    }
+    #[test]
+    fn rpmを署名ヘッダとcpioごと読み戻せる形で書き出す() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path().join("app"), "binary").unwrap();
+        write_file(dir.path().join("app.toml"), "port = 80").unwrap();
+        let info = PackageInfo {
+            description: Some("Deploys things".to_string()),
+            license: Some("MIT".to_string()),
+            ..PackageInfo::new("app", "1.2.3-rc.1")
+        };
+        let files = vec![
+            PackageFile {
+                source: dir.path().join("app"),
+                path: "usr/bin/app".to_string(),
+                mode: 0o755,
+            },
+            PackageFile {
+                source: dir.path().join("app.toml"),
+                path: "etc/app/app.toml".to_string(),
+                mode: 0o600,
+            },
+        ];
+
+        let path = write(&info, "x86_64-unknown-linux-gnu", &files, dir.path()).unwrap();
+
+        assert_eq!(path, dir.path().join("app-1.2.3~rc.1-1.x86_64.rpm"));
+        let rpm = std::fs::read(&path).unwrap();
+        assert_eq!(rpm[..4], [0xed, 0xab, 0xee, 0xdb]);
+        assert!(rpm[10..].starts_with(b"app-1.2.3~rc.1-1\0"));
+
+        let (signature, signature_len) = parse_header(&rpm[96..]);
+        let header_at = 96 + signature_len.div_ceil(8) * 8;
+        let (header, header_len) = parse_header(&rpm[header_at..]);
+        let header_bytes = &rpm[header_at..header_at + header_len];
+        assert_eq!(signature[&tag::HEADER_SIGNATURES].0, 0);
+        assert_eq!(
+            values(&signature, tag::SIG_SHA256),
+            vec![sha256_hex(header_bytes)]
+        );
+        assert_eq!(
+            values(&signature, tag::SIG_SIZE),
+            vec![(rpm.len() - header_at).to_string()]
+        );
+
+        assert_eq!(header[&tag::HEADER_IMMUTABLE].0, 0);
+        assert_eq!(values(&header, tag::NAME), vec!["app"]);
+        assert_eq!(values(&header, tag::VERSION), vec!["1.2.3~rc.1"]);
+        assert_eq!(values(&header, tag::ARCH), vec!["x86_64"]);
+        assert_eq!(values(&header, tag::LICENSE), vec!["MIT"]);
+        assert_eq!(
+            values(&header, tag::SOURCE_RPM),
+            vec!["app-1.2.3~rc.1-1.src.rpm"]
+        );
+        assert_eq!(values(&header, tag::BASENAMES), vec!["app", "app.toml"]);
+        assert_eq!(
+            values(&header, tag::DIRNAMES),
+            vec!["/usr/bin/", "/etc/app/"]
+        );
+        assert_eq!(values(&header, tag::DIR_INDEXES), vec!["0", "1"]);
+        assert_eq!(values(&header, tag::FILE_FLAGS), vec!["0", "17"]);
+        assert_eq!(
+            values(&header, tag::FILE_DIGESTS),
+            vec![sha256_hex(b"binary"), sha256_hex(b"port = 80")]
+        );
+
+        let payload = &rpm[header_at + header_len..];
+        assert_eq!(
+            values(&header, tag::PAYLOAD_DIGEST),
+            vec![sha256_hex(payload)]
+        );
+        let mut cpio = Vec::new();
+        flate2::read::GzDecoder::new(payload)
+            .read_to_end(&mut cpio)
+            .unwrap();
+        assert_eq!(
+            values(&signature, tag::SIG_PAYLOAD_SIZE),
+            vec![cpio.len().to_string()]
+        );
+        let mut unpacked = Vec::new();
+        let mut at = 0;
+        loop {
+            let field = |i: usize| {
+                usize::from_str_radix(
+                    std::str::from_utf8(&cpio[at + 6 + i * 8..at + 14 + i * 8]).unwrap(),
+                    16,
+                )
+                .unwrap()
+            };
+            assert_eq!(&cpio[at..at + 6], b"070701");
+            let (mode, size, name_len) = (field(1), field(6), field(11));
+            let name = std::str::from_utf8(&cpio[at + 110..at + 110 + name_len - 1]).unwrap();
+            if name == "TRAILER!!!" {
+                break;
+            }
+            let content_at = (at + 110 + name_len).div_ceil(4) * 4;
+            let content = std::str::from_utf8(&cpio[content_at..content_at + size]).unwrap();
+            unpacked.push(format!("{} {:o} {}", name, mode, content));
+            at = (content_at + size).div_ceil(4) * 4;
+        }
+        assert_eq!(
+            unpacked,
+            vec![
+                "./usr/bin/app 100755 binary",
+                "./etc/app/app.toml 100600 port = 80"
+            ]
+        );
+    }
+}
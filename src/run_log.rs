@@ -0,0 +1,521 @@
+use std::{
+    cell::RefCell,
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    config::LogConfig,
+    runner::{CommandLine, CommandOutput, CommandRunner},
+    time::{format_compact, format_duration, format_rfc3339},
+    xdg,
+};
+
+/// Everything that happened during one cw invocation.
+#[derive(Debug)]
+pub struct RunLog {
+    id: String,
+    command_line: String,
+    project: PathBuf,
+    started_at: SystemTime,
+    started: Instant,
+    env: Vec<(String, String)>,
+    entries: RefCell<Vec<Entry>>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Command {
+        command: String,
+        duration: Duration,
+        result: Result<CommandOutput, String>,
+    },
+    Install {
+        path: PathBuf,
+    },
+    Removal {
+        path: PathBuf,
+    },
+}
+
+impl RunLog {
+    const SECRET_MARKERS: [&'static str; 4] = ["TOKEN", "SECRET", "PASSWORD", "KEY"];
+    pub fn start(args: impl IntoIterator<Item = String>, project: impl AsRef<Path>) -> Self {
+        let started_at = SystemTime::now();
+        let project = project.as_ref();
+        Self {
+            id: Self::id_at(started_at),
+            command_line: args.into_iter().collect::<Vec<_>>().join(" "),
+            project: project
+                .canonicalize()
+                .unwrap_or_else(|_| project.to_path_buf()),
+            started_at,
+            started: Instant::now(),
+            env: Self::env_summary(std::env::vars()),
+            entries: RefCell::new(Vec::new()),
+        }
+    }
+    /// `YYYYMMDD-HHMMSS-micros-pid`, fixed width up to the pid so ids sort in start order.
+    fn id_at(started_at: SystemTime) -> String {
+        let micros = started_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_micros();
+        format!(
+            "{}-{:06}-{}",
+            format_compact(started_at),
+            micros,
+            std::process::id()
+        )
+    }
+    pub fn id(&self) -> &str {
+        self.id.as_str()
+    }
+    pub fn record_command(
+        &self,
+        command: &CommandLine,
+        duration: Duration,
+        result: Result<CommandOutput, String>,
+    ) {
+        let result = result.map(|output| match command.get_render_stdout() {
+            Some(render) => CommandOutput {
+                stdout: Self::rendered_stdout(&output.stdout, render),
+                ..output
+            },
+            None => output,
+        });
+        self.entries.borrow_mut().push(Entry::Command {
+            command: command.to_string(),
+            duration,
+            result,
+        });
+    }
+    /// Stdout as the terminal showed it: rendered diagnostics without colors, other lines as is.
+    fn rendered_stdout(stdout: &str, render: fn(&str) -> Option<String>) -> String {
+        let mut rendered = String::new();
+        for line in stdout.lines() {
+            match render(line) {
+                Some(text) => rendered.push_str(&strip_ansi(&text)),
+                None => {
+                    rendered.push_str(line);
+                    rendered.push('\n');
+                }
+            }
+        }
+        rendered
+    }
+    pub fn record_install(&self, path: impl AsRef<Path>) {
+        self.entries.borrow_mut().push(Entry::Install {
+            path: path.as_ref().to_path_buf(),
+        });
+    }
+    pub fn record_removal(&self, path: impl AsRef<Path>) {
+        self.entries.borrow_mut().push(Entry::Removal {
+            path: path.as_ref().to_path_buf(),
+        });
+    }
+    /// Cargo and rust related variables, with anything that looks like a credential masked.
+    fn env_summary(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+        let mut env = vars
+            .filter(|(key, _)| key.starts_with("CARGO") || key.starts_with("RUST"))
+            .map(|(key, value)| {
+                if Self::SECRET_MARKERS
+                    .iter()
+                    .any(|marker| key.contains(marker))
+                {
+                    (key, "***".to_string())
+                } else {
+                    (key, value)
+                }
+            })
+            .collect::<Vec<_>>();
+        env.sort();
+        env
+    }
+    pub fn render(&self, result: &Result<(), Box<dyn std::error::Error>>) -> String {
+        let mut log = String::new();
+        let status = match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+        let _ = writeln!(log, "id: {}", self.id);
+        let _ = writeln!(log, "command: {}", self.command_line);
+        let _ = writeln!(log, "project: {}", self.project.display());
+        let _ = writeln!(log, "started: {}", format_rfc3339(self.started_at));
+        let _ = writeln!(log, "duration: {}", format_duration(self.started.elapsed()));
+        let _ = writeln!(log, "status: {}", status);
+        let _ = writeln!(log, "env:");
+        for (key, value) in &self.env {
+            let _ = writeln!(log, "  {}={}", key, value);
+        }
+        for entry in self.entries.borrow().iter() {
+            match entry {
+                Entry::Command {
+                    command,
+                    duration,
+                    result,
+                } => {
+                    let _ = writeln!(log, "\n$ {} ({})", command, format_duration(*duration));
+                    match result {
+                        Ok(output) => {
+                            match output.status {
+                                Some(status) => {
+                                    let _ = writeln!(log, "exit status: {}", status);
+                                }
+                                None => {
+                                    let _ = writeln!(log, "killed by signal");
+                                }
+                            }
+                            let _ = write!(log, "{}{}", output.stdout, output.stderr);
+                        }
+                        Err(e) => {
+                            let _ = writeln!(log, "error: {}", e);
+                        }
+                    }
+                }
+                Entry::Install { path } => {
+                    let _ = writeln!(log, "\ninstalled: {}", path.display());
+                }
+                Entry::Removal { path } => {
+                    let _ = writeln!(log, "\nremoved: {}", path.display());
+                }
+            }
+        }
+        log
+    }
+}
+
+/// Removes the escape sequences that color rustc's rendered diagnostics.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' && chars.peek() == Some(&'[') {
+            chars.next();
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+            continue;
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+/// Decorates a runner so every command and its output end up in the `RunLog`.
+#[derive(Debug)]
+pub struct LoggingCommandRunner<'a, R: CommandRunner> {
+    inner: R,
+    log: &'a RunLog,
+}
+impl<'a, R: CommandRunner> LoggingCommandRunner<'a, R> {
+    pub fn new(inner: R, log: &'a RunLog) -> Self {
+        Self { inner, log }
+    }
+}
+impl<R: CommandRunner> CommandRunner for LoggingCommandRunner<'_, R> {
+    fn run(&self, command: &CommandLine) -> Result<CommandOutput, Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        let result = self.inner.run(command);
+        self.log.record_command(
+            command,
+            started.elapsed(),
+            result
+                .as_ref()
+                .map(|output| output.clone())
+                .map_err(|e| e.to_string()),
+        );
+        result
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogSummary {
+    pub id: String,
+    pub path: PathBuf,
+    pub command: String,
+    pub project: String,
+    pub started: String,
+    pub duration: String,
+    pub status: String,
+}
+
+/// Log files in the state directory, one file per invocation.
+#[derive(Debug, Clone)]
+pub struct LogStore {
+    dir: PathBuf,
+    max_files: usize,
+    max_size: Option<u64>,
+}
+
+impl LogStore {
+    const EXTENSION: &'static str = "log";
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_files: LogConfig::default().max_files,
+            max_size: None,
+        }
+    }
+    /// `$XDG_STATE_HOME/cw/logs`.
+    pub fn from_config(config: &LogConfig) -> Option<Self> {
+        xdg::state_dir().map(|dir| Self {
+            max_files: config.max_files,
+            max_size: config.max_size,
+            ..Self::new(dir.join("logs"))
+        })
+    }
+    pub fn save(
+        &self,
+        log: &RunLog,
+        result: &Result<(), Box<dyn std::error::Error>>,
+    ) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.{}", log.id(), Self::EXTENSION));
+        std::fs::write(&path, log.render(result))?;
+        self.rotate(&path)?;
+        Ok(path)
+    }
+    /// Oldest first.
+    pub fn list(&self) -> std::io::Result<Vec<LogSummary>> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        self.paths()?
+            .into_iter()
+            .map(|path| Self::summary(&path))
+            .collect()
+    }
+    pub fn list_project(&self, project: impl AsRef<Path>) -> std::io::Result<Vec<LogSummary>> {
+        let project = project.as_ref();
+        let project = project
+            .canonicalize()
+            .unwrap_or_else(|_| project.to_path_buf());
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|summary| Path::new(&summary.project) == project)
+            .collect())
+    }
+    /// Accepts any unique prefix of a log id.
+    pub fn read(&self, id: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let matched = self
+            .list()?
+            .into_iter()
+            .filter(|summary| summary.id.starts_with(id))
+            .collect::<Vec<_>>();
+        match matched.as_slice() {
+            [summary] => Ok(std::fs::read_to_string(&summary.path)?),
+            [] => Err(format!("no log found for id {}", id).into()),
+            _ => Err(format!("log id {} is ambiguous", id).into()),
+        }
+    }
+    fn paths(&self) -> std::io::Result<Vec<PathBuf>> {
+        crate::commands::FileWalker::new(&self.dir)
+            .glob(&format!("*.{}", Self::EXTENSION))
+            .walk()
+    }
+    fn summary(path: &Path) -> std::io::Result<LogSummary> {
+        let content = std::fs::read_to_string(path)?;
+        let header = |key: &str| {
+            content
+                .lines()
+                .take_while(|line| !line.is_empty())
+                .find_map(|line| line.strip_prefix(&format!("{}: ", key)))
+                .unwrap_or_default()
+                .to_string()
+        };
+        Ok(LogSummary {
+            id: header("id"),
+            path: path.to_path_buf(),
+            command: header("command"),
+            project: header("project"),
+            started: header("started"),
+            duration: header("duration"),
+            status: header("status"),
+        })
+    }
+    fn rotate(&self, keep: &Path) -> std::io::Result<()> {
+        let mut paths = self
+            .paths()?
+            .into_iter()
+            .map(|path| {
+                let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+                (path, size)
+            })
+            .collect::<Vec<_>>();
+        let mut total = paths.iter().map(|(_, size)| size).sum::<u64>();
+        while let Some((oldest, size)) = paths.first().cloned() {
+            let too_many = paths.len() > self.max_files;
+            let too_large = self.max_size.is_some_and(|max| total > max);
+            if oldest == keep || !(too_many || too_large) {
+                break;
+            }
+            std::fs::remove_file(&oldest)?;
+            total -= size;
+            paths.remove(0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::RecordingCommandRunner;
+
+    use super::*;
+    fn sample_log(project: &Path) -> RunLog {
+        let log = RunLog::start(["cw".to_string(), "build".to_string()], project);
+        let runner = LoggingCommandRunner::new(
+            RecordingCommandRunner::new().respond(
+                "cargo build",
+                CommandOutput::new(0).stderr("   Compiling app v0.1.0\n"),
+            ),
+            &log,
+        );
+        runner
+            .run_success(&CommandLine::new("cargo").arg("build"))
+            .unwrap();
+        log.record_install("/bin-dir/app");
+        log
+    }
+    #[test]
+    fn コマンドの出力とインストール先をログに残す() {
+        let project = tempfile::tempdir().unwrap();
+        let rendered = sample_log(project.path()).render(&Ok(()));
+
+        assert!(rendered.contains("command: cw build\n"));
+        assert!(rendered.contains("status: ok\n"));
+        assert!(rendered.contains("$ cargo build ("));
+        assert!(rendered.contains("   Compiling app v0.1.0\n"));
+        assert!(rendered.contains("installed: /bin-dir/app\n"));
+    }
+    #[test]
+    fn 診断はjsonではなく色を除いた表示のままログに残す() {
+        let project = tempfile::tempdir().unwrap();
+        let log = RunLog::start(["cw".to_string(), "build".to_string()], project.path());
+        let diagnostic = r#"{"reason":"compiler-message","message":{"rendered":"\u001b[0m\u001b[1m\u001b[33mwarning\u001b[0m: unused variable\n"}}"#;
+        let runner = LoggingCommandRunner::new(
+            RecordingCommandRunner::new().respond(
+                "cargo build",
+                CommandOutput::new(0).stdout(format!(
+                    "{}\n{{\"reason\":\"build-finished\",\"success\":true}}\n",
+                    diagnostic
+                )),
+            ),
+            &log,
+        );
+        runner
+            .run_success(
+                &CommandLine::new("cargo")
+                    .arg("build")
+                    .render_stdout(|line| {
+                        serde_json::from_str::<serde_json::Value>(line)
+                            .ok()?
+                            .pointer("/message/rendered")?
+                            .as_str()
+                            .map(|text| text.to_string())
+                    }),
+            )
+            .unwrap();
+
+        let rendered = log.render(&Ok(()));
+
+        assert!(rendered.contains("\nwarning: unused variable\n"));
+        assert!(!rendered.contains("compiler-message"));
+        assert!(!rendered.contains('\u{1b}'));
+        assert!(rendered.contains("{\"reason\":\"build-finished\",\"success\":true}\n"));
+    }
+    #[test]
+    fn 同じ秒に始まったログも開始順に並ぶ() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LogStore::new(dir.path());
+        let second = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ids = [900_000, 20_000, 100_000]
+            .map(|micros| RunLog::id_at(second + Duration::from_micros(micros)));
+        for id in &ids {
+            let path = dir.path().join(format!("{}.{}", id, LogStore::EXTENSION));
+            std::fs::write(path, format!("id: {}\n", id)).unwrap();
+        }
+
+        let listed = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(listed, vec![ids[1].clone(), ids[2].clone(), ids[0].clone()]);
+    }
+    #[test]
+    fn 秘密情報らしき環境変数は伏せる() {
+        let env = RunLog::env_summary(
+            vec![
+                ("CARGO_REGISTRY_TOKEN".to_string(), "abc".to_string()),
+                ("RUST_BIN_PATH".to_string(), "/bin-dir".to_string()),
+                ("HOME".to_string(), "/root".to_string()),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            env,
+            vec![
+                ("CARGO_REGISTRY_TOKEN".to_string(), "***".to_string()),
+                ("RUST_BIN_PATH".to_string(), "/bin-dir".to_string()),
+            ]
+        );
+    }
+    #[test]
+    fn ログを保存しプロジェクトやidで探せる() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        let store = LogStore::new(dir.path());
+        let log = sample_log(project.path());
+
+        store.save(&log, &Err("boom".into())).unwrap();
+
+        let summaries = store.list_project(project.path()).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].status, "error: boom");
+        assert!(store
+            .read(&log.id()[..8])
+            .unwrap()
+            .contains("$ cargo build"));
+        assert!(store.list_project(dir.path()).unwrap().is_empty());
+        assert!(store.read("no-such-id").is_err());
+    }
+    #[test]
+    fn 古いログから件数とサイズで削除する() {
+        let dir = tempfile::tempdir().unwrap();
+        for id in [
+            "20230101-000000-1",
+            "20230102-000000-1",
+            "20230103-000000-1",
+        ] {
+            std::fs::write(dir.path().join(format!("{}.log", id)), "x".repeat(10)).unwrap();
+        }
+        let store = LogStore {
+            max_files: 2,
+            ..LogStore::new(dir.path())
+        };
+        store
+            .rotate(&dir.path().join("20230103-000000-1.log"))
+            .unwrap();
+        assert_eq!(store.paths().unwrap().len(), 2);
+
+        let store = LogStore {
+            max_size: Some(15),
+            ..LogStore::new(dir.path())
+        };
+        store
+            .rotate(&dir.path().join("20230103-000000-1.log"))
+            .unwrap();
+        assert_eq!(
+            store.paths().unwrap(),
+            vec![dir.path().join("20230103-000000-1.log")]
+        );
+    }
+}
@@ -0,0 +1,677 @@
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    program: String,
+    args: Vec<String>,
+    current_dir: Option<PathBuf>,
+    envs: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    quiet_stdout: bool,
+    quiet_stderr: bool,
+    render_stdout: Option<fn(&str) -> Option<String>>,
+    forward_signals: bool,
+    null_stdin: bool,
+}
+
+impl CommandLine {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            current_dir: None,
+            envs: Vec::new(),
+            timeout: None,
+            quiet_stdout: false,
+            quiet_stderr: false,
+            render_stdout: None,
+            forward_signals: false,
+            null_stdin: false,
+        }
+    }
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+    /// Sets an environment variable on top of the inherited environment.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+    /// Kills the command when it runs longer than `timeout`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Captures stdout without echoing it, e.g. for machine readable output.
+    pub fn quiet_stdout(mut self) -> Self {
+        self.quiet_stdout = true;
+        self
+    }
+    /// Captures stderr without echoing it, for commands whose failures are expected.
+    pub fn quiet_stderr(mut self) -> Self {
+        self.quiet_stderr = true;
+        self
+    }
+    /// Instead of echoing stdout, prints to stderr what `render` makes of each line,
+    /// e.g. the human readable part of JSON output.
+    pub fn render_stdout(mut self, render: fn(&str) -> Option<String>) -> Self {
+        self.render_stdout = Some(render);
+        self
+    }
+    /// Runs the command in its own process group and forwards SIGINT/SIGTERM received by cw
+    /// to it, so that cw gets to clean up after it exits, e.g. for `cargo build`.
+    pub fn forward_signals(mut self) -> Self {
+        self.forward_signals = true;
+        self
+    }
+    /// Gives the command no stdin instead of cw's, so that it cannot wait for input.
+    pub fn null_stdin(mut self) -> Self {
+        self.null_stdin = true;
+        self
+    }
+    pub fn get_program(&self) -> &str {
+        self.program.as_str()
+    }
+    pub fn get_args(&self) -> &[String] {
+        self.args.as_slice()
+    }
+    pub fn get_current_dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+    pub fn get_envs(&self) -> &[(String, String)] {
+        self.envs.as_slice()
+    }
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+    pub fn is_quiet_stdout(&self) -> bool {
+        self.quiet_stdout
+    }
+    pub fn is_quiet_stderr(&self) -> bool {
+        self.quiet_stderr
+    }
+    pub fn get_render_stdout(&self) -> Option<fn(&str) -> Option<String>> {
+        self.render_stdout
+    }
+    pub fn is_forward_signals(&self) -> bool {
+        self.forward_signals
+    }
+    pub fn is_null_stdin(&self) -> bool {
+        self.null_stdin
+    }
+}
+impl Display for CommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// `None` when the process was terminated by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn new(status: i32) -> Self {
+        Self {
+            status: Some(status),
+            ..Default::default()
+        }
+    }
+    pub fn stdout(mut self, stdout: impl Into<String>) -> Self {
+        self.stdout = stdout.into();
+        self
+    }
+    pub fn stderr(mut self, stderr: impl Into<String>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+    /// Turns a non zero exit status of `command` into an error.
+    pub fn check(self, command: &CommandLine) -> Result<Self, Box<dyn std::error::Error>> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(Box::new(CommandFailed {
+                command: command.to_string(),
+                status: self.status,
+            }))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CommandFailed {
+    command: String,
+    status: Option<i32>,
+}
+impl Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "`{}` exited with status {}", self.command, status),
+            None => write!(f, "`{}` was terminated by a signal", self.command),
+        }
+    }
+}
+impl std::error::Error for CommandFailed {}
+
+#[derive(Debug)]
+pub struct CommandTimedOut {
+    command: String,
+    timeout: Duration,
+}
+impl Display for CommandTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` did not finish within {}s and was killed",
+            self.command,
+            self.timeout.as_secs_f64()
+        )
+    }
+}
+impl std::error::Error for CommandTimedOut {}
+
+#[derive(Debug)]
+pub struct Cancelled;
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cancelled by signal")
+    }
+}
+impl std::error::Error for Cancelled {}
+
+/// Whether cw received SIGINT or SIGTERM while forwarding signals.
+pub fn is_cancelled() -> bool {
+    signal::is_cancelled()
+}
+
+pub trait CommandRunner {
+    fn run(&self, command: &CommandLine) -> Result<CommandOutput, Box<dyn std::error::Error>>;
+    /// Same as `run` but a non zero exit status is turned into an error.
+    fn run_success(
+        &self,
+        command: &CommandLine,
+    ) -> Result<CommandOutput, Box<dyn std::error::Error>> {
+        self.run(command)?.check(command)
+    }
+}
+impl<T: CommandRunner + ?Sized> CommandRunner for &T {
+    fn run(&self, command: &CommandLine) -> Result<CommandOutput, Box<dyn std::error::Error>> {
+        (**self).run(command)
+    }
+}
+
+/// Spawns real processes, echoing their output while capturing it.
+///
+/// Commands asking for `CommandLine::forward_signals` and commands with a timeout run in their
+/// own process group, so that a timeout kills everything they spawned, and SIGINT/SIGTERM
+/// received by cw are forwarded to that group while they run. cw's previous handlers are
+/// back in place once the child exits. `forward_signals(false)` turns all of this off.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemCommandRunner {
+    forward_signals: bool,
+}
+
+impl SystemCommandRunner {
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+    const KILL_GRACE: Duration = Duration::from_secs(5);
+    pub fn new() -> Self {
+        Self {
+            forward_signals: true,
+        }
+    }
+    pub fn forward_signals(mut self, forward_signals: bool) -> Self {
+        self.forward_signals = forward_signals;
+        self
+    }
+    fn wait(
+        &self,
+        command: &CommandLine,
+        child: &mut Child,
+        group: bool,
+    ) -> Result<std::process::ExitStatus, Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if let Some(timeout) = command.get_timeout() {
+                if started.elapsed() >= timeout {
+                    Self::kill(child, group)?;
+                    return Err(Box::new(CommandTimedOut {
+                        command: command.to_string(),
+                        timeout,
+                    }));
+                }
+            }
+            std::thread::sleep(Self::POLL_INTERVAL);
+        }
+    }
+    /// Asks the child's process group to terminate and kills it after a grace period.
+    fn kill(child: &mut Child, group: bool) -> std::io::Result<()> {
+        if group && signal::terminate_group(child.id()) {
+            let deadline = Instant::now() + Self::KILL_GRACE;
+            while Instant::now() < deadline {
+                if child.try_wait()?.is_some() {
+                    return Ok(());
+                }
+                std::thread::sleep(Self::POLL_INTERVAL);
+            }
+            signal::kill_group(child.id());
+        }
+        let _ = child.kill();
+        child.wait().map(|_| ())
+    }
+}
+impl Default for SystemCommandRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, command: &CommandLine) -> Result<CommandOutput, Box<dyn std::error::Error>> {
+        if is_cancelled() {
+            return Err(Box::new(Cancelled));
+        }
+        let forward = self.forward_signals
+            && (command.is_forward_signals() || command.get_timeout().is_some());
+        let mut cmd = Command::new(command.get_program());
+        cmd.args(command.get_args());
+        if let Some(dir) = command.get_current_dir() {
+            cmd.current_dir(dir);
+        }
+        cmd.envs(command.get_envs().iter().map(|(key, value)| (key, value)));
+        if command.is_null_stdin() {
+            cmd.stdin(Stdio::null());
+        }
+        if forward {
+            signal::own_process_group(&mut cmd);
+        }
+        // Installed before spawning, so that a signal arriving in between is not lost.
+        let mut forwarding = forward.then(signal::ForwardGuard::install);
+        let mut child = cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to execute `{}`: {}", command, e))?;
+        if let Some(forwarding) = &mut forwarding {
+            forwarding.forward_to(child.id());
+        }
+
+        let quiet_stderr = command.is_quiet_stderr();
+        let stderr = child.stderr.take().map(|stderr| {
+            std::thread::spawn(move || {
+                echo_lines(stderr, |line| {
+                    if !quiet_stderr {
+                        eprintln!("{}", line)
+                    }
+                })
+            })
+        });
+        let quiet_stdout = command.is_quiet_stdout();
+        let render_stdout = command.get_render_stdout();
+        let stdout = child.stdout.take().map(|stdout| {
+            std::thread::spawn(move || {
+                echo_lines(stdout, |line| match render_stdout {
+                    Some(render) => {
+                        if let Some(text) = render(line) {
+                            eprint!("{}", text)
+                        }
+                    }
+                    None if !quiet_stdout => println!("{}", line),
+                    None => {}
+                })
+            })
+        });
+        let status = self.wait(command, &mut child, forward);
+        let stdout = stdout
+            .map(|handle| handle.join().unwrap_or_default())
+            .unwrap_or_default();
+        let stderr = stderr
+            .map(|handle| handle.join().unwrap_or_default())
+            .unwrap_or_default();
+        drop(forwarding);
+        let status = status?;
+        if is_cancelled() {
+            return Err(Box::new(Cancelled));
+        }
+
+        Ok(CommandOutput {
+            status: status.code(),
+            stdout,
+            stderr,
+        })
+    }
+}
+fn echo_lines(reader: impl Read, echo: impl Fn(&str)) -> String {
+    BufReader::new(reader)
+        .lines()
+        .map_while(Result::ok)
+        .fold(String::new(), |mut acc, line| {
+            echo(&line);
+            acc.push_str(&line);
+            acc.push('\n');
+            acc
+        })
+}
+
+#[cfg(unix)]
+mod signal {
+    use std::{
+        os::unix::process::CommandExt,
+        process::Command,
+        sync::{
+            atomic::{AtomicBool, AtomicI32, Ordering},
+            Mutex,
+        },
+    };
+
+    static CANCELLED: AtomicBool = AtomicBool::new(false);
+    static CHILD_PGID: AtomicI32 = AtomicI32::new(0);
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    extern "C" fn forward(signal: libc::c_int) {
+        CANCELLED.store(true, Ordering::SeqCst);
+        let pgid = CHILD_PGID.load(Ordering::SeqCst);
+        if pgid > 0 {
+            // SAFETY: kill is async-signal-safe.
+            unsafe {
+                libc::kill(-pgid, signal);
+            }
+        }
+    }
+
+    pub fn is_cancelled() -> bool {
+        CANCELLED.load(Ordering::SeqCst)
+    }
+    pub fn own_process_group(cmd: &mut Command) {
+        cmd.process_group(0);
+    }
+    pub fn terminate_group(pid: u32) -> bool {
+        // SAFETY: plain syscall on a process group cw created.
+        unsafe { libc::kill(-(pid as i32), libc::SIGTERM) == 0 }
+    }
+    pub fn kill_group(pid: u32) {
+        // SAFETY: plain syscall on a process group cw created.
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+    }
+
+    /// cw's own handlers while any `ForwardGuard` is alive.
+    struct Installed {
+        guards: usize,
+        previous: Option<[libc::sigaction; SIGNALS.len()]>,
+    }
+    static INSTALLED: Mutex<Installed> = Mutex::new(Installed {
+        guards: 0,
+        previous: None,
+    });
+
+    /// Forwards SIGINT/SIGTERM to the child while alive; the handlers cw had before are
+    /// restored once the last guard is dropped.
+    pub struct ForwardGuard {
+        pid: i32,
+    }
+    impl ForwardGuard {
+        pub fn install() -> Self {
+            let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+            if installed.guards == 0 {
+                // SAFETY: sigaction is plain old data; the handler only touches atomics and
+                // calls kill, and the previous actions are written by sigaction itself.
+                unsafe {
+                    let mut action: libc::sigaction = std::mem::zeroed();
+                    action.sa_sigaction =
+                        forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                    action.sa_flags = libc::SA_RESTART;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    let mut previous: [libc::sigaction; SIGNALS.len()] = std::mem::zeroed();
+                    for (signal, previous) in SIGNALS.iter().zip(&mut previous) {
+                        libc::sigaction(*signal, &action, previous);
+                    }
+                    installed.previous = Some(previous);
+                }
+            }
+            installed.guards += 1;
+            Self { pid: 0 }
+        }
+        /// Sends signals to the process group of `pid` from now on, including one that
+        /// arrived before it was spawned.
+        pub fn forward_to(&mut self, pid: u32) {
+            self.pid = pid as i32;
+            CHILD_PGID.store(self.pid, Ordering::SeqCst);
+            if is_cancelled() {
+                terminate_group(pid);
+            }
+        }
+    }
+    impl Drop for ForwardGuard {
+        fn drop(&mut self) {
+            let _ = CHILD_PGID.compare_exchange(self.pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+            let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+            installed.guards -= 1;
+            if installed.guards == 0 {
+                if let Some(previous) = installed.previous.take() {
+                    for (signal, previous) in SIGNALS.iter().zip(&previous) {
+                        // SAFETY: restores an action sigaction returned earlier.
+                        unsafe {
+                            libc::sigaction(*signal, previous, std::ptr::null_mut());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+#[cfg(not(unix))]
+mod signal {
+    use std::process::Command;
+
+    pub fn is_cancelled() -> bool {
+        false
+    }
+    pub fn own_process_group(_cmd: &mut Command) {}
+    pub fn terminate_group(_pid: u32) -> bool {
+        false
+    }
+    pub fn kill_group(_pid: u32) {}
+    pub struct ForwardGuard;
+    impl ForwardGuard {
+        pub fn install() -> Self {
+            Self
+        }
+        pub fn forward_to(&mut self, _pid: u32) {}
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Response {
+    Output(CommandOutput),
+    TimedOut,
+}
+
+/// Records every command instead of running it.
+/// Commands answer with a scripted output, or with a successful empty one.
+#[derive(Debug, Default)]
+pub struct RecordingCommandRunner {
+    commands: RefCell<Vec<CommandLine>>,
+    responses: Vec<(String, Response)>,
+}
+
+impl RecordingCommandRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Answers commands starting with `command_prefix` (e.g. `"cargo build"`) with `output`.
+    pub fn respond(mut self, command_prefix: impl Into<String>, output: CommandOutput) -> Self {
+        self.responses
+            .push((command_prefix.into(), Response::Output(output)));
+        self
+    }
+    /// Behaves as if the command hit its timeout.
+    pub fn time_out(mut self, command_prefix: impl Into<String>) -> Self {
+        self.responses
+            .push((command_prefix.into(), Response::TimedOut));
+        self
+    }
+    pub fn fail(self, command_prefix: impl Into<String>) -> Self {
+        self.respond(command_prefix, CommandOutput::new(1))
+    }
+    pub fn commands(&self) -> Vec<CommandLine> {
+        self.commands.borrow().clone()
+    }
+    pub fn command_lines(&self) -> Vec<String> {
+        self.commands
+            .borrow()
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    }
+}
+impl CommandRunner for RecordingCommandRunner {
+    fn run(&self, command: &CommandLine) -> Result<CommandOutput, Box<dyn std::error::Error>> {
+        self.commands.borrow_mut().push(command.clone());
+        let line = command.to_string();
+        match self
+            .responses
+            .iter()
+            .find(|(prefix, _)| line.starts_with(prefix.as_str()))
+            .map(|(_, response)| response.clone())
+        {
+            Some(Response::Output(output)) => Ok(output),
+            Some(Response::TimedOut) => Err(Box::new(CommandTimedOut {
+                command: line,
+                timeout: command.get_timeout().unwrap_or_default(),
+            })),
+            None => Ok(CommandOutput::new(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn 実際のコマンドの出力と終了コードを取得する() {
+        let output = SystemCommandRunner::new()
+            .run(&CommandLine::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]))
+            .unwrap();
+        assert_eq!(output.status, Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+    }
+    #[test]
+    fn 環境変数を追加して実行する() {
+        let output = SystemCommandRunner::new()
+            .run(
+                &CommandLine::new("sh")
+                    .args(["-c", "echo $CW_TEST_VALUE"])
+                    .env("CW_TEST_VALUE", "set")
+                    .quiet_stdout(),
+            )
+            .unwrap();
+        assert_eq!(output.stdout, "set\n");
+    }
+    /// Serializes the tests that install signal handlers.
+    static SIGNAL_HANDLERS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    #[test]
+    fn タイムアウトしたコマンドはプロセスグループごと終了させる() {
+        let _handlers = SIGNAL_HANDLERS.lock().unwrap_or_else(|e| e.into_inner());
+        let started = Instant::now();
+        let err = SystemCommandRunner::new()
+            .run(
+                &CommandLine::new("sh")
+                    .args(["-c", "sleep 30 & sleep 30"])
+                    .timeout(Some(Duration::from_millis(200))),
+            )
+            .unwrap_err();
+        assert!(err.is::<CommandTimedOut>());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn 求めたコマンドだけシグナルを転送し終わればハンドラを戻す() {
+        let _handlers = SIGNAL_HANDLERS.lock().unwrap_or_else(|e| e.into_inner());
+        // pid and process group of `cat` itself.
+        let stat = |command: CommandLine| {
+            let output = SystemCommandRunner::new()
+                .run(&command.arg("/proc/self/stat").quiet_stdout())
+                .unwrap();
+            let (pid, rest) = output.stdout.split_once(' ').unwrap();
+            let pgrp = rest.rsplit_once(") ").unwrap().1.split(' ').nth(2).unwrap();
+            (pid.parse::<i32>().unwrap(), pgrp.parse::<i32>().unwrap())
+        };
+        let sigint = || {
+            // SAFETY: only reads the current action.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(libc::SIGINT, std::ptr::null(), &mut action);
+                action.sa_sigaction
+            }
+        };
+        let before = sigint();
+
+        let (_, plain) = stat(CommandLine::new("cat"));
+        let (pid, forwarded) = stat(CommandLine::new("cat").forward_signals());
+
+        // SAFETY: plain syscall.
+        assert_eq!(plain, unsafe { libc::getpgrp() });
+        assert_eq!(forwarded, pid);
+        assert_eq!(sigint(), before);
+    }
+    #[test]
+    fn 存在しないコマンドはエラーを返す() {
+        assert!(SystemCommandRunner::new()
+            .run(&CommandLine::new("cw-command-not-found"))
+            .is_err());
+    }
+    #[test]
+    fn 記録用のrunnerはコマンドを記録し失敗を再現できる() {
+        let runner = RecordingCommandRunner::new().fail("cargo build");
+        runner
+            .run_success(&CommandLine::new("cargo").arg("check"))
+            .unwrap();
+        let err = runner
+            .run_success(&CommandLine::new("cargo").args(["build", "--release"]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`cargo build --release` exited with status 1"
+        );
+        assert_eq!(
+            runner.command_lines(),
+            vec!["cargo check", "cargo build --release"]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use crate::runner::{CommandLine, CommandRunner};
+
+/// The parts of `rustc -vV` cw cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustcVersion {
+    /// e.g. `rustc 1.70.0 (90c541806 2023-05-31)`
+    pub version: String,
+    /// Target triple of the host, e.g. `x86_64-unknown-linux-gnu`.
+    pub host: String,
+}
+
+impl RustcVersion {
+    /// Runs the `rustc` the project would use, honoring `rust-toolchain` files.
+    pub fn load(
+        runner: &impl CommandRunner,
+        project_dir: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = runner.run_success(
+            &CommandLine::new("rustc")
+                .arg("-vV")
+                .current_dir(project_dir)
+                .quiet_stdout(),
+        )?;
+        Self::parse(&output.stdout)
+    }
+    pub fn parse(output: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut lines = output.lines();
+        let version = lines
+            .next()
+            .filter(|line| line.starts_with("rustc "))
+            .ok_or("unexpected output of `rustc -vV`")?
+            .to_string();
+        let host = lines
+            .find_map(|line| line.strip_prefix("host: "))
+            .ok_or("`rustc -vV` did not report the host")?
+            .to_string();
+        Ok(Self { version, host })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const RUSTC_VV: &str = "\
+rustc 1.70.0 (90c541806 2023-05-31)
+binary: rustc
+commit-hash: 90c541806f23a127002de5b4038be731ba1458ca
+commit-date: 2023-05-31
+host: x86_64-unknown-linux-gnu
+release: 1.70.0
+LLVM version: 16.0.2
+";
+    #[test]
+    fn rustcのバージョンとホストを読み取る() {
+        assert_eq!(
+            RustcVersion::parse(RUSTC_VV).unwrap(),
+            RustcVersion {
+                version: "rustc 1.70.0 (90c541806 2023-05-31)".to_string(),
+                host: "x86_64-unknown-linux-gnu".to_string(),
+            }
+        );
+        assert!(RustcVersion::parse("").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fmt::Display,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::{
+    checksum::{hex, parse_hex},
+    xdg,
+};
+
+/// A local ed25519 key signing the name and SHA-256 of every installed binary.
+///
+/// The file holds the hex encoded seed; the public key lives next to it in `<file>.pub`
+/// so that `cw verify` can check signatures without the secret.
+#[derive(Debug)]
+pub struct SignKey {
+    key: SigningKey,
+}
+
+/// The public half of a `SignKey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    key: VerifyingKey,
+}
+
+#[derive(Debug)]
+pub struct KeyExists {
+    pub path: PathBuf,
+}
+impl Display for KeyExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} already exists; remove it first to replace the key",
+            self.path.display()
+        )
+    }
+}
+impl std::error::Error for KeyExists {}
+
+impl SignKey {
+    const FILE_NAME: &'static str = "signing.key";
+    /// `$XDG_CONFIG_HOME/cw/signing.key`
+    pub fn default_path() -> Option<PathBuf> {
+        xdg::config_dir().map(|dir| dir.join(Self::FILE_NAME))
+    }
+    pub fn public_path(path: impl AsRef<Path>) -> PathBuf {
+        let mut name = path.as_ref().as_os_str().to_owned();
+        name.push(".pub");
+        PathBuf::from(name)
+    }
+    /// Creates a key from `/dev/urandom`, refusing to overwrite an existing one.
+    pub fn generate(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(Box::new(KeyExists {
+                path: path.to_path_buf(),
+            }));
+        }
+        let mut seed = [0; 32];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+        let key = Self::from_seed(seed);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_secret(path, &hex(&seed))?;
+        std::fs::write(Self::public_path(path), key.public_key().to_string() + "\n")?;
+        Ok(key)
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("signing key {}: {}", path.display(), e))?;
+        let seed = parse_hex(content.trim())
+            .and_then(|seed| <[u8; 32]>::try_from(seed).ok())
+            .ok_or_else(|| format!("{} is not an ed25519 signing key", path.display()))?;
+        Ok(Self::from_seed(seed))
+    }
+    fn from_seed(seed: [u8; 32]) -> Self {
+        Self {
+            key: SigningKey::from_bytes(&seed),
+        }
+    }
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            key: self.key.verifying_key(),
+        }
+    }
+    /// Signs the hex digest recorded in the ledger together with the name it is installed as,
+    /// so that a signature cannot be moved to another entry.
+    pub fn sign(&self, name: &str, sha256: &str) -> String {
+        hex(&self.key.sign(&message(name, sha256)).to_bytes())
+    }
+}
+
+impl PublicKey {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("public key {}: {}", path.display(), e))?;
+        parse_hex(content.trim())
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .map(|key| Self { key })
+            .ok_or_else(|| format!("{} is not an ed25519 public key", path.display()).into())
+    }
+    pub fn verify(&self, name: &str, sha256: &str, signature: &str) -> bool {
+        parse_hex(signature)
+            .and_then(|signature| Signature::from_slice(&signature).ok())
+            .is_some_and(|signature| self.key.verify(&message(name, sha256), &signature).is_ok())
+    }
+}
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex(self.key.as_bytes()))
+    }
+}
+
+/// The line `sha256sum` would print for the binary.
+fn message(name: &str, sha256: &str) -> Vec<u8> {
+    format!("{}  {}", sha256, name).into_bytes()
+}
+
+#[cfg(unix)]
+fn write_secret(path: &Path, content: &str) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(content.as_bytes())
+}
+#[cfg(not(unix))]
+fn write_secret(path: &Path, content: &str) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn sign_key() -> SignKey {
+        SignKey::from_seed([7; 32])
+    }
+    #[test]
+    fn 署名を公開鍵で検証できる() {
+        let key = sign_key();
+        let signature = key.sign("app", "abcd");
+
+        assert!(key.public_key().verify("app", "abcd", &signature));
+        assert!(!key.public_key().verify("app", "abce", &signature));
+        assert!(!key.public_key().verify("other", "abcd", &signature));
+        assert!(!key.public_key().verify("app", "abcd", "not hex"));
+        assert!(!SignKey::from_seed([8; 32])
+            .public_key()
+            .verify("app", "abcd", &signature));
+    }
+    #[test]
+    fn 鍵を作って読み込み上書きはしない() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cw/signing.key");
+
+        let key = SignKey::generate(&path).unwrap();
+        let loaded = SignKey::load(&path).unwrap();
+        let public = PublicKey::load(dir.path().join("cw/signing.key.pub")).unwrap();
+
+        assert_eq!(loaded.public_key(), key.public_key());
+        assert_eq!(public, key.public_key());
+        assert!(public.verify("app", "abcd", &loaded.sign("app", "abcd")));
+        assert!(SignKey::generate(&path).unwrap_err().is::<KeyExists>());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    config::SmokeConfig,
+    runner::{CommandLine, CommandRunner},
+};
+
+/// Runs an installed binary once to see that it starts at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmokeTest {
+    /// Tried in order until one of them exits successfully.
+    attempts: Vec<Vec<String>>,
+    timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub struct SmokeTestFailed {
+    binary: PathBuf,
+    reason: String,
+}
+impl Display for SmokeTestFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "smoke test of {} failed: {}",
+            self.binary.display(),
+            self.reason
+        )
+    }
+}
+impl std::error::Error for SmokeTestFailed {}
+
+impl SmokeTest {
+    /// Tries `--version`, then `--help`.
+    pub fn new() -> Self {
+        Self {
+            attempts: vec![vec!["--version".to_string()], vec!["--help".to_string()]],
+            timeout: Some(SmokeConfig::DEFAULT_TIMEOUT),
+        }
+    }
+    pub fn from_config(config: &SmokeConfig) -> Self {
+        let smoke_test = Self::new().timeout(config.timeout);
+        match &config.args {
+            Some(args) => smoke_test.args(args.clone()),
+            None => smoke_test,
+        }
+    }
+    /// Runs the binary with exactly these arguments instead of the defaults.
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.attempts = vec![args];
+        self
+    }
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    pub fn check(&self, runner: &impl CommandRunner, binary: &Path) -> Result<(), SmokeTestFailed> {
+        let mut reason = String::new();
+        for args in &self.attempts {
+            let command = CommandLine::new(binary.display().to_string())
+                .args(args.iter().cloned())
+                // With a timeout it runs in a process group of its own, where reading the
+                // terminal would stop it until killed.
+                .null_stdin()
+                .quiet_stdout()
+                .timeout(self.timeout);
+            match runner
+                .run(&command)
+                .and_then(|output| output.check(&command))
+            {
+                Ok(_) => return Ok(()),
+                Err(e) => reason = e.to_string(),
+            }
+        }
+        Err(SmokeTestFailed {
+            binary: binary.to_path_buf(),
+            reason,
+        })
+    }
+}
+impl Default for SmokeTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::{CommandOutput, RecordingCommandRunner};
+
+    use super::*;
+    #[test]
+    fn versionが失敗してもhelpが成功すれば通る() {
+        let runner = RecordingCommandRunner::new().fail("/bin/app --version");
+
+        let result = SmokeTest::new().check(&runner, Path::new("/bin/app"));
+
+        assert!(result.is_ok());
+        assert_eq!(
+            runner.command_lines(),
+            vec!["/bin/app --version", "/bin/app --help"]
+        );
+        assert_eq!(
+            runner.commands()[0].get_timeout(),
+            Some(Duration::from_secs(10))
+        );
+        assert!(runner.commands()[0].is_null_stdin());
+    }
+    #[test]
+    fn 指定した引数で失敗すれば失敗になる() {
+        let runner = RecordingCommandRunner::new()
+            .respond("/bin/app check", CommandOutput::new(101).stderr("panicked"));
+
+        let err = SmokeTest::new()
+            .args(vec!["check".to_string()])
+            .check(&runner, Path::new("/bin/app"))
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "smoke test of /bin/app failed: `/bin/app check` exited with status 101"
+        );
+        assert_eq!(runner.command_lines(), vec!["/bin/app check"]);
+    }
+}
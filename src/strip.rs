@@ -0,0 +1,149 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    history::format_size,
+    runner::{CommandLine, CommandRunner},
+};
+
+/// Strips installed binaries with binutils, leaving the target directory untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Strip {
+    keep_debug: bool,
+}
+
+/// Sizes of an installed binary around stripping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stripped {
+    pub path: PathBuf,
+    pub before: u64,
+    pub after: u64,
+    /// `<bin>.debug` next to the binary, linked with a gnu-debuglink.
+    pub debug_file: Option<PathBuf>,
+}
+impl Display for Stripped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "stripped {}: {} -> {}",
+            self.path.display(),
+            format_size(self.before),
+            format_size(self.after)
+        )?;
+        if let Some(debug_file) = &self.debug_file {
+            write!(f, " (debug info in {})", debug_file.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl Strip {
+    const DEBUG_EXTENSION: &'static str = "debug";
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Saves the removed debug info as `<bin>.debug` and links the binary to it.
+    pub fn keep_debug(mut self, keep_debug: bool) -> Self {
+        self.keep_debug = keep_debug;
+        self
+    }
+    pub fn debug_file(binary: &Path) -> PathBuf {
+        let mut name = binary.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(Self::DEBUG_EXTENSION);
+        binary.with_file_name(name)
+    }
+    /// Strips `binary` in place, saving its debug info to `debug_file` when kept.
+    ///
+    /// The binary links to the debug info by the file name of `debug_file`, so it has to be
+    /// named as it will be installed, e.g. `app.debug` in a staging directory.
+    pub fn strip(
+        &self,
+        runner: &impl CommandRunner,
+        binary: &Path,
+        debug_file: &Path,
+    ) -> Result<Stripped, Box<dyn std::error::Error>> {
+        let before = binary.metadata()?.len();
+        let file = binary.display().to_string();
+        let debug_file = self.keep_debug.then(|| debug_file.to_path_buf());
+        if let Some(debug_file) = &debug_file {
+            runner.run_success(
+                &CommandLine::new("objcopy")
+                    .args(["--only-keep-debug", file.as_str()])
+                    .arg(debug_file.display().to_string()),
+            )?;
+        }
+        runner.run_success(&CommandLine::new("strip").arg(file.as_str()))?;
+        if let Some(debug_file) = &debug_file {
+            runner.run_success(
+                &CommandLine::new("objcopy")
+                    .arg(format!("--add-gnu-debuglink={}", debug_file.display()))
+                    .arg(file.as_str()),
+            )?;
+        }
+        Ok(Stripped {
+            path: binary.to_path_buf(),
+            before,
+            after: binary.metadata()?.len(),
+            debug_file,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commands::write_file,
+        runner::{RecordingCommandRunner, SystemCommandRunner},
+    };
+
+    use super::*;
+    #[test]
+    fn デバッグ情報を分けて保存しリンクする() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app");
+        write_file(&app, "binary").unwrap();
+        let runner = RecordingCommandRunner::new();
+
+        let stripped = Strip::new()
+            .keep_debug(true)
+            .strip(&runner, &app, &Strip::debug_file(&app))
+            .unwrap();
+
+        let app = app.display();
+        let debug = dir.path().join("app.debug");
+        assert_eq!(
+            runner.command_lines(),
+            vec![
+                format!("objcopy --only-keep-debug {} {}", app, debug.display()),
+                format!("strip {}", app),
+                format!("objcopy --add-gnu-debuglink={} {}", debug.display(), app),
+            ]
+        );
+        assert_eq!(stripped.before, 6);
+        assert_eq!(stripped.debug_file, Some(debug));
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn 実際のバイナリを小さくする() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app");
+        std::fs::copy(std::env::current_exe().unwrap(), &app).unwrap();
+        let runner = SystemCommandRunner::new().forward_signals(false);
+        if runner
+            .run(&CommandLine::new("strip").arg("--version"))
+            .is_err()
+        {
+            return;
+        }
+
+        let stripped = Strip::new()
+            .strip(&runner, &app, &Strip::debug_file(&app))
+            .unwrap();
+
+        assert!(stripped.after < stripped.before);
+        assert_eq!(stripped.after, app.metadata().unwrap().len());
+    }
+}
@@ -0,0 +1,504 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    git::{head_commit, is_dirty, rev_parse, short},
+    ledger::Ledger,
+    metadata::CargoMetadata,
+    runner::CommandRunner,
+    xdg,
+};
+
+/// The tools a team expects everyone to have installed, read from `toolbox.toml`.
+///
+/// ```toml
+/// [[tool]]
+/// path = "../deploy-tool"
+/// rev = "main"
+/// profile = "release"
+/// bins = ["deploy"]
+/// features = ["s3"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Toolbox {
+    #[serde(default, rename = "tool")]
+    pub tools: Vec<Tool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Tool {
+    /// Project directory; relative paths are relative to `toolbox.toml`, `~` is expanded.
+    pub path: PathBuf,
+    /// Branch, tag or commit the checkout is expected to be at.
+    pub rev: Option<String>,
+    #[serde(default = "Tool::default_profile")]
+    pub profile: String,
+    /// All bin targets of the project when empty.
+    #[serde(default)]
+    pub bins: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+impl Tool {
+    fn default_profile() -> String {
+        "release".to_string()
+    }
+}
+
+impl Toolbox {
+    pub const FILE_NAME: &'static str = "toolbox.toml";
+    /// `toolbox.toml` in `dir`, else `$XDG_CONFIG_HOME/cw/toolbox.toml`.
+    pub fn find(dir: impl AsRef<Path>) -> Option<PathBuf> {
+        std::iter::once(dir.as_ref().join(Self::FILE_NAME))
+            .chain(xdg::config_dir().map(|dir| dir.join(Self::FILE_NAME)))
+            .find(|path| path.is_file())
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        Self::from_toml(&content, base).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+    pub fn from_toml(content: &str, base: &Path) -> Result<Self, toml::de::Error> {
+        let mut toolbox: Self = toml::from_str(content)?;
+        for tool in &mut toolbox.tools {
+            tool.path = resolve(&tool.path, base);
+        }
+        Ok(toolbox)
+    }
+}
+
+fn resolve(path: &Path, base: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        if let Some(home) = std::env::var_os("HOME") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+    base.join(path)
+}
+
+/// How an installed tool differs from `toolbox.toml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    ProjectMissing,
+    /// `rev` does not name a commit of the checkout.
+    UnknownRevision {
+        rev: String,
+        reason: String,
+    },
+    /// `cargo metadata` failed, so the bins of the project are unknown.
+    MetadataFailed {
+        reason: String,
+    },
+    /// The checkout is at another commit than `rev`; cw does not switch branches for you.
+    WrongRevision {
+        rev: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    /// The checkout is at `rev` but has uncommitted changes, so a build would not be `rev`.
+    Dirty {
+        rev: String,
+    },
+    NotInstalled {
+        bin: String,
+    },
+    Outdated {
+        bin: String,
+        reason: String,
+    },
+}
+impl Drift {
+    /// Whether building the project as it is on disk resolves it.
+    pub fn is_fixable(&self) -> bool {
+        matches!(self, Self::NotInstalled { .. } | Self::Outdated { .. })
+    }
+}
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ProjectMissing => write!(f, "project directory does not exist"),
+            Self::UnknownRevision { rev, reason } => {
+                write!(f, "unknown revision {}: {}", rev, reason)
+            }
+            Self::MetadataFailed { reason } => write!(f, "cargo metadata failed: {}", reason),
+            Self::WrongRevision {
+                rev,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "checked out at {}, expected {} ({})",
+                actual.as_deref().map(short).unwrap_or("no commit"),
+                rev,
+                short(expected)
+            ),
+            Self::Dirty { rev } => write!(
+                f,
+                "checkout is at {} but has uncommitted changes; commit or stash them",
+                rev
+            ),
+            Self::NotInstalled { bin } => write!(f, "{} is not installed", bin),
+            Self::Outdated { bin, reason } => write!(f, "{} is outdated: {}", bin, reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolStatus {
+    pub tool: Tool,
+    pub drifts: Vec<Drift>,
+}
+
+/// Compares the tools of a toolbox with the install ledger.
+pub struct ToolboxChecker<R: CommandRunner> {
+    install_dir: PathBuf,
+    runner: R,
+}
+
+impl<R: CommandRunner> ToolboxChecker<R> {
+    pub fn new(install_dir: impl AsRef<Path>, runner: R) -> Self {
+        Self {
+            install_dir: install_dir.as_ref().to_path_buf(),
+            runner,
+        }
+    }
+    pub fn check(&self, toolbox: &Toolbox) -> Result<Vec<ToolStatus>, Box<dyn std::error::Error>> {
+        let ledger = Ledger::load(&self.install_dir)?;
+        toolbox
+            .tools
+            .iter()
+            .map(|tool| {
+                Ok(ToolStatus {
+                    tool: tool.clone(),
+                    drifts: self.drifts(tool, &ledger)?,
+                })
+            })
+            .collect()
+    }
+    fn drifts(
+        &self,
+        tool: &Tool,
+        ledger: &Ledger,
+    ) -> Result<Vec<Drift>, Box<dyn std::error::Error>> {
+        let Ok(project) = tool.path.canonicalize() else {
+            return Ok(vec![Drift::ProjectMissing]);
+        };
+        let commit = head_commit(&self.runner, &project);
+        if let Some(rev) = &tool.rev {
+            let expected = match rev_parse(&self.runner, &project, rev) {
+                Ok(expected) => expected,
+                Err(e) => {
+                    return Ok(vec![Drift::UnknownRevision {
+                        rev: rev.clone(),
+                        reason: e.to_string(),
+                    }])
+                }
+            };
+            if commit.as_ref() != Some(&expected) {
+                return Ok(vec![Drift::WrongRevision {
+                    rev: rev.clone(),
+                    expected,
+                    actual: commit,
+                }]);
+            }
+            if is_dirty(&self.runner, &project).unwrap_or(false) {
+                return Ok(vec![Drift::Dirty { rev: rev.clone() }]);
+            }
+        }
+        let bins = if tool.bins.is_empty() {
+            match CargoMetadata::load(&self.runner, &project) {
+                Ok(metadata) => metadata
+                    .bin_names()
+                    .into_iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+                Err(e) => {
+                    return Ok(vec![Drift::MetadataFailed {
+                        reason: e.to_string(),
+                    }])
+                }
+            }
+        } else {
+            tool.bins.clone()
+        };
+        let project = project.display().to_string();
+        let mut features = tool.features.clone();
+        features.sort();
+        Ok(bins
+            .into_iter()
+            .filter_map(|bin| {
+                let Some(entry) = ledger.get(&bin).filter(|entry| entry.project == project) else {
+                    return Some(Drift::NotInstalled { bin });
+                };
+                let mut installed_features = entry.features.clone();
+                installed_features.sort();
+                let reason = if entry.commit != commit {
+                    format!(
+                        "installed from {}, checkout is at {}",
+                        entry
+                            .commit
+                            .as_deref()
+                            .map(short)
+                            .unwrap_or("unknown commit"),
+                        commit.as_deref().map(short).unwrap_or("no commit")
+                    )
+                } else if entry.profile != tool.profile {
+                    format!("installed as {}, expected {}", entry.profile, tool.profile)
+                } else if installed_features != features {
+                    format!(
+                        "installed with features [{}], expected [{}]",
+                        installed_features.join(", "),
+                        features.join(", ")
+                    )
+                } else {
+                    return None;
+                };
+                Some(Drift::Outdated { bin, reason })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commands::write_file,
+        ledger::{tests::entry, LedgerEntry},
+        runner::{CommandOutput, RecordingCommandRunner},
+    };
+
+    use super::*;
+    #[test]
+    fn toolbox_tomlを読み込みパスを解決する() {
+        let toolbox = Toolbox::from_toml(
+            r#"
+[[tool]]
+path = "deploy"
+rev = "main"
+bins = ["deploy"]
+features = ["s3"]
+
+[[tool]]
+path = "/work/lint"
+profile = "debug"
+"#,
+            Path::new("/team"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            toolbox.tools,
+            vec![
+                Tool {
+                    path: PathBuf::from("/team/deploy"),
+                    rev: Some("main".to_string()),
+                    profile: "release".to_string(),
+                    bins: vec!["deploy".to_string()],
+                    features: vec!["s3".to_string()],
+                },
+                Tool {
+                    path: PathBuf::from("/work/lint"),
+                    rev: None,
+                    profile: "debug".to_string(),
+                    bins: Vec::new(),
+                    features: Vec::new(),
+                }
+            ]
+        );
+    }
+    #[test]
+    fn 台帳と比べて未インストールや古いものを見つける() {
+        let install_dir = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        write_file(project.path().join("Cargo.toml"), "").unwrap();
+        let project_path = project.path().canonicalize().unwrap();
+        let project_path = project_path.to_str().unwrap();
+        let mut ledger = Ledger::default();
+        ledger.record(LedgerEntry {
+            commit: Some("new".to_string()),
+            ..entry("current", project_path)
+        });
+        ledger.record(LedgerEntry {
+            commit: Some("old".to_string()),
+            ..entry("stale", project_path)
+        });
+        ledger.record(LedgerEntry {
+            commit: Some("new".to_string()),
+            ..entry("featured", project_path)
+        });
+        ledger.save(install_dir.path()).unwrap();
+        let runner = RecordingCommandRunner::new().respond(
+            "git rev-parse --verify --quiet HEAD",
+            CommandOutput::new(0).stdout("new\n"),
+        );
+        let toolbox = Toolbox {
+            tools: vec![
+                Tool {
+                    path: project.path().to_path_buf(),
+                    rev: None,
+                    profile: "release".to_string(),
+                    bins: ["current", "stale", "missing"].map(String::from).to_vec(),
+                    features: Vec::new(),
+                },
+                Tool {
+                    path: project.path().to_path_buf(),
+                    rev: None,
+                    profile: "release".to_string(),
+                    bins: vec!["featured".to_string()],
+                    features: vec!["extra".to_string()],
+                },
+                Tool {
+                    path: PathBuf::from("/no/such/project"),
+                    rev: None,
+                    profile: "release".to_string(),
+                    bins: Vec::new(),
+                    features: Vec::new(),
+                },
+            ],
+        };
+
+        let statuses = ToolboxChecker::new(install_dir.path(), &runner)
+            .check(&toolbox)
+            .unwrap();
+
+        let drifts = statuses
+            .iter()
+            .map(|status| {
+                status
+                    .drifts
+                    .iter()
+                    .map(|drift| drift.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            drifts,
+            vec![
+                vec![
+                    "stale is outdated: installed from old, checkout is at new".to_string(),
+                    "missing is not installed".to_string(),
+                ],
+                vec![
+                    "featured is outdated: installed with features [], expected [extra]"
+                        .to_string()
+                ],
+                vec!["project directory does not exist".to_string()],
+            ]
+        );
+    }
+    #[test]
+    fn 期待したリビジョンでなければ報告する() {
+        let project = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "git rev-parse --verify --quiet HEAD",
+                CommandOutput::new(0).stdout("aaaa\n"),
+            )
+            .respond(
+                "git rev-parse --verify --quiet v1.0",
+                CommandOutput::new(0).stdout("bbbb\n"),
+            );
+        let toolbox = Toolbox {
+            tools: vec![Tool {
+                path: project.path().to_path_buf(),
+                rev: Some("v1.0".to_string()),
+                profile: "release".to_string(),
+                bins: Vec::new(),
+                features: Vec::new(),
+            }],
+        };
+
+        let statuses = ToolboxChecker::new(project.path(), &runner)
+            .check(&toolbox)
+            .unwrap();
+
+        assert_eq!(
+            statuses[0].drifts,
+            vec![Drift::WrongRevision {
+                rev: "v1.0".to_string(),
+                expected: "bbbb".to_string(),
+                actual: Some("aaaa".to_string())
+            }]
+        );
+        assert!(!statuses[0].drifts[0].is_fixable());
+    }
+    #[test]
+    fn リビジョンが合っていても変更があれば報告する() {
+        let project = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new()
+            .respond(
+                "git rev-parse --verify --quiet",
+                CommandOutput::new(0).stdout("aaaa\n"),
+            )
+            .respond(
+                "git status --porcelain",
+                CommandOutput::new(0).stdout(" M src/main.rs\n"),
+            );
+        let toolbox = Toolbox {
+            tools: vec![Tool {
+                path: project.path().to_path_buf(),
+                rev: Some("v1.0".to_string()),
+                profile: "release".to_string(),
+                bins: Vec::new(),
+                features: Vec::new(),
+            }],
+        };
+
+        let statuses = ToolboxChecker::new(project.path(), &runner)
+            .check(&toolbox)
+            .unwrap();
+
+        assert_eq!(
+            statuses[0].drifts,
+            vec![Drift::Dirty {
+                rev: "v1.0".to_string()
+            }]
+        );
+        assert!(!statuses[0].drifts[0].is_fixable());
+    }
+    #[test]
+    fn 一つのツールで失敗しても他のツールを調べ続ける() {
+        let install_dir = tempfile::tempdir().unwrap();
+        let unknown_rev = tempfile::tempdir().unwrap();
+        let broken = tempfile::tempdir().unwrap();
+        let runner = RecordingCommandRunner::new()
+            .fail("git rev-parse --verify --quiet gone")
+            .fail("cargo metadata");
+        let tool = |path: &Path, rev: Option<&str>| Tool {
+            path: path.to_path_buf(),
+            rev: rev.map(String::from),
+            profile: "release".to_string(),
+            bins: Vec::new(),
+            features: Vec::new(),
+        };
+        let toolbox = Toolbox {
+            tools: vec![
+                tool(unknown_rev.path(), Some("gone")),
+                tool(broken.path(), None),
+                tool(Path::new("/no/such/project"), None),
+            ],
+        };
+
+        let statuses = ToolboxChecker::new(install_dir.path(), &runner)
+            .check(&toolbox)
+            .unwrap();
+
+        assert!(matches!(
+            &statuses[0].drifts[..],
+            [Drift::UnknownRevision { rev, .. }] if rev == "gone"
+        ));
+        assert!(matches!(
+            &statuses[1].drifts[..],
+            [Drift::MetadataFailed { .. }]
+        ));
+        assert_eq!(statuses[2].drifts, vec![Drift::ProjectMissing]);
+        assert!(statuses
+            .iter()
+            .all(|status| status.drifts.iter().all(|drift| !drift.is_fixable())));
+    }
+}
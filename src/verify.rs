@@ -0,0 +1,207 @@
+use std::{fmt::Display, path::Path};
+
+use crate::{checksum::sha256_file, ledger::Ledger, sign::PublicKey};
+
+/// What `cw verify` found for one installed binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Verified {
+        signed: bool,
+    },
+    Missing,
+    Modified {
+        expected: String,
+        actual: String,
+    },
+    BadSignature,
+    /// Installed before cw recorded checksums.
+    Unrecorded,
+    /// Signed, but there is no public key to check the signature with.
+    Unverifiable,
+    /// No signature or checksum although signing is in use, e.g. stripped from the ledger.
+    Unsigned,
+}
+impl Verdict {
+    /// Whether the file is no longer what cw installed.
+    pub fn is_tampered(&self) -> bool {
+        matches!(
+            self,
+            Self::Missing | Self::Modified { .. } | Self::BadSignature | Self::Unsigned
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub name: String,
+    pub verdict: Verdict,
+}
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.verdict {
+            Verdict::Verified { signed: true } => write!(f, "{}: ok, signature valid", self.name),
+            Verdict::Verified { signed: false } => write!(f, "{}: ok", self.name),
+            Verdict::Missing => write!(f, "{}: missing from the install directory", self.name),
+            Verdict::Modified { expected, actual } => write!(
+                f,
+                "{}: modified, sha256 is {} but {} was installed",
+                self.name, actual, expected
+            ),
+            Verdict::BadSignature => write!(f, "{}: signature does not match", self.name),
+            Verdict::Unrecorded => write!(
+                f,
+                "{}: no checksum recorded, reinstall it to verify it",
+                self.name
+            ),
+            Verdict::Unverifiable => write!(
+                f,
+                "{}: checksum ok, but there is no public key to check the signature",
+                self.name
+            ),
+            Verdict::Unsigned => write!(f, "{}: not signed, but signing is in use", self.name),
+        }
+    }
+}
+
+/// Re-hashes installed binaries and compares them with the ledger.
+///
+/// Every binary in the ledger is checked when `name` is `None`.
+/// Entries without a signature or checksum count as tampered when there is a public key or
+/// `signing` is set, since stripping them would otherwise pass.
+pub fn verify(
+    install_dir: impl AsRef<Path>,
+    name: Option<&str>,
+    public_key: Option<&PublicKey>,
+    signing: bool,
+) -> Result<Vec<Verification>, Box<dyn std::error::Error>> {
+    let install_dir = install_dir.as_ref();
+    let ledger = Ledger::load(install_dir)?;
+    let entries = match name {
+        Some(name) => vec![ledger
+            .get(name)
+            .ok_or_else(|| format!("{} was not installed by cw", name))?],
+        None => ledger.entries().iter().collect(),
+    };
+    let signing = signing || public_key.is_some();
+    entries
+        .into_iter()
+        .map(|entry| {
+            let path = install_dir.join(&entry.name);
+            let verdict = match &entry.sha256 {
+                _ if !path.is_file() => Verdict::Missing,
+                None if signing => Verdict::Unsigned,
+                None => Verdict::Unrecorded,
+                Some(expected) => {
+                    let actual = sha256_file(&path)?;
+                    if actual != *expected {
+                        Verdict::Modified {
+                            expected: expected.clone(),
+                            actual,
+                        }
+                    } else {
+                        match (&entry.signature, public_key) {
+                            (None, _) if signing => Verdict::Unsigned,
+                            (None, _) => Verdict::Verified { signed: false },
+                            (Some(_), None) => Verdict::Unverifiable,
+                            (Some(signature), Some(key))
+                                if key.verify(&entry.name, expected, signature) =>
+                            {
+                                Verdict::Verified { signed: true }
+                            }
+                            (Some(_), Some(_)) => Verdict::BadSignature,
+                        }
+                    }
+                }
+            };
+            Ok(Verification {
+                name: entry.name.clone(),
+                verdict,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        checksum::sha256_hex,
+        commands::write_file,
+        ledger::{tests::entry, LedgerEntry},
+        sign::tests::sign_key,
+    };
+
+    use super::*;
+    #[test]
+    fn 改ざんや署名の不一致を見つける() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = sign_key();
+        let mut ledger = Ledger::default();
+        for name in [
+            "intact", "tampered", "forged", "missing", "old", "stripped", "swapped",
+        ] {
+            write_file(dir.path().join(name), name).unwrap();
+            let sha256 = sha256_hex(name.as_bytes());
+            ledger.record(LedgerEntry {
+                signature: Some(key.sign(name, &sha256)),
+                sha256: Some(sha256),
+                ..entry(name, "/work/app")
+            });
+        }
+        ledger.record(LedgerEntry {
+            signature: Some(key.sign("forged", &sha256_hex(b"other"))),
+            ..ledger.get("forged").unwrap().clone()
+        });
+        ledger.record(entry("old", "/work/app"));
+        ledger.record(LedgerEntry {
+            signature: None,
+            ..ledger.get("stripped").unwrap().clone()
+        });
+        // Another binary with its checksum and signature copied over.
+        write_file(dir.path().join("swapped"), "intact").unwrap();
+        ledger.record(LedgerEntry {
+            name: "swapped".to_string(),
+            ..ledger.get("intact").unwrap().clone()
+        });
+        ledger.save(dir.path()).unwrap();
+        write_file(dir.path().join("tampered"), "evil").unwrap();
+        std::fs::remove_file(dir.path().join("missing")).unwrap();
+
+        let verifications = verify(dir.path(), None, Some(&key.public_key()), false).unwrap();
+
+        assert_eq!(
+            verifications
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "forged: signature does not match".to_string(),
+                "intact: ok, signature valid".to_string(),
+                "missing: missing from the install directory".to_string(),
+                "old: not signed, but signing is in use".to_string(),
+                "stripped: not signed, but signing is in use".to_string(),
+                "swapped: signature does not match".to_string(),
+                format!(
+                    "tampered: modified, sha256 is {} but {} was installed",
+                    sha256_hex(b"evil"),
+                    sha256_hex(b"tampered")
+                ),
+            ]
+        );
+        assert_eq!(
+            verifications
+                .iter()
+                .filter(|v| v.verdict.is_tampered())
+                .count(),
+            6
+        );
+
+        let without_key = verify(dir.path(), Some("intact"), None, false).unwrap();
+        assert_eq!(without_key[0].verdict, Verdict::Unverifiable);
+        let unsigned = verify(dir.path(), Some("stripped"), None, true).unwrap();
+        assert_eq!(unsigned[0].verdict, Verdict::Unsigned);
+        let not_signing = verify(dir.path(), Some("old"), None, false).unwrap();
+        assert_eq!(not_signing[0].verdict, Verdict::Unrecorded);
+        assert!(!not_signing[0].verdict.is_tampered());
+        assert!(verify(dir.path(), Some("unknown"), None, false).is_err());
+    }
+}
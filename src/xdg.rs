@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+/// `$XDG_CONFIG_HOME/cw`, falling back to `~/.config/cw`.
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("cw"))
+}
+
+/// `$XDG_STATE_HOME/cw`, falling back to `~/.local/state/cw`.
+pub fn state_dir() -> Option<PathBuf> {
+    base_dir("XDG_STATE_HOME", ".local/state").map(|dir| dir.join("cw"))
+}
+
+/// `$XDG_CACHE_HOME/cw`, falling back to `~/.cache/cw`.
+pub fn cache_dir() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache").map(|dir| dir.join("cw"))
+}
+
+/// `$XDG_DATA_HOME`, falling back to `~/.local/share`; shared with other programs, not just cw.
+pub fn data_home() -> Option<PathBuf> {
+    base_dir("XDG_DATA_HOME", ".local/share")
+}
+
+fn base_dir(env: &str, home_relative: &str) -> Option<PathBuf> {
+    match std::env::var_os(env) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(home_relative)),
+    }
+}